@@ -35,7 +35,7 @@ fn main() {
     ];
 
     // run
-    let mut vm_bak = clone_vm_status(&vm);
+    let mut vm_bak = vm.clone();
     loop {
         if vm.pc as usize >= program.len() {
             println!("\nProcess finished.");
@@ -58,7 +58,7 @@ fn main() {
             }
         }
         display_vm_status(&vm, &vm_bak);
-        vm_bak = clone_vm_status(&vm);
+        vm_bak = vm.clone();
         std::io::stdin().read_line(&mut String::new()).unwrap();
     }
 }
@@ -106,13 +106,3 @@ fn display_vm_status(vm: &AoVM, vm_bak: &AoVM) {
     }
     println!("}}");
 }
-
-fn clone_vm_status(vm: &AoVM) -> AoVM {
-    let mut new_vm = AoVM::new(|_, _| None);
-    new_vm.pc = vm.pc;
-    new_vm.ca = vm.ca.clone();
-    new_vm.dp = vm.dp;
-    new_vm.dsb = vm.dsb;
-    new_vm.ds = vm.ds.clone();
-    new_vm
-}
@@ -0,0 +1,118 @@
+use crate::opcodes::*;
+use crate::AoArg;
+
+/// Instructions that may run after the one at `index`, or `None` if the
+/// targets are only known at runtime.
+fn successors(program: &[Box<dyn AoOpcode>], index: usize) -> Option<Vec<usize>> {
+    let opcode = &program[index];
+    let relative = |addr: i32| (index as i64 + addr as i64) as usize;
+
+    let result = match (opcode.get_id(), opcode.get_args()) {
+        // call
+        (0x10, OpcodeArgType::u32(addr)) => vec![index + 1, addr as usize],
+        // ret, throw, halt, retv
+        (0x11 | 0x19 | 0x1C | 0x1E, _) => vec![],
+        // jmp
+        (0x12, OpcodeArgType::i32(addr)) => vec![relative(addr)],
+        // jmpa
+        (0x13, OpcodeArgType::u32(addr)) => vec![addr as usize],
+        // jt, jf
+        (0x14 | 0x16, OpcodeArgType::i32(addr)) => vec![index + 1, relative(addr)],
+        // jta, jfa
+        (0x15 | 0x17, OpcodeArgType::u32(addr)) => vec![index + 1, addr as usize],
+        // jmpr
+        (0x18, _) => return None,
+        // jbound
+        (0x1A, OpcodeArgType::u32_i32(_, addr)) => vec![index + 1, relative(addr)],
+        // jmptable
+        (0x1D, OpcodeArgType::u32s(targets)) => std::iter::once(index + 1)
+            .chain(targets.iter().map(|addr| *addr as usize))
+            .collect(),
+        // mov pc,src
+        (0x20, OpcodeArgType::AoArg2(AoArg::PC, _)) => return None,
+        // int 0
+        (0x21, OpcodeArgType::u8(0)) => vec![],
+        _ => vec![index + 1],
+    };
+    Some(result)
+}
+
+/// Find the instructions that can never be executed.
+///
+/// Walks the program from the first instruction, following jumps and calls.
+/// `ret`, `retv`, `throw`, `halt` and `int 0` end a path, and the target of
+/// every `call` in the program is treated as reachable. Programs that jump to
+/// addresses computed at runtime (`jmpr`, `mov pc,...`) cannot be analyzed,
+/// so nothing is reported for them.
+///
+/// # Examples
+/// ```
+/// use aoi::*;
+/// use aoi::analysis::unreachable_instructions;
+///
+/// let program = ao_program![
+///     jmp 2
+///     inc
+///     int 0
+/// ];
+/// assert_eq!(unreachable_instructions(&program), vec![1]);
+/// ```
+pub fn unreachable_instructions(program: &[Box<dyn AoOpcode>]) -> Vec<usize> {
+    let mut successors_of = Vec::with_capacity(program.len());
+    for index in 0..program.len() {
+        match successors(program, index) {
+            Some(successors) => successors_of.push(successors),
+            None => return Vec::new(),
+        }
+    }
+
+    let mut reachable = vec![false; program.len()];
+    let mut pending: Vec<usize> = vec![0];
+    for opcode in program {
+        if let (0x10, OpcodeArgType::u32(addr)) = (opcode.get_id(), opcode.get_args()) {
+            pending.push(addr as usize);
+        }
+    }
+
+    while let Some(index) = pending.pop() {
+        if index >= program.len() || reachable[index] {
+            continue;
+        }
+        reachable[index] = true;
+        pending.extend_from_slice(&successors_of[index]);
+    }
+
+    (0..program.len()).filter(|i| !reachable[*i]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn test_unreachable_instructions() {
+        let program = ao_program![
+            mov ca,0
+            jmp 3
+            inc
+            inc
+            lt 10
+            jt (-2)
+            call 9
+            int 0
+            nop
+            inc
+            ret
+            dec
+        ];
+        assert_eq!(unreachable_instructions(&program), vec![2, 8, 11]);
+
+        let program = ao_program![
+            mov ca,2
+            jmpr
+            nop
+        ];
+        assert!(unreachable_instructions(&program).is_empty());
+    }
+}
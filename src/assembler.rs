@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::opcode_table;
+use crate::opcodes::*;
+use crate::AoArg;
+use crate::AoProgram;
+
+/// Error while parsing Aoi assembly text.
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    /// Line of the error, starting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn mnemonics() -> HashMap<&'static str, u8> {
+    opcode_table()
+        .iter()
+        .map(|info| (info.mnemonic.as_str(), info.id))
+        .collect()
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut prev = '\0';
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '/' if !in_string && prev == '/' => return &line[..i - 1],
+            _ => (),
+        }
+        prev = c;
+    }
+    line
+}
+
+fn split_list(operands: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in operands.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                result.push(operands[start..i].trim());
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    result.push(operands[start..].trim());
+    result
+}
+
+fn split_operands(operands: &str) -> Option<(&str, &str)> {
+    match split_list(operands)[..] {
+        [left, right] => Some((left, right)),
+        _ => None,
+    }
+}
+
+pub(crate) fn parse_arg(value: &str) -> Option<AoArg> {
+    match value {
+        "pc" => Some(AoArg::PC),
+        "dp" => Some(AoArg::DP),
+        "mp" => Some(AoArg::MP),
+        "dsb" => Some(AoArg::DSB),
+        "dst" => Some(AoArg::DST),
+        "ca" => Some(AoArg::CA),
+        "cb" => Some(AoArg::CB),
+        "ds" => Some(AoArg::DS),
+        "mem" => Some(AoArg::MEM),
+        "gvs" => Some(AoArg::GVS),
+        _ => value.parse().ok().map(AoArg::Imm),
+    }
+}
+
+fn parse_args(shape: OpcodeArgType, operands: &str) -> Option<OpcodeArgType> {
+    match shape {
+        OpcodeArgType::NoArg => operands.is_empty().then_some(OpcodeArgType::NoArg),
+        OpcodeArgType::u8(_) => operands.parse().ok().map(OpcodeArgType::u8),
+        OpcodeArgType::i32(_) => operands.parse().ok().map(OpcodeArgType::i32),
+        OpcodeArgType::u32(_) => operands.parse().ok().map(OpcodeArgType::u32),
+        OpcodeArgType::bool(_) => None,
+        OpcodeArgType::AoArg(_) => parse_arg(operands).map(OpcodeArgType::AoArg),
+        OpcodeArgType::AoArg2(_, _) => {
+            let (left, right) = split_operands(operands)?;
+            Some(OpcodeArgType::AoArg2(parse_arg(left)?, parse_arg(right)?))
+        }
+        OpcodeArgType::u32_u32(_, _) => {
+            let (left, right) = split_operands(operands)?;
+            Some(OpcodeArgType::u32_u32(
+                left.parse().ok()?,
+                right.parse().ok()?,
+            ))
+        }
+        OpcodeArgType::u32_i32(_, _) => {
+            let (left, right) = split_operands(operands)?;
+            Some(OpcodeArgType::u32_i32(
+                left.parse().ok()?,
+                right.parse().ok()?,
+            ))
+        }
+        OpcodeArgType::AoTypes(_) if operands.is_empty() => Some(OpcodeArgType::AoTypes(vec![])),
+        OpcodeArgType::AoTypes(_) => split_list(operands)
+            .into_iter()
+            .map(|v| v.parse().ok())
+            .collect::<Option<_>>()
+            .map(OpcodeArgType::AoTypes),
+        OpcodeArgType::u32s(_) if operands.is_empty() => Some(OpcodeArgType::u32s(vec![])),
+        OpcodeArgType::u32s(_) => split_list(operands)
+            .into_iter()
+            .map(|v| v.parse().ok())
+            .collect::<Option<_>>()
+            .map(OpcodeArgType::u32s),
+    }
+}
+
+/// Parse Aoi assembly text into a program.
+///
+/// Each non-empty line holds one instruction written the way the opcode is
+/// displayed, e.g. `push "Hello"`, `mov ca,ds` or `jmp 18`. Everything after
+/// `//` is a comment, and a leading `/* ... */` comment such as the index
+/// written by [`AoAsmSerializer::disassemble`](crate::AoAsmSerializer::disassemble)
+/// is skipped.
+///
+/// # Examples
+/// ```
+/// use aoi::assembler::parse_text;
+///
+/// let program = parse_text("push dsb\npush \"Hello Aoi!\" // greeting\ncnf 1\nint 1").unwrap();
+/// assert_eq!(program.len(), 4);
+/// assert_eq!(program[1].to_string(), "push \"Hello Aoi!\"");
+/// ```
+pub fn parse_text(src: &str) -> Result<AoProgram, ParseError> {
+    let mnemonics = mnemonics();
+    let mut result: AoProgram = Vec::new();
+    for (index, line) in src.lines().enumerate() {
+        let error = |message: String| ParseError {
+            line: index + 1,
+            message,
+        };
+
+        let mut line = strip_comment(line).trim();
+        if let Some(rest) = line.strip_prefix("/*") {
+            let end = rest
+                .find("*/")
+                .ok_or_else(|| error("unterminated comment".to_string()))?;
+            line = rest[end + 2..].trim();
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operands) = match line.split_once(char::is_whitespace) {
+            Some((mnemonic, operands)) => (mnemonic, operands.trim()),
+            None => (line, ""),
+        };
+        let mut opcode = mnemonics
+            .get(mnemonic)
+            .and_then(|id| create_opcode_by_id(*id))
+            .ok_or_else(|| error(format!("unknown mnemonic `{}`", mnemonic)))?;
+
+        if let OpcodeArgType::bool(_) = opcode.get_args() {
+            let normalized = format!("{} {}", mnemonic, operands);
+            let value = [true, false].into_iter().find(|value| {
+                opcode.set_args(OpcodeArgType::bool(*value));
+                opcode.to_string() == normalized.trim_end()
+            });
+            if value.is_none() {
+                return Err(error(format!("invalid operands `{}`", operands)));
+            }
+        } else {
+            let args = parse_args(opcode.get_args(), operands)
+                .ok_or_else(|| error(format!("invalid operands `{}`", operands)))?;
+            opcode.set_args(args);
+        }
+        result.push(opcode);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn test_round_trip() {
+        #[rustfmt::skip]
+        let program = ao_program![
+            mov mp,0 mov mem,3 mov mp,1 mov mem,19 mov mp,2 mov mem,5 mov mp,3 mov mem,15
+            mov mp,4 mov mem,1 mov mp,5 mov mem,4 mov mp,6 mov mem,16 mov mp,7 mov mem,8
+            push 8
+            push 0 arg 1 mov ca,ds add 1 arg 0 lt ds jfa 80
+            push 0 arg 2 mov ca,ds add 1 arg 1 add ds arg 0 lt ds jfa 74
+            arg 2 mov ca,ds csp mov mp,ca push mem add 1 mov mp,ca push mem
+            mov ca,dst sub 2 mov dp,ca pop ca lt ds pop jfa 69
+            arg 2 mov ca,ds add 1 csp mov mp,ca push mem
+            arg 2 mov ca,ds csp mov mp,ca push mem add 1 mov mp,ca pop ca mov mem,ca
+            arg 2 mov ca,ds csp mov mp,ca pop ca mov mem,ca
+            arg 2 mov ca,ds add 1 mov ds,ca jmpa 25 pop
+            arg 1 mov ca,ds add 1 mov ds,ca jmpa 18 pop
+            push 0 arg 1 mov ca,ds arg 0 lt ds jfa 103
+            push dsb arg 1 mov ca,ds csp mov mp,ca mov ca,mem css add ", " push ca cnf 1 int 1
+            arg 1 mov ca,ds add 1 mov ds,ca jmpa 82 pop
+        ];
+
+        let text: Vec<String> = program.iter().map(|opcode| opcode.to_string()).collect();
+        let parsed = parse_text(&text.join("\n")).unwrap();
+        let reparsed = parse_text(&AoAsmSerializer::disassemble(&program)).unwrap();
+        assert_eq!(
+            AoAsmSerializer::serialize(&reparsed),
+            AoAsmSerializer::serialize(&program)
+        );
+
+        assert_eq!(parsed.len(), program.len());
+        for (parsed, original) in parsed.iter().zip(program.iter()) {
+            assert_eq!(parsed.to_string(), original.to_string());
+        }
+        assert_eq!(
+            AoAsmSerializer::serialize(&parsed),
+            AoAsmSerializer::serialize(&program)
+        );
+    }
+
+    #[test]
+    fn test_display_distinct() {
+        assert_eq!(Jmp { addr: -3 }.to_string(), "jmp -3");
+        assert_eq!(Jmpa { addr: 3 }.to_string(), "jmpa 3");
+
+        let mut count = 0;
+        for id in 0..=u8::MAX {
+            if let Some(opcode) = create_opcode_by_id(id) {
+                count += 1;
+                let parsed = parse_text(&opcode.to_string()).unwrap();
+                assert_eq!(parsed[0].get_id(), id, "{}", opcode);
+                assert_eq!(parsed[0].to_string(), opcode.to_string());
+            }
+        }
+        assert_eq!(mnemonics().len(), count);
+    }
+
+    #[test]
+    fn test_parse_text() {
+        let src = "
+            // prints a string
+            push dsb
+            push \"a // b, c\"
+            cnf 1
+
+            int 1 // print
+            pop ca
+            pop
+            jmp -3
+            cswap 0,1
+            mov ca,2.5f
+            inset 1,\"a,b\",true
+        ";
+        let program = parse_text(src).unwrap();
+        let text: Vec<String> = program.iter().map(|opcode| opcode.to_string()).collect();
+        assert_eq!(
+            text,
+            vec![
+                "push dsb",
+                "push \"a // b, c\"",
+                "cnf 1",
+                "int 1",
+                "pop ca",
+                "pop",
+                "jmp -3",
+                "cswap 0,1",
+                "mov ca,2.5f",
+                "inset 1,\"a,b\",true",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_text_escapes() {
+        let program = ao_program![
+            push "a\nb"
+            push "x\" // y"
+            mov ca,"\\, \"\t"
+            inset 1,"a\",b",true
+        ];
+        let text = AoAsmSerializer::disassemble(&program);
+        let parsed = parse_text(&text).unwrap();
+        assert_eq!(AoAsmSerializer::disassemble(&parsed), text);
+
+        let mut vm = AoVM::default();
+        assert_eq!(vm.run(&parsed[..2]), AoStatus::Exit);
+        assert_eq!(vm.ds, vec![AoType::from("a\nb"), AoType::from("x\" // y")]);
+        assert_eq!(
+            parse_text("push \"a\\nb\"").unwrap()[0].to_string(),
+            "push \"a\\nb\""
+        );
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert_eq!(
+            parse_text("nop\n\nfoo 1").err(),
+            Some(ParseError {
+                line: 3,
+                message: "unknown mnemonic `foo`".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_text("mov ca").err(),
+            Some(ParseError {
+                line: 1,
+                message: "invalid operands `ca`".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_text("pop cb").err(),
+            Some(ParseError {
+                line: 1,
+                message: "invalid operands `cb`".to_string(),
+            })
+        );
+    }
+}
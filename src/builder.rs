@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::opcodes::*;
+use crate::AoArg;
+use crate::AoProgram;
+
+/// Error while building a program with [`ProgramBuilder`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    /// A jump or call refers to a label that was never defined.
+    UnknownLabel(String),
+    /// A label was defined more than once.
+    DuplicateLabel(String),
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::UnknownLabel(label) => write!(f, "Unknown Label({})", label),
+            BuildError::DuplicateLabel(label) => write!(f, "Duplicate Label({})", label),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// Builder for constructing an `AoProgram` in plain Rust.
+///
+/// Jumps and calls to labels are emitted as absolute jumps and resolved when
+/// the program is built.
+///
+/// # Examples
+/// ```
+/// use aoi::*;
+/// use aoi::builder::ProgramBuilder;
+///
+/// let program = ProgramBuilder::new()
+///     .mov(AoArg::CB, 0)
+///     .label("loop")
+///     .mov(AoArg::CA, AoArg::CB)
+///     .inc()
+///     .mov(AoArg::CB, AoArg::CA)
+///     .lt(3)
+///     .jt_to("loop")
+///     .build()
+///     .unwrap();
+///
+/// let mut vm = AoVM::default();
+/// assert_eq!(vm.run(&program), AoStatus::Exit);
+/// assert_eq!(vm.cb, AoType::AoInt(3));
+/// ```
+#[derive(Default)]
+pub struct ProgramBuilder {
+    program: AoProgram,
+    labels: HashMap<String, u32>,
+    // index of the opcode and the label it jumps to
+    fixups: Vec<(usize, String)>,
+    duplicate: Option<String>,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> ProgramBuilder {
+        ProgramBuilder::default()
+    }
+
+    /// Append any opcode.
+    pub fn op<O: AoOpcode + 'static>(mut self, opcode: O) -> ProgramBuilder {
+        self.program.push(Box::new(opcode));
+        self
+    }
+
+    /// Mark the address of the next opcode.
+    pub fn label(mut self, label: &str) -> ProgramBuilder {
+        let addr = self.program.len() as u32;
+        if self.labels.insert(label.to_string(), addr).is_some() && self.duplicate.is_none() {
+            self.duplicate = Some(label.to_string());
+        }
+        self
+    }
+
+    fn op_to<O: AoOpcode + 'static>(mut self, opcode: O, label: &str) -> ProgramBuilder {
+        self.fixups.push((self.program.len(), label.to_string()));
+        self.op(opcode)
+    }
+
+    pub fn nop(self) -> ProgramBuilder {
+        self.op(Nop)
+    }
+
+    pub fn call(self, addr: u32) -> ProgramBuilder {
+        self.op(Call { addr })
+    }
+
+    pub fn call_to(self, label: &str) -> ProgramBuilder {
+        self.op_to(Call { addr: 0 }, label)
+    }
+
+    pub fn ret(self) -> ProgramBuilder {
+        self.op(Ret)
+    }
+
+    pub fn jmp(self, addr: i32) -> ProgramBuilder {
+        self.op(Jmp { addr })
+    }
+
+    pub fn jmp_to(self, label: &str) -> ProgramBuilder {
+        self.op_to(Jmpa { addr: 0 }, label)
+    }
+
+    pub fn jt(self, addr: i32) -> ProgramBuilder {
+        self.op(Jt { addr })
+    }
+
+    pub fn jt_to(self, label: &str) -> ProgramBuilder {
+        self.op_to(Jta { addr: 0 }, label)
+    }
+
+    pub fn jf(self, addr: i32) -> ProgramBuilder {
+        self.op(Jf { addr })
+    }
+
+    pub fn jf_to(self, label: &str) -> ProgramBuilder {
+        self.op_to(Jfa { addr: 0 }, label)
+    }
+
+    pub fn halt(self) -> ProgramBuilder {
+        self.op(Halt { with_ca: false })
+    }
+
+    pub fn mov<S: Into<AoArg>>(self, dst: AoArg, src: S) -> ProgramBuilder {
+        self.op(Mov {
+            dst,
+            src: src.into(),
+        })
+    }
+
+    pub fn int(self, id: u8) -> ProgramBuilder {
+        self.op(Int { id })
+    }
+
+    pub fn push<S: Into<AoArg>>(self, src: S) -> ProgramBuilder {
+        self.op(Push { src: src.into() })
+    }
+
+    pub fn pop(self) -> ProgramBuilder {
+        self.op(Pop { to_ca: false })
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn add<S: Into<AoArg>>(self, src: S) -> ProgramBuilder {
+        self.op(Add { src: src.into() })
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn sub<S: Into<AoArg>>(self, src: S) -> ProgramBuilder {
+        self.op(Sub { src: src.into() })
+    }
+
+    pub fn inc(self) -> ProgramBuilder {
+        self.op(Inc)
+    }
+
+    pub fn dec(self) -> ProgramBuilder {
+        self.op(Dec)
+    }
+
+    pub fn equ<S: Into<AoArg>>(self, src: S) -> ProgramBuilder {
+        self.op(Equ { src: src.into() })
+    }
+
+    pub fn lt<S: Into<AoArg>>(self, src: S) -> ProgramBuilder {
+        self.op(Lt { src: src.into() })
+    }
+
+    pub fn le<S: Into<AoArg>>(self, src: S) -> ProgramBuilder {
+        self.op(Le { src: src.into() })
+    }
+
+    pub fn gt<S: Into<AoArg>>(self, src: S) -> ProgramBuilder {
+        self.op(Gt { src: src.into() })
+    }
+
+    pub fn ge<S: Into<AoArg>>(self, src: S) -> ProgramBuilder {
+        self.op(Ge { src: src.into() })
+    }
+
+    pub fn arg(self, offset: u32) -> ProgramBuilder {
+        self.op(Arg { offset })
+    }
+
+    pub fn cnf(self, argc: u32) -> ProgramBuilder {
+        self.op(Cnf { argc })
+    }
+
+    /// Resolve the labels and return the program.
+    pub fn build(mut self) -> Result<AoProgram, BuildError> {
+        if let Some(label) = self.duplicate {
+            return Err(BuildError::DuplicateLabel(label));
+        }
+        for (index, label) in self.fixups {
+            match self.labels.get(&label) {
+                Some(addr) => self.program[index].set_args(OpcodeArgType::u32(*addr)),
+                None => return Err(BuildError::UnknownLabel(label)),
+            }
+        }
+        Ok(self.program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn test_build() {
+        // sum of 1..=100
+        let built = ProgramBuilder::new()
+            .push(1)
+            .push(0)
+            .label("loop")
+            .arg(0)
+            .mov(AoArg::CA, AoArg::DS)
+            .le(100)
+            .jf_to("end")
+            .mov(AoArg::CA, AoArg::DS)
+            .arg(1)
+            .add(AoArg::DS)
+            .mov(AoArg::DS, AoArg::CA)
+            .arg(0)
+            .mov(AoArg::CA, AoArg::DS)
+            .inc()
+            .mov(AoArg::DS, AoArg::CA)
+            .jmp_to("loop")
+            .label("end")
+            .build()
+            .unwrap();
+        let program = ao_program![
+            push 1
+            push 0
+            arg 0
+            mov ca,ds
+            le 100
+            jfa 15
+            mov ca,ds
+            arg 1
+            add ds
+            mov ds,ca
+            arg 0
+            mov ca,ds
+            inc
+            mov ds,ca
+            jmpa 2
+        ];
+        assert_eq!(
+            AoAsmSerializer::disassemble(&built),
+            AoAsmSerializer::disassemble(&program)
+        );
+
+        let mut vm = AoVM::default();
+        assert_eq!(vm.run(&built), AoStatus::Exit);
+        assert_eq!(vm.ds, vec![AoType::AoInt(101), AoType::AoInt(5050)]);
+
+        assert_eq!(
+            ProgramBuilder::new().jmp_to("missing").build().err(),
+            Some(BuildError::UnknownLabel("missing".to_string()))
+        );
+        assert_eq!(
+            ProgramBuilder::new()
+                .label("a")
+                .nop()
+                .label("a")
+                .build()
+                .err(),
+            Some(BuildError::DuplicateLabel("a".to_string()))
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use std::fmt::Display;
+
+use serde_json::{json, Value};
+
+use crate::assembler::parse_arg;
+use crate::opcodes::*;
+use crate::AoArg;
+use crate::AoProgram;
+
+/// Error while reading a program from JSON.
+#[derive(Debug, PartialEq)]
+pub struct JsonError {
+    /// Index of the offending opcode, `None` if the document itself is invalid.
+    pub index: Option<usize>,
+    pub message: String,
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.index {
+            Some(index) => write!(f, "opcode {}: {}", index, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+fn args_to_json(args: OpcodeArgType) -> Value {
+    match args {
+        OpcodeArgType::NoArg => json!([]),
+        OpcodeArgType::u8(v) => json!([v]),
+        OpcodeArgType::i32(v) => json!([v]),
+        OpcodeArgType::u32(v) => json!([v]),
+        OpcodeArgType::bool(v) => json!([v]),
+        OpcodeArgType::AoArg(v) => json!([v.to_string()]),
+        OpcodeArgType::AoArg2(v1, v2) => json!([v1.to_string(), v2.to_string()]),
+        OpcodeArgType::u32_u32(v1, v2) => json!([v1, v2]),
+        OpcodeArgType::u32_i32(v1, v2) => json!([v1, v2]),
+        OpcodeArgType::AoTypes(v) => v.iter().map(|v| json!(v.to_string())).collect(),
+        OpcodeArgType::u32s(v) => json!(v),
+    }
+}
+
+fn args_from_json(shape: OpcodeArgType, args: &[Value]) -> Option<OpcodeArgType> {
+    let int = |index: usize| args.get(index).and_then(Value::as_i64);
+    let arg = |index: usize| -> Option<AoArg> { parse_arg(args.get(index)?.as_str()?) };
+
+    if let OpcodeArgType::AoTypes(_) = shape {
+        return args
+            .iter()
+            .map(|v| v.as_str()?.parse().ok())
+            .collect::<Option<_>>()
+            .map(OpcodeArgType::AoTypes);
+    }
+    if let OpcodeArgType::u32s(_) = shape {
+        return args
+            .iter()
+            .map(|v| v.as_u64()?.try_into().ok())
+            .collect::<Option<_>>()
+            .map(OpcodeArgType::u32s);
+    }
+
+    let (result, count) = match shape {
+        OpcodeArgType::NoArg => (OpcodeArgType::NoArg, 0),
+        OpcodeArgType::u8(_) => (OpcodeArgType::u8(int(0)?.try_into().ok()?), 1),
+        OpcodeArgType::i32(_) => (OpcodeArgType::i32(int(0)?.try_into().ok()?), 1),
+        OpcodeArgType::u32(_) => (OpcodeArgType::u32(int(0)?.try_into().ok()?), 1),
+        OpcodeArgType::bool(_) => (OpcodeArgType::bool(args.first()?.as_bool()?), 1),
+        OpcodeArgType::AoArg(_) => (OpcodeArgType::AoArg(arg(0)?), 1),
+        OpcodeArgType::AoArg2(_, _) => (OpcodeArgType::AoArg2(arg(0)?, arg(1)?), 2),
+        OpcodeArgType::u32_u32(_, _) => (
+            OpcodeArgType::u32_u32(int(0)?.try_into().ok()?, int(1)?.try_into().ok()?),
+            2,
+        ),
+        OpcodeArgType::u32_i32(_, _) => (
+            OpcodeArgType::u32_i32(int(0)?.try_into().ok()?, int(1)?.try_into().ok()?),
+            2,
+        ),
+        OpcodeArgType::AoTypes(_) | OpcodeArgType::u32s(_) => unreachable!(),
+    };
+    (args.len() == count).then_some(result)
+}
+
+/// Convert a program to JSON.
+///
+/// The document is an array with one object per opcode holding its `id`,
+/// `mnemonic` and `args`. Keys are sorted and register or immediate operands
+/// are written in assembly syntax, so the output is stable and easy to edit.
+///
+/// # Examples
+/// ```
+/// use aoi::*;
+/// use aoi::json::to_json;
+///
+/// let program = ao_program![mov ca,"Aoi"];
+/// assert_eq!(
+///     to_json(&program),
+///     r#"[{"args":["ca","\"Aoi\""],"id":32,"mnemonic":"mov"}]"#
+/// );
+/// ```
+pub fn to_json(program: &AoProgram) -> String {
+    let opcodes: Vec<Value> = program
+        .iter()
+        .map(|opcode| {
+            let disp = opcode.to_string();
+            let mnemonic = disp.split_whitespace().next().unwrap_or_default();
+            json!({
+                "id": opcode.get_id(),
+                "mnemonic": mnemonic,
+                "args": args_to_json(opcode.get_args()),
+            })
+        })
+        .collect();
+    Value::Array(opcodes).to_string()
+}
+
+/// Read a program from JSON produced by [`to_json`].
+///
+/// The `mnemonic` field is optional, but must match the `id` when present.
+pub fn from_json(src: &str) -> Result<AoProgram, JsonError> {
+    let document: Value = serde_json::from_str(src).map_err(|e| JsonError {
+        index: None,
+        message: e.to_string(),
+    })?;
+    let opcodes = document.as_array().ok_or_else(|| JsonError {
+        index: None,
+        message: "expected an array of opcodes".to_string(),
+    })?;
+
+    let mut result: AoProgram = Vec::new();
+    for (index, value) in opcodes.iter().enumerate() {
+        let error = |message: String| JsonError {
+            index: Some(index),
+            message,
+        };
+
+        let id = value
+            .get("id")
+            .and_then(Value::as_u64)
+            .and_then(|id| u8::try_from(id).ok())
+            .ok_or_else(|| error("missing or invalid `id`".to_string()))?;
+        let mut opcode =
+            create_opcode_by_id(id).ok_or_else(|| error(format!("unknown opcode id {}", id)))?;
+
+        let disp = opcode.to_string();
+        let expected = disp.split_whitespace().next().unwrap_or_default();
+        if let Some(mnemonic) = value.get("mnemonic") {
+            if mnemonic.as_str() != Some(expected) {
+                return Err(error(format!(
+                    "mnemonic {} does not match id {}",
+                    mnemonic, id
+                )));
+            }
+        }
+
+        let args = value
+            .get("args")
+            .and_then(Value::as_array)
+            .map_or(&[][..], Vec::as_slice);
+        let args = args_from_json(opcode.get_args(), args)
+            .ok_or_else(|| error(format!("invalid args for `{}`", expected)))?;
+        opcode.set_args(args);
+        result.push(opcode);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    fn program() -> AoProgram {
+        assembler::parse_text(
+            r#"
+            nop
+            push dsb
+            push "Hello, Aoi!"
+            mov ca,2.5f
+            pop ca
+            pop
+            jmp -3
+            cnf 1
+            int 1
+            cswap 0,1
+            jbound 4,-2
+            mov mp,12p
+            mov mem,true
+            inset 1,"a",2.5f
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let program = program();
+        let parsed = from_json(&to_json(&program)).unwrap();
+
+        assert_eq!(parsed.len(), program.len());
+        for (parsed, original) in parsed.iter().zip(program.iter()) {
+            assert_eq!(parsed.to_string(), original.to_string());
+        }
+        assert_eq!(
+            AoAsmSerializer::serialize(&parsed),
+            AoAsmSerializer::serialize(&program)
+        );
+    }
+
+    #[test]
+    fn test_stable() {
+        let json = to_json(&program());
+        assert_eq!(json, to_json(&program()));
+        assert_eq!(json, to_json(&from_json(&json).unwrap()));
+        assert!(json.starts_with(r#"[{"args":[],"id":0,"mnemonic":"nop"},"#));
+    }
+
+    #[test]
+    fn test_error() {
+        assert_eq!(from_json("{}").err().unwrap().index, None);
+        assert_eq!(
+            from_json(r#"[{"id":0},{"id":32,"mnemonic":"push","args":["ca","ds"]}]"#)
+                .err()
+                .unwrap(),
+            JsonError {
+                index: Some(1),
+                message: r#"mnemonic "push" does not match id 32"#.to_string(),
+            }
+        );
+        assert_eq!(
+            from_json(r#"[{"id":32,"args":["ca"]}]"#).err().unwrap(),
+            JsonError {
+                index: Some(0),
+                message: "invalid args for `mov`".to_string(),
+            }
+        );
+    }
+}
@@ -1,7 +1,18 @@
+// `ao_program!` builds programs by pushing one opcode at a time.
+#![cfg_attr(test, allow(clippy::vec_init_then_push))]
+
+pub mod analysis;
+pub mod assembler;
+pub mod builder;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod optimize;
 pub mod runtime;
 pub mod serialization;
+pub mod validation;
 
 pub use runtime::*;
-pub use serialization::AoAsmSerializer;
+pub use serialization::{load_program, AoAsmSerializer, AoDeserializeError};
+pub use validation::{validate, validate_and_run, ValidationError};
 
 pub type AoProgram = Vec<Box<dyn runtime::opcode::AoOpcode>>;
@@ -0,0 +1,403 @@
+use crate::analysis::unreachable_instructions;
+use crate::opcodes::*;
+use crate::AoArg;
+use crate::AoProgram;
+use crate::AoStatus;
+use crate::AoVM;
+
+/// Addresses that jumps and calls in the program can land on, or `None` if
+/// the program jumps to or reads addresses computed at runtime.
+fn jump_targets(program: &AoProgram) -> Option<Vec<bool>> {
+    let mut targets = vec![false; program.len() + 1];
+    let mut mark = |addr: i64| {
+        if (0..targets.len() as i64).contains(&addr) {
+            targets[addr as usize] = true;
+        }
+    };
+    for (index, opcode) in program.iter().enumerate() {
+        let relative = |addr: i32| index as i64 + addr as i64;
+        match (opcode.get_id(), opcode.get_args()) {
+            // call, jmpa, jta, jfa
+            (0x10 | 0x13 | 0x15 | 0x17, OpcodeArgType::u32(addr)) => mark(addr as i64),
+            // jmp, jt, jf
+            (0x12 | 0x14 | 0x16, OpcodeArgType::i32(addr)) => mark(relative(addr)),
+            // jbound
+            (0x1A, OpcodeArgType::u32_i32(_, addr)) => mark(relative(addr)),
+            // jmptable
+            (0x1D, OpcodeArgType::u32s(addrs)) => addrs.iter().for_each(|a| mark(*a as i64)),
+            // jmpr
+            (0x18, _) => return None,
+            (_, OpcodeArgType::AoArg(AoArg::PC)) => return None,
+            (_, OpcodeArgType::AoArg2(dst, src)) if dst == AoArg::PC || src == AoArg::PC => {
+                return None
+            }
+            _ => (),
+        }
+    }
+    Some(targets)
+}
+
+/// Drop the opcodes whose entry in `keep` is `false` and move every jump and
+/// call target to the address its instruction ends up at.
+///
+/// Targets must not point at dropped opcodes. Targets past the end of the
+/// program keep their distance from the end.
+fn compact(program: AoProgram, keep: &[bool]) -> AoProgram {
+    // new address of every old address, including the end of the program
+    let mut moved = Vec::with_capacity(program.len() + 1);
+    let mut kept = 0u32;
+    for keep in keep {
+        moved.push(kept);
+        kept += *keep as u32;
+    }
+    moved.push(kept);
+    let removed = program.len() as u32 - kept;
+    let absolute = |addr: u32| match moved.get(addr as usize) {
+        Some(addr) => *addr,
+        None => addr.wrapping_sub(removed),
+    };
+
+    let mut result = AoProgram::with_capacity(kept as usize);
+    for (index, mut opcode) in program.into_iter().enumerate() {
+        if !keep[index] {
+            continue;
+        }
+        let relative = |addr: i32| {
+            let target = (index as u32).wrapping_add_signed(addr);
+            absolute(target).wrapping_sub(moved[index]) as i32
+        };
+        let args = match (opcode.get_id(), opcode.get_args()) {
+            (0x10 | 0x13 | 0x15 | 0x17, OpcodeArgType::u32(addr)) => {
+                Some(OpcodeArgType::u32(absolute(addr)))
+            }
+            (0x12 | 0x14 | 0x16, OpcodeArgType::i32(addr)) => {
+                Some(OpcodeArgType::i32(relative(addr)))
+            }
+            (0x1A, OpcodeArgType::u32_i32(len, addr)) => {
+                Some(OpcodeArgType::u32_i32(len, relative(addr)))
+            }
+            (0x1D, OpcodeArgType::u32s(addrs)) => Some(OpcodeArgType::u32s(
+                addrs.into_iter().map(absolute).collect(),
+            )),
+            _ => None,
+        };
+        if let Some(args) = args {
+            opcode.set_args(args);
+        }
+        result.push(opcode);
+    }
+    result
+}
+
+/// Whether the opcode only reads `ca` and an immediate and only writes `ca`.
+fn is_foldable(opcode: &dyn AoOpcode) -> bool {
+    match (opcode.get_id(), opcode.get_args()) {
+        // inc, dec, not, bnot, truthy, casts and type checks
+        (0x35 | 0x36 | 0x43 | 0x47 | 0x48 | 0x60..=0x6F, OpcodeArgType::NoArg) => true,
+        // arithmetic, logic and comparison with an immediate
+        (0x30..=0x3E | 0x40..=0x46 | 0x50..=0x55 | 0x57, OpcodeArgType::AoArg(AoArg::Imm(_))) => {
+            true
+        }
+        // inset
+        (0x56, OpcodeArgType::AoTypes(_)) => true,
+        _ => false,
+    }
+}
+
+/// Collapse arithmetic on `ca` with immediate operands into a single `mov`.
+///
+/// A `mov ca,<imm>` followed by opcodes that only combine `ca` with
+/// immediates, such as `add 3` or `lt 10`, is replaced by one `mov` of the
+/// result. Opcodes touching the stack, memory or other registers end a chain,
+/// and so do jump targets and opcodes that would fail at runtime. Jumps are
+/// adjusted to the shorter program. Programs that jump to or read addresses
+/// computed at runtime (`jmpr`, `mov pc,...`, `mov ca,pc`) are returned
+/// unchanged.
+///
+/// # Examples
+/// ```
+/// use aoi::*;
+/// use aoi::optimize::fold_constants;
+///
+/// let program = fold_constants(ao_program![
+///     mov ca,2
+///     add 3
+///     mul 4
+/// ]);
+/// assert_eq!(program.len(), 1);
+/// assert_eq!(program[0].to_string(), "mov ca,20");
+/// ```
+pub fn fold_constants(mut program: AoProgram) -> AoProgram {
+    let targets = match jump_targets(&program) {
+        Some(targets) => targets,
+        None => return program,
+    };
+
+    let mut keep = vec![true; program.len()];
+    let mut index = 0;
+    while index < program.len() {
+        let start = match (program[index].get_id(), program[index].get_args()) {
+            (0x20, OpcodeArgType::AoArg2(AoArg::CA, AoArg::Imm(value))) => value,
+            _ => {
+                index += 1;
+                continue;
+            }
+        };
+
+        // run the chain on a scratch VM, stopping before anything that fails
+        let mut vm = AoVM {
+            ca: start,
+            safe_mode: true,
+            ..AoVM::default()
+        };
+        let mut end = index + 1;
+        while end < program.len() && !targets[end] && is_foldable(program[end].as_ref()) {
+            vm.pc = 0;
+            if vm.step(std::slice::from_ref(&program[end])) != AoStatus::Ok {
+                break;
+            }
+            end += 1;
+        }
+
+        if end > index + 1 {
+            program[index] = Box::new(Mov {
+                dst: AoArg::CA,
+                src: AoArg::Imm(vm.ca),
+            });
+            keep[index + 1..end].fill(false);
+        }
+        index = end;
+    }
+
+    if keep.iter().all(|keep| *keep) {
+        return program;
+    }
+    compact(program, &keep)
+}
+
+/// Drop the opcodes that can never be executed.
+///
+/// Reachability is decided by [`unreachable_instructions`], following every
+/// static jump and call from the first opcode. Jumps are adjusted to the
+/// shorter program. Programs that jump to or read addresses computed at
+/// runtime (`jmpr`, `mov pc,...`, `mov ca,pc`) are returned unchanged.
+///
+/// # Examples
+/// ```
+/// use aoi::*;
+/// use aoi::optimize::remove_unreachable;
+///
+/// let program = remove_unreachable(ao_program![
+///     jmpa 3
+///     inc
+///     inc
+///     int 0
+/// ]);
+/// assert_eq!(program.len(), 2);
+/// assert_eq!(program[0].to_string(), "jmpa 1");
+/// ```
+pub fn remove_unreachable(program: AoProgram) -> AoProgram {
+    if jump_targets(&program).is_none() {
+        return program;
+    }
+    let unreachable = unreachable_instructions(&program);
+    if unreachable.is_empty() {
+        return program;
+    }
+
+    let mut keep = vec![true; program.len()];
+    for index in unreachable {
+        keep[index] = false;
+    }
+    compact(program, &keep)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    fn assert_same_result(program: AoProgram, expected_len: usize) {
+        assert_same_result_with(fold_constants, program, expected_len);
+    }
+
+    fn assert_same_result_with(
+        optimize: fn(AoProgram) -> AoProgram,
+        program: AoProgram,
+        expected_len: usize,
+    ) {
+        let mut vm = AoVM {
+            safe_mode: true,
+            ..AoVM::default()
+        };
+        let status = vm.run(&program);
+
+        let optimized = optimize(program);
+        assert_eq!(optimized.len(), expected_len);
+        let mut optimized_vm = AoVM {
+            safe_mode: true,
+            ..AoVM::default()
+        };
+        assert_eq!(optimized_vm.run(&optimized), status);
+        assert_eq!(optimized_vm.ca, vm.ca);
+        assert_eq!(optimized_vm.cb, vm.cb);
+        assert_eq!(optimized_vm.ds, vm.ds);
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        let program = fold_constants(ao_program![
+            mov ca,2
+            add 3
+            mul 4
+            lt 30
+            push ca
+        ]);
+        assert_eq!(program.len(), 2);
+        assert_eq!(program[0].to_string(), "mov ca,true");
+
+        assert_same_result(
+            ao_program![
+                mov ca,2
+                add 3
+                push ca
+                mov ca,10
+                sub 1
+                shl 2
+                push ca
+            ],
+            4,
+        );
+
+        // stack and register operands end the chain
+        assert_same_result(
+            ao_program![
+                push 5
+                mov ca,1
+                add ds
+                mov cb,7
+                mov ca,3
+                add cb
+                inc
+                push ca
+            ],
+            8,
+        );
+
+        // division by zero is left for the VM to report
+        assert_same_result(
+            ao_program![
+                mov ca,1
+                add 1
+                div 0
+            ],
+            2,
+        );
+
+        // casts and comparisons
+        assert_same_result(
+            ao_program![
+                mov ca,"12"
+                csi
+                add 1
+                equ 13
+                push ca
+            ],
+            2,
+        );
+    }
+
+    #[test]
+    fn test_fold_constants_jumps() {
+        // the loop body starts at a jump target and is not folded into the
+        // mov before it; the jumps over the folded code still land right
+        assert_same_result(
+            ao_program![
+                mov cb,0
+                jmp 4
+                mov ca,1
+                add 2
+                push ca
+                mov ca,cb
+                add 1
+                mov cb,ca
+                lt 5
+                jt (-4)
+                mov ca,10
+                add 5
+                sub 3
+                push ca
+                call 16
+                int 0
+                push cb
+                ret
+            ],
+            15,
+        );
+
+        // jmpr makes the targets unknown
+        let program = ao_program![
+            mov ca,3
+            add 1
+            jmpr
+            nop
+        ];
+        assert_eq!(fold_constants(program).len(), 4);
+    }
+
+    #[test]
+    fn test_remove_unreachable() {
+        assert_same_result_with(
+            remove_unreachable,
+            ao_program![
+                mov cb,0
+                call 8
+                jmpa 6
+                mov ca,99
+                push ca
+                halt
+                push cb
+                int 0
+                mov ca,cb
+                add 2
+                mov cb,ca
+                lt 6
+                jt (-4)
+                ret
+                push 7
+                ret
+            ],
+            11,
+        );
+
+        let program = remove_unreachable(ao_program![
+            jmpa 4
+            push 1
+            jmp (-1)
+            halt
+            mov ca,1
+            jta 6
+            jmp 1
+            int 0
+        ]);
+        assert_eq!(
+            AoAsmSerializer::disassemble(&program),
+            AoAsmSerializer::disassemble(&ao_program![
+                jmpa 1
+                mov ca,1
+                jta 3
+                jmp 1
+                int 0
+            ])
+        );
+
+        // jmpr makes the targets unknown
+        let program = ao_program![
+            mov ca,3
+            jmpr
+            nop
+            halt
+        ];
+        assert_eq!(remove_unreachable(program).len(), 4);
+    }
+}
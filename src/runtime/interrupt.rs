@@ -0,0 +1,105 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::AoStatus;
+use super::AoType;
+
+/// Result of an interrupt handler.
+///
+/// `Ok` carries the value to store in CA, if any. `Err` stops the VM with
+/// the given status, and `Err(AoStatus::Yielded)` pauses it after the
+/// interrupt instead.
+pub type InterruptResult = Result<Option<AoType>, AoStatus>;
+
+/// Interrupt handler for all ids, shared between clones of a VM.
+pub type SharedInterrupt = Rc<RefCell<dyn FnMut(u8, Vec<AoType>) -> InterruptResult>>;
+
+/// Handler for a single interrupt id.
+pub type InterruptHandler = Box<dyn FnMut(Vec<AoType>) -> InterruptResult>;
+
+/// Table of interrupt handlers indexed by interrupt id.
+#[derive(Default)]
+pub struct InterruptTable {
+    handlers: HashMap<u8, InterruptHandler>,
+}
+
+impl InterruptTable {
+    /// Create an empty interrupt table.
+    pub fn new() -> InterruptTable {
+        InterruptTable {
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Register a handler for the interrupt id, replacing any previous one.
+    ///
+    /// # Examples
+    /// ```
+    /// use aoi::runtime::interrupt::InterruptTable;
+    ///
+    /// let mut table = InterruptTable::new();
+    /// table.register(1, |args| Ok(args.into_iter().next()));
+    /// assert!(table.get_mut(1).is_some());
+    /// assert!(table.get_mut(2).is_none());
+    /// ```
+    pub fn register<F>(&mut self, id: u8, handler: F)
+    where
+        F: FnMut(Vec<AoType>) -> InterruptResult + 'static,
+    {
+        self.handlers.insert(id, Box::new(handler));
+    }
+
+    /// Get the handler registered for the interrupt id.
+    pub fn get_mut(&mut self, id: u8) -> Option<&mut InterruptHandler> {
+        self.handlers.get_mut(&id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn test_interrupt_table() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let captured = log.clone();
+
+        let mut table = InterruptTable::new();
+        table.register(1, move |args| {
+            captured.borrow_mut().push(args[0].clone());
+            Ok(None)
+        });
+        table.register(2, |args| match &args[0] {
+            AoType::AoInt(v) => Ok(Some(AoType::AoInt(v * 2))),
+            _ => Ok(None),
+        });
+
+        let mut vm = AoVM::with_interrupt_table(table);
+        let program = ao_program![
+            push dsb
+            push "logged"
+            cnf 1
+            int 1
+            push dsb
+            push 21
+            cnf 1
+            int 2
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(*log.borrow(), vec![AoType::from("logged")]);
+        assert_eq!(vm.ca, AoType::AoInt(42));
+
+        vm.reset();
+        let program = ao_program![
+            push dsb
+            cnf 0
+            int 3
+        ];
+        assert_eq!(vm.run(&program), AoStatus::UnknownInterrupt(3));
+    }
+}
@@ -1,9 +1,17 @@
+pub mod interrupt;
 pub mod opcode;
+pub mod scheduler;
+pub mod span;
 pub mod status;
 pub mod types;
 pub mod vm;
 
+pub use interrupt::{InterruptResult, InterruptTable, SharedInterrupt};
 pub use opcode::*;
-pub use status::AoStatus;
-pub use types::AoType;
+pub use scheduler::AoScheduler;
+pub use span::SourceSpan;
+pub use status::{AoFault, AoStatus};
+pub use types::{
+    AoMapKey, AoType, AoTypeError, AoTypeTag, ParseAoTypeError, MAX_NESTING_DEPTH, MAX_STRING_LEN,
+};
 pub use vm::AoVM;
@@ -5,7 +5,7 @@ use crate::AoType;
 use crate::AoVM;
 
 /// The argument of the opcode.
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, Default, PartialEq, Clone)]
 pub enum AoArg {
     /// Program counter.
     PC,
@@ -18,12 +18,15 @@ pub enum AoArg {
     /// Pointer to the top of the stack.
     DST,
     /// Registers for calculation.
+    #[default]
     CA,
     CB,
     /// Stack.
     DS,
     /// Memory.
     MEM,
+    /// Global variables, indexed by `mp`.
+    GVS,
     /// Immediate value.
     Imm(AoType),
 }
@@ -31,28 +34,39 @@ pub enum AoArg {
 impl AoArg {
     /// Returns the value of the argument.
     ///
+    /// Fails with `AoStatus::BadDataAccess` if `mp` is out of range for
+    /// `AoArg::GVS`.
+    ///
     /// # Examples
     /// ```
     /// use aoi::runtime::opcode::AoArg;
+    /// use aoi::runtime::status::AoStatus;
     /// use aoi::runtime::types::AoType;
     /// use aoi::runtime::vm::AoVM;
     ///
     /// let mut vm = AoVM::default();
-    /// assert_eq!(AoArg::CA.get_value(&mut vm), AoType::default());
+    /// assert_eq!(AoArg::CA.get_value(&mut vm), Ok(AoType::default()));
+    /// assert_eq!(AoArg::GVS.get_value(&mut vm), Err(AoStatus::BadDataAccess));
     /// ```
-    pub fn get_value(&self, vm: &AoVM) -> AoType {
-        match self {
+    pub fn get_value(&self, vm: &AoVM) -> Result<AoType, AoStatus> {
+        let value = match self {
             AoArg::PC => AoType::AoPtr(vm.pc),
             AoArg::DP => AoType::AoPtr(vm.dp),
             AoArg::MP => AoType::AoPtr(vm.mp),
-            AoArg::DSB => AoType::AoPtr(vm.dsb as u32),
+            AoArg::DSB => AoType::AoPtr(vm.dsb),
             AoArg::DST => AoType::AoPtr(vm.ds.len() as u32),
             AoArg::CA => vm.ca.clone(),
             AoArg::CB => vm.cb.clone(),
             AoArg::DS => vm.ds[vm.dp as usize].clone(),
             AoArg::MEM => vm.mem.get(vm.mp),
-            AoArg::Imm(value) => value.clone(),
-        }
+            AoArg::GVS => match vm.gvs.get(vm.mp as usize) {
+                Some(value) => value.clone(),
+                None => return Err(AoStatus::BadDataAccess),
+            },
+            // a fresh copy, so that storing into it cannot change the program
+            AoArg::Imm(value) => value.deep_clone(),
+        };
+        Ok(value)
     }
 
     /// Sets the value of the argument.
@@ -117,9 +131,19 @@ impl AoArg {
                 AoStatus::Ok
             }
             AoArg::MEM => {
-                vm.mem.set(vm.mp, value);
-                AoStatus::Ok
+                if vm.mem.set(vm.mp, value) {
+                    AoStatus::Ok
+                } else {
+                    AoStatus::OutOfMemory
+                }
             }
+            AoArg::GVS => match vm.gvs.get_mut(vm.mp as usize) {
+                Some(slot) => {
+                    *slot = value;
+                    AoStatus::Ok
+                }
+                None => AoStatus::BadDataAccess,
+            },
             AoArg::Imm(_) => {
                 AoStatus::SetValueInvalidTarget("cannot set immediate value".to_string())
             }
@@ -139,16 +163,24 @@ impl Display for AoArg {
             AoArg::CB => write!(f, "cb"),
             AoArg::DS => write!(f, "ds"),
             AoArg::MEM => write!(f, "mem"),
+            AoArg::GVS => write!(f, "gvs"),
             AoArg::Imm(v) => write!(f, "{}", v),
         }
     }
 }
 
 macro_rules! impl_from {
+    ( $at:ident, String ) => {
+        impl From<String> for AoArg {
+            fn from(t: String) -> AoArg {
+                AoArg::Imm(AoType::from(t))
+            }
+        }
+    };
     ( $at:ident, &str ) => {
         impl From<&str> for AoArg {
             fn from(t: &str) -> AoArg {
-                AoArg::Imm(AoType::$at(t.to_string()))
+                AoArg::Imm(AoType::from(t))
             }
         }
     };
@@ -179,6 +211,7 @@ pub enum AoArgLowerCase {
     cb,
     ds,
     mem,
+    gvs,
     imm(AoType),
 }
 
@@ -194,6 +227,7 @@ impl AoArgLowerCase {
             AoArgLowerCase::cb => AoArg::CB,
             AoArgLowerCase::ds => AoArg::DS,
             AoArgLowerCase::mem => AoArg::MEM,
+            AoArgLowerCase::gvs => AoArg::GVS,
             AoArgLowerCase::imm(v) => AoArg::Imm(v.clone()),
         }
     }
@@ -0,0 +1,334 @@
+use std::fmt::Display;
+
+use super::opcodes::*;
+use crate::AoStatus;
+use crate::AoVM;
+
+macro_rules! instructions {
+    ( $( $id:literal => $t:ident ),* $(,)? ) => {
+        /// An opcode stored inline, executed with a `match` instead of a
+        /// virtual call.
+        ///
+        /// Programs built as `AoProgram` can be converted once with
+        /// [`to_instructions`] and then run by the VM like any other program.
+        #[derive(Clone)]
+        pub enum Instruction {
+            $( $t($t), )*
+        }
+
+        impl Instruction {
+            /// Copy a boxed opcode into an instruction.
+            ///
+            /// Returns `None` if the opcode is not one of the built-in ones.
+            pub fn from_opcode(opcode: &dyn AoOpcode) -> Option<Instruction> {
+                match opcode.get_id() {
+                    $(
+                        $id => {
+                            let mut inst = $t::default();
+                            inst.set_args(opcode.get_args());
+                            Some(Instruction::$t(inst))
+                        }
+                    )*
+                    _ => None,
+                }
+            }
+        }
+
+        impl Display for Instruction {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    $( Instruction::$t(inst) => inst.fmt(f), )*
+                }
+            }
+        }
+
+        impl Serializable for Instruction {
+            fn get_id(&self) -> u8 {
+                match self {
+                    $( Instruction::$t(inst) => inst.get_id(), )*
+                }
+            }
+            fn get_args(&self) -> OpcodeArgType {
+                match self {
+                    $( Instruction::$t(inst) => inst.get_args(), )*
+                }
+            }
+            fn set_args(&mut self, args: OpcodeArgType) {
+                match self {
+                    $( Instruction::$t(inst) => inst.set_args(args), )*
+                }
+            }
+        }
+
+        impl AoOpcode for Instruction {
+            #[inline]
+            fn execute(&self, vm: &mut AoVM) -> AoStatus {
+                match self {
+                    $( Instruction::$t(inst) => inst.execute(vm), )*
+                }
+            }
+
+            fn cost(&self) -> u64 {
+                match self {
+                    $( Instruction::$t(inst) => inst.cost(), )*
+                }
+            }
+        }
+    };
+}
+
+instructions! {
+    0x00 => Nop,
+    0x01 => NopN,
+
+    0x10 => Call,
+    0x11 => Ret,
+    0x12 => Jmp,
+    0x13 => Jmpa,
+    0x14 => Jt,
+    0x15 => Jta,
+    0x16 => Jf,
+    0x17 => Jfa,
+    0x18 => Jmpr,
+    0x19 => Throw,
+    0x1A => Jbound,
+    0x1B => Assert,
+    0x1C => Halt,
+    0x1D => JmpTable,
+    0x1E => RetV,
+    0x1F => Depth,
+
+    0x20 => Mov,
+    0x21 => Int,
+    0x22 => Push,
+    0x23 => Pop,
+    0x24 => Popn,
+    0x25 => Cswap,
+    0x26 => Memcpy,
+    0x27 => Memset,
+    0x28 => Swab,
+    0x29 => Peek,
+    0x2A => Pick,
+    0x2B => Roll,
+
+    0x30 => Add,
+    0x31 => Sub,
+    0x32 => Mul,
+    0x33 => Div,
+    0x34 => Rem,
+    0x35 => Inc,
+    0x36 => Dec,
+    0x37 => Shl,
+    0x38 => Shr,
+    0x3C => AddC,
+    0x3D => SubC,
+    0x3E => MulC,
+
+    0x40 => And,
+    0x41 => Or,
+    0x42 => Xor,
+    0x43 => Not,
+    0x44 => Band,
+    0x45 => Bor,
+    0x46 => Bxor,
+    0x47 => Bnot,
+    0x48 => Truthy,
+
+    0x50 => Equ,
+    0x51 => Neq,
+    0x52 => Gt,
+    0x53 => Lt,
+    0x54 => Ge,
+    0x55 => Le,
+    0x56 => Inset,
+    0x57 => EquI,
+
+    0x60 => CsiSat,
+    0x61 => Csi,
+    0x62 => Csf,
+    0x63 => Csp,
+    0x64 => Css,
+    0x65 => Scode,
+    0x66 => Cchar,
+    0x67 => CsiTrap,
+    0x68 => Isb,
+    0x69 => Isi,
+    0x6A => Isf,
+    0x6B => Isp,
+    0x6C => Iss,
+    0x6D => Ctoi,
+    0x6E => Ctof,
+    0x6F => Type,
+
+    0x70 => Arg,
+    0x71 => Cnf,
+    0x72 => Args2,
+    0x73 => Clrf,
+
+    0xB0 => Sizeof,
+    0xB1 => Format,
+    0xB2 => Strcat,
+    0xB3 => Rev,
+    0xB4 => Rand,
+    0xB5 => RandF,
+    0xB6 => Steps,
+
+    0xC0 => Newarr,
+    0xC1 => Aget,
+    0xC2 => Aset,
+    0xC3 => Alen,
+    0xC4 => Newmap,
+    0xC5 => Mget,
+    0xC6 => Mset,
+    0xC7 => Mhas,
+    0xC8 => Mlen,
+    0xC9 => Wrap,
+}
+
+/// Convert a program to instructions.
+///
+/// Returns `None` if the program contains an opcode that is not built in.
+///
+/// # Examples
+/// ```
+/// use aoi::*;
+/// use aoi::runtime::opcode::instruction::to_instructions;
+///
+/// let program = ao_program![
+///     mov ca,1
+///     add 2
+/// ];
+/// let instructions = to_instructions(&program).unwrap();
+///
+/// let mut vm = AoVM::default();
+/// assert_eq!(vm.run(&instructions), AoStatus::Exit);
+/// assert_eq!(vm.ca, AoType::AoInt(3));
+/// ```
+pub fn to_instructions(program: &[Box<dyn AoOpcode>]) -> Option<Vec<Instruction>> {
+    program
+        .iter()
+        .map(|opcode| Instruction::from_opcode(opcode.as_ref()))
+        .collect()
+}
+
+/// Convert a program to instructions with relative jumps turned into
+/// absolute ones, so that taking a jump only assigns `pc`.
+///
+/// `jmp`, `jt` and `jf` become `jmpa`, `jta` and `jfa`; `jbound` has no
+/// absolute form and is kept. The source program is left untouched, so it
+/// still serializes with its relative jumps.
+///
+/// Returns `None` if the program contains an opcode that is not built in.
+pub fn resolve_jumps(program: &[Box<dyn AoOpcode>]) -> Option<Vec<Instruction>> {
+    let mut instructions = to_instructions(program)?;
+    for (index, inst) in instructions.iter_mut().enumerate() {
+        let target = |addr: i32| (index as u32).wrapping_add_signed(addr);
+        *inst = match inst {
+            Instruction::Jmp(jmp) => Instruction::Jmpa(Jmpa {
+                addr: target(jmp.addr),
+            }),
+            Instruction::Jt(jt) => Instruction::Jta(Jta {
+                addr: target(jt.addr),
+            }),
+            Instruction::Jf(jf) => Instruction::Jfa(Jfa {
+                addr: target(jf.addr),
+            }),
+            _ => continue,
+        };
+    }
+    Some(instructions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn test_from_opcode() {
+        for id in 0..=u8::MAX {
+            if let Some(opcode) = create_opcode_by_id(id) {
+                let inst = Instruction::from_opcode(opcode.as_ref()).unwrap();
+                assert_eq!(inst.get_id(), id);
+                assert_eq!(inst.to_string(), opcode.to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn test_same_result() {
+        // sum of 1..=100
+        let program = ao_program![
+            push 1
+            push 0
+            arg 0
+            mov ca,ds
+            le 100
+            jfa 15
+            mov ca,ds
+            arg 1
+            add ds
+            mov ds,ca
+            arg 0
+            mov ca,ds
+            inc
+            mov ds,ca
+            jmpa 2
+        ];
+        let instructions = to_instructions(&program).unwrap();
+        assert_eq!(
+            AoAsmSerializer::serialize(&instructions),
+            AoAsmSerializer::serialize(&program)
+        );
+
+        let mut boxed = AoVM::default();
+        let mut inline = AoVM::default();
+        assert_eq!(boxed.step_n(&program, 10000), (AoStatus::Exit, 1306));
+        assert_eq!(inline.step_n(&instructions, 10000), (AoStatus::Exit, 1306));
+        assert_eq!(inline.ds, boxed.ds);
+        assert_eq!(inline.ds[1], AoType::AoInt(5050));
+        assert_eq!(inline.ca, boxed.ca);
+        assert_eq!(inline.pc, boxed.pc);
+    }
+
+    #[test]
+    fn test_resolve_jumps() {
+        // sum of 1..=100 counting down
+        let program = ao_program![
+            push 100
+            push 0
+            jmp 2
+            nop
+            arg 0
+            mov ca,ds
+            gt 0
+            jf 12
+            arg 1
+            mov ca,ds
+            arg 0
+            add ds
+            arg 1
+            mov ds,ca
+            arg 0
+            mov ca,ds
+            dec
+            mov ds,ca
+            jmp (-14)
+            jt 100
+        ];
+        let resolved = resolve_jumps(&program).unwrap();
+        assert_eq!(resolved[2].to_string(), "jmpa 4");
+        assert_eq!(resolved[7].to_string(), "jfa 19");
+        assert_eq!(resolved[18].to_string(), "jmpa 4");
+        assert_eq!(resolved[19].to_string(), "jta 119");
+        assert_eq!(program[18].to_string(), "jmp -14");
+
+        let mut relative = AoVM::default();
+        let mut absolute = AoVM::default();
+        assert_eq!(relative.run(&program), AoStatus::Exit);
+        assert_eq!(absolute.run(&resolved), AoStatus::Exit);
+        assert_eq!(relative.ds, vec![AoType::AoInt(0), AoType::AoInt(5050)]);
+        assert_eq!(absolute.ds, relative.ds);
+        assert_eq!(absolute.pc, relative.pc);
+    }
+}
@@ -3,31 +3,71 @@ macro_rules! ao_asm {
     ( nop ) => {
         Box::new(opcodes::Nop)
     };
+    ( nopn $pad:literal ) => {
+        Box::new(opcodes::NopN { pad: $pad })
+    };
+    ( halt ) => {
+        Box::new(opcodes::Halt { with_ca: false })
+    };
+    ( halt ca ) => {
+        Box::new(opcodes::Halt { with_ca: true })
+    };
+    ( assert ) => {
+        Box::new(opcodes::Assert { message: None })
+    };
+    ( assert $message:literal ) => {
+        Box::new(opcodes::Assert {
+            message: Some($message.to_string()),
+        })
+    };
 
-    ( call $addr:literal ) => {
+    ( call $addr:expr ) => {
         Box::new(opcodes::Call { addr: $addr })
     };
     ( ret ) => {
         Box::new(opcodes::Ret)
     };
+    ( retv ) => {
+        Box::new(opcodes::RetV)
+    };
+    ( depth ) => {
+        Box::new(opcodes::Depth)
+    };
     ( jmp $addr:expr ) => {
         Box::new(opcodes::Jmp { addr: $addr })
     };
-    ( jmpa $addr:literal ) => {
+    ( jmpa $addr:expr ) => {
         Box::new(opcodes::Jmpa { addr: $addr })
     };
     ( jt $addr:expr ) => {
         Box::new(opcodes::Jt { addr: $addr })
     };
-    ( jta $addr:literal ) => {
+    ( jta $addr:expr ) => {
         Box::new(opcodes::Jta { addr: $addr })
     };
     ( jf $addr:expr ) => {
         Box::new(opcodes::Jf { addr: $addr })
     };
-    ( jfa $addr:literal ) => {
+    ( jfa $addr:expr ) => {
         Box::new(opcodes::Jfa { addr: $addr })
     };
+    ( jmptable [$($target:expr),* $(,)?] ) => {
+        Box::new(opcodes::JmpTable {
+            targets: vec![$($target),*],
+        })
+    };
+    ( jmpr ) => {
+        Box::new(opcodes::Jmpr)
+    };
+    ( jbound $len:literal,$addr:expr ) => {
+        Box::new(opcodes::Jbound {
+            len: $len,
+            addr: $addr,
+        })
+    };
+    ( throw ) => {
+        Box::new(opcodes::Throw)
+    };
 
     ( mov $dst:ident,$src:ident ) => {
         Box::new(opcodes::Mov {
@@ -75,6 +115,27 @@ macro_rules! ao_asm {
     ( popn $count:literal ) => {
         Box::new(opcodes::Popn { count: $count })
     };
+    ( peek $offset:literal ) => {
+        Box::new(opcodes::Peek { offset: $offset })
+    };
+    ( pick $n:literal ) => {
+        Box::new(opcodes::Pick { n: $n })
+    };
+    ( roll $n:literal ) => {
+        Box::new(opcodes::Roll { n: $n })
+    };
+    ( cswap $i:literal,$j:literal ) => {
+        Box::new(opcodes::Cswap { i: $i, j: $j })
+    };
+    ( memcpy ) => {
+        Box::new(opcodes::Memcpy)
+    };
+    ( memset ) => {
+        Box::new(opcodes::Memset)
+    };
+    ( swab ) => {
+        Box::new(opcodes::Swab)
+    };
 
     ( add $src:ident ) => {
         Box::new(opcodes::Add {
@@ -152,6 +213,36 @@ macro_rules! ao_asm {
             src: AoArg::from($val),
         })
     };
+    ( addc $src:ident ) => {
+        Box::new(opcodes::AddC {
+            src: AoArgLowerCase::$src.to_aoarg(),
+        })
+    };
+    ( addc $val:literal ) => {
+        Box::new(opcodes::AddC {
+            src: AoArg::from($val),
+        })
+    };
+    ( subc $src:ident ) => {
+        Box::new(opcodes::SubC {
+            src: AoArgLowerCase::$src.to_aoarg(),
+        })
+    };
+    ( subc $val:literal ) => {
+        Box::new(opcodes::SubC {
+            src: AoArg::from($val),
+        })
+    };
+    ( mulc $src:ident ) => {
+        Box::new(opcodes::MulC {
+            src: AoArgLowerCase::$src.to_aoarg(),
+        })
+    };
+    ( mulc $val:literal ) => {
+        Box::new(opcodes::MulC {
+            src: AoArg::from($val),
+        })
+    };
 
     ( and $src:ident ) => {
         Box::new(opcodes::And {
@@ -219,6 +310,9 @@ macro_rules! ao_asm {
     ( bnot ) => {
         Box::new(opcodes::Bnot)
     };
+    ( truthy ) => {
+        Box::new(opcodes::Truthy)
+    };
 
     ( equ $src:ident ) => {
         Box::new(opcodes::Equ {
@@ -230,6 +324,16 @@ macro_rules! ao_asm {
             src: AoArg::from($val),
         })
     };
+    ( equi $src:ident ) => {
+        Box::new(opcodes::EquI {
+            src: AoArgLowerCase::$src.to_aoarg(),
+        })
+    };
+    ( equi $val:literal ) => {
+        Box::new(opcodes::EquI {
+            src: AoArg::from($val),
+        })
+    };
     ( neq $src:ident ) => {
         Box::new(opcodes::Neq {
             src: AoArgLowerCase::$src.to_aoarg(),
@@ -281,6 +385,9 @@ macro_rules! ao_asm {
         })
     };
 
+    ( csisat ) => {
+        Box::new(opcodes::CsiSat)
+    };
     ( csi ) => {
         Box::new(opcodes::Csi)
     };
@@ -293,6 +400,24 @@ macro_rules! ao_asm {
     ( css ) => {
         Box::new(opcodes::Css)
     };
+    ( scode ) => {
+        Box::new(opcodes::Scode)
+    };
+    ( cchar ) => {
+        Box::new(opcodes::Cchar)
+    };
+    ( csitrap ) => {
+        Box::new(opcodes::CsiTrap)
+    };
+    ( type ) => {
+        Box::new(opcodes::Type)
+    };
+    ( ctoi ) => {
+        Box::new(opcodes::Ctoi)
+    };
+    ( ctof ) => {
+        Box::new(opcodes::Ctof)
+    };
     ( isb ) => {
         Box::new(opcodes::Isb)
     };
@@ -315,6 +440,79 @@ macro_rules! ao_asm {
     ( cnf $argc:literal ) => {
         Box::new(opcodes::Cnf { argc: $argc })
     };
+    ( inset $($value:literal),+ ) => {
+        Box::new(opcodes::Inset {
+            set: vec![$(AoType::from($value)),+],
+        })
+    };
+    ( args2 ) => {
+        Box::new(opcodes::Args2)
+    };
+    ( clrf ) => {
+        Box::new(opcodes::Clrf)
+    };
+
+    ( sizeof ) => {
+        Box::new(opcodes::Sizeof)
+    };
+    ( format $count:literal ) => {
+        Box::new(opcodes::Format { count: $count })
+    };
+    ( strcat $count:literal ) => {
+        Box::new(opcodes::Strcat { count: $count })
+    };
+    ( rev ) => {
+        Box::new(opcodes::Rev)
+    };
+    ( rand ) => {
+        Box::new(opcodes::Rand)
+    };
+    ( randf ) => {
+        Box::new(opcodes::RandF)
+    };
+    ( steps ) => {
+        Box::new(opcodes::Steps)
+    };
+
+    ( newarr ) => {
+        Box::new(opcodes::Newarr)
+    };
+
+    ( aget ) => {
+        Box::new(opcodes::Aget)
+    };
+
+    ( aset ) => {
+        Box::new(opcodes::Aset)
+    };
+
+    ( alen ) => {
+        Box::new(opcodes::Alen)
+    };
+
+    ( newmap ) => {
+        Box::new(opcodes::Newmap)
+    };
+
+    ( mget ) => {
+        Box::new(opcodes::Mget)
+    };
+
+    ( mset ) => {
+        Box::new(opcodes::Mset)
+    };
+
+    ( mhas ) => {
+        Box::new(opcodes::Mhas)
+    };
+
+    ( mlen ) => {
+        Box::new(opcodes::Mlen)
+    };
+
+    ( wrap ) => {
+        Box::new(opcodes::Wrap)
+    };
 }
 
 #[macro_export]
@@ -326,6 +524,18 @@ macro_rules! ao_program {
         $v.push(ao_asm!(mov $dst,$src));
         ao_program!(@muncher $v, $($tail)*)
     };
+    (@muncher $v:ident, jbound $len:tt,$addr:tt $($tail:tt)* ) => {
+        $v.push(ao_asm!(jbound $len,$addr));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, cswap $i:tt,$j:tt $($tail:tt)* ) => {
+        $v.push(ao_asm!(cswap $i,$j));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, push [$($val:literal),* $(,)?] $($tail:tt)* ) => {
+        $( $v.push(ao_asm!(push $val)); )*
+        ao_program!(@muncher $v, $($tail)*)
+    };
 
     // no args
     (@muncher $v:ident, nop $($tail:tt)* ) => {
@@ -336,6 +546,42 @@ macro_rules! ao_program {
         $v.push(ao_asm!(ret));
         ao_program!(@muncher $v, $($tail)*)
     };
+    (@muncher $v:ident, retv $($tail:tt)* ) => {
+        $v.push(ao_asm!(retv));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, depth $($tail:tt)* ) => {
+        $v.push(ao_asm!(depth));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, halt ca $($tail:tt)* ) => {
+        $v.push(ao_asm!(halt ca));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, halt $($tail:tt)* ) => {
+        $v.push(ao_asm!(halt));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, assert $message:literal $($tail:tt)* ) => {
+        $v.push(ao_asm!(assert $message));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, assert $($tail:tt)* ) => {
+        $v.push(ao_asm!(assert));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, jmptable [$($target:tt)*] $($tail:tt)* ) => {
+        $v.push(ao_asm!(jmptable [$($target)*]));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, jmpr $($tail:tt)* ) => {
+        $v.push(ao_asm!(jmpr));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, throw $($tail:tt)* ) => {
+        $v.push(ao_asm!(throw));
+        ao_program!(@muncher $v, $($tail)*)
+    };
     (@muncher $v:ident, pop ca $($tail:tt)* ) => {
         $v.push(ao_asm!(pop ca));
         ao_program!(@muncher $v, $($tail)*)
@@ -344,6 +590,18 @@ macro_rules! ao_program {
         $v.push(ao_asm!(pop));
         ao_program!(@muncher $v, $($tail)*)
     };
+    (@muncher $v:ident, memcpy $($tail:tt)* ) => {
+        $v.push(ao_asm!(memcpy));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, memset $($tail:tt)* ) => {
+        $v.push(ao_asm!(memset));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, swab $($tail:tt)* ) => {
+        $v.push(ao_asm!(swab));
+        ao_program!(@muncher $v, $($tail)*)
+    };
     (@muncher $v:ident, inc $($tail:tt)* ) => {
         $v.push(ao_asm!(inc));
         ao_program!(@muncher $v, $($tail)*)
@@ -360,7 +618,15 @@ macro_rules! ao_program {
         $v.push(ao_asm!(bnot));
         ao_program!(@muncher $v, $($tail)*)
     };
+    (@muncher $v:ident, truthy $($tail:tt)* ) => {
+        $v.push(ao_asm!(truthy));
+        ao_program!(@muncher $v, $($tail)*)
+    };
 
+    (@muncher $v:ident, csisat $($tail:tt)* ) => {
+        $v.push(ao_asm!(csisat));
+        ao_program!(@muncher $v, $($tail)*)
+    };
     (@muncher $v:ident, csi $($tail:tt)* ) => {
         $v.push(ao_asm!(csi));
         ao_program!(@muncher $v, $($tail)*)
@@ -377,6 +643,30 @@ macro_rules! ao_program {
         $v.push(ao_asm!(css));
         ao_program!(@muncher $v, $($tail)*)
     };
+    (@muncher $v:ident, scode $($tail:tt)* ) => {
+        $v.push(ao_asm!(scode));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, cchar $($tail:tt)* ) => {
+        $v.push(ao_asm!(cchar));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, csitrap $($tail:tt)* ) => {
+        $v.push(ao_asm!(csitrap));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, type $($tail:tt)* ) => {
+        $v.push(ao_asm!(type));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, ctoi $($tail:tt)* ) => {
+        $v.push(ao_asm!(ctoi));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, ctof $($tail:tt)* ) => {
+        $v.push(ao_asm!(ctof));
+        ao_program!(@muncher $v, $($tail)*)
+    };
     (@muncher $v:ident, isb $($tail:tt)* ) => {
         $v.push(ao_asm!(isb));
         ao_program!(@muncher $v, $($tail)*)
@@ -398,6 +688,96 @@ macro_rules! ao_program {
         ao_program!(@muncher $v, $($tail)*)
     };
 
+    (@muncher $v:ident, inset $($tail:tt)* ) => {
+        ao_program!(@inset $v, [], $($tail)*)
+    };
+    (@inset $v:ident, [$($set:literal),*], $value:literal, $($tail:tt)* ) => {
+        ao_program!(@inset $v, [$($set,)* $value], $($tail)*)
+    };
+    (@inset $v:ident, [$($set:literal),*], $value:literal $($tail:tt)* ) => {
+        $v.push(ao_asm!(inset $($set,)* $value));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, args2 $($tail:tt)* ) => {
+        $v.push(ao_asm!(args2));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, clrf $($tail:tt)* ) => {
+        $v.push(ao_asm!(clrf));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+
+    (@muncher $v:ident, sizeof $($tail:tt)* ) => {
+        $v.push(ao_asm!(sizeof));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, rev $($tail:tt)* ) => {
+        $v.push(ao_asm!(rev));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, rand $($tail:tt)* ) => {
+        $v.push(ao_asm!(rand));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, randf $($tail:tt)* ) => {
+        $v.push(ao_asm!(randf));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+    (@muncher $v:ident, steps $($tail:tt)* ) => {
+        $v.push(ao_asm!(steps));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+
+    (@muncher $v:ident, newarr $($tail:tt)* ) => {
+        $v.push(ao_asm!(newarr));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+
+    (@muncher $v:ident, aget $($tail:tt)* ) => {
+        $v.push(ao_asm!(aget));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+
+    (@muncher $v:ident, aset $($tail:tt)* ) => {
+        $v.push(ao_asm!(aset));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+
+    (@muncher $v:ident, alen $($tail:tt)* ) => {
+        $v.push(ao_asm!(alen));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+
+    (@muncher $v:ident, newmap $($tail:tt)* ) => {
+        $v.push(ao_asm!(newmap));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+
+    (@muncher $v:ident, mget $($tail:tt)* ) => {
+        $v.push(ao_asm!(mget));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+
+    (@muncher $v:ident, mset $($tail:tt)* ) => {
+        $v.push(ao_asm!(mset));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+
+    (@muncher $v:ident, mhas $($tail:tt)* ) => {
+        $v.push(ao_asm!(mhas));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+
+    (@muncher $v:ident, mlen $($tail:tt)* ) => {
+        $v.push(ao_asm!(mlen));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+
+    (@muncher $v:ident, wrap $($tail:tt)* ) => {
+        $v.push(ao_asm!(wrap));
+        ao_program!(@muncher $v, $($tail)*)
+    };
+
     // one args
     (@muncher $v:ident, $op:ident $arg:tt $($tail:tt)* ) => {
         $v.push(ao_asm!($op $arg));
@@ -405,7 +785,7 @@ macro_rules! ao_program {
     };
 
     ( $( $rest:tt )* ) => {{
-        let mut program: aoi::AoProgram = vec![];
+        let mut program: $crate::AoProgram = vec![];
         ao_program!(@muncher program, $($rest)*);
         program
     }};
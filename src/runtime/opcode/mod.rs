@@ -1,7 +1,13 @@
 mod macros;
 
 pub mod args;
+pub mod instruction;
 pub mod opcodes;
+pub mod registry;
+pub mod table;
 
 pub use args::{AoArg, AoArgLowerCase};
+pub use instruction::Instruction;
 pub use opcodes::AoOpcode;
+pub use registry::OpcodeRegistry;
+pub use table::{opcode_table, OpcodeArgShape, OpcodeInfo};
@@ -1,9 +1,16 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::fmt::Display;
+use std::rc::Rc;
 
 use super::AoArg;
+use crate::AoAsmSerializer;
+use crate::AoMapKey;
 use crate::AoStatus;
 use crate::AoType;
+use crate::AoTypeTag;
 use crate::AoVM;
+use crate::MAX_STRING_LEN;
 
 #[allow(non_camel_case_types)]
 pub enum OpcodeArgType {
@@ -14,6 +21,10 @@ pub enum OpcodeArgType {
     bool(bool),
     AoArg(AoArg),
     AoArg2(AoArg, AoArg),
+    u32_u32(u32, u32),
+    u32_i32(u32, i32),
+    AoTypes(Vec<AoType>),
+    u32s(Vec<u32>),
 }
 
 pub trait Serializable {
@@ -24,6 +35,33 @@ pub trait Serializable {
 
 pub trait AoOpcode: Display + Serializable {
     fn execute(&self, vm: &mut AoVM) -> AoStatus;
+
+    /// Gas consumed by executing the opcode.
+    fn cost(&self) -> u64 {
+        1
+    }
+}
+
+impl<T: Serializable + ?Sized> Serializable for Box<T> {
+    fn get_id(&self) -> u8 {
+        (**self).get_id()
+    }
+    fn get_args(&self) -> OpcodeArgType {
+        (**self).get_args()
+    }
+    fn set_args(&mut self, args: OpcodeArgType) {
+        (**self).set_args(args)
+    }
+}
+
+impl<T: AoOpcode + ?Sized> AoOpcode for Box<T> {
+    fn execute(&self, vm: &mut AoVM) -> AoStatus {
+        (**self).execute(vm)
+    }
+
+    fn cost(&self) -> u64 {
+        (**self).cost()
+    }
 }
 
 macro_rules! impl_disp {
@@ -56,16 +94,26 @@ macro_rules! impl_disp {
 
 macro_rules! impl_ao_opcode {
     ( $t:tt, (&$s:ident, $v:ident) $e:block ) => {
-        #[allow(unused_variables)]
+        #[allow(unused_variables, unreachable_code)]
         impl AoOpcode for $t {
             fn execute(&$s, $v: &mut AoVM) -> AoStatus { $e AoStatus::Ok }
         }
     };
 }
 
+// Read an argument, returning the status from the opcode on failure.
+macro_rules! arg_value {
+    ( $arg:expr, $vm:ident ) => {
+        match $arg.get_value($vm) {
+            Ok(value) => value,
+            Err(status) => return status,
+        }
+    };
+}
+
 macro_rules! opcode {
     ( $t:tt, $c:expr, $d:expr, (&$s:ident, $v:ident) $e:block ) => {
-        #[derive(Clone)]
+        #[derive(Clone, Default)]
         pub struct $t;
         impl_disp!($t, $d);
         impl_ao_opcode!( $t, (&$s, $v) { $e });
@@ -80,11 +128,34 @@ macro_rules! opcode {
             }
         }
     };
+    ( $t:tt, $c:expr, $d:expr, $at:ident($ft1:tt $f1:ident, $ft2:tt $f2:ident), (&$s:ident, $v:ident) $e:block ) => {
+        #[derive(Clone, Default)]
+        pub struct $t {
+            pub $f1: $ft1,
+            pub $f2: $ft2,
+        }
+        impl_disp!($t, $d, $f1, $f2);
+        impl_ao_opcode!( $t, (&$s, $v) { $e });
+        impl Serializable for $t {
+            fn get_id(&self) -> u8 {
+                $c
+            }
+            fn get_args(&self) -> OpcodeArgType {
+                OpcodeArgType::$at(self.$f1, self.$f2)
+            }
+            fn set_args(&mut self, args: OpcodeArgType){
+                if let OpcodeArgType::$at($f1, $f2) = args {
+                    self.$f1 = $f1;
+                    self.$f2 = $f2;
+                }
+            }
+        }
+    };
     ( $t:tt, $c:expr, $d:expr, $f:ident, (&$s:ident, $v:ident) $e:block ) => {
         opcode!($t, $c, $d, AoArg $f, (&$s, $v) $e);
     };
     ( $t:tt, $c:expr, $d:expr, $ft:tt $f:ident, (&$s:ident, $v:ident) $e:block ) => {
-        #[derive(Clone)]
+        #[derive(Clone, Default)]
         pub struct $t {
             pub $f: $ft,
         }
@@ -105,7 +176,7 @@ macro_rules! opcode {
         }
     };
     ( $t:tt, $c:expr, $mt:expr, $mf:expr, bool $f:ident, (&$s:ident, $v:ident) $e:block ) => {
-        #[derive(Clone)]
+        #[derive(Clone, Default)]
         pub struct $t {
             pub $f: bool,
         }
@@ -126,7 +197,7 @@ macro_rules! opcode {
         }
     };
     ( $t:tt, $c:expr, $d:expr, $f1:ident, $f2:ident, (&$s:ident, $v:ident) $e:block ) => {
-        #[derive(Clone)]
+        #[derive(Clone, Default)]
         pub struct $t {
             pub $f1: AoArg,
             pub $f2: AoArg,
@@ -152,9 +223,13 @@ macro_rules! opcode {
 
 opcode!(Nop, 0x00, "nop", (&self, _vm) {});
 
+// Does nothing, but takes as many bytes as an opcode with a u32 argument, so
+// it can later be replaced by one in place.
+opcode!(NopN, 0x01, "nopn {}", u32 pad, (&self, _vm) {});
+
 opcode!(Call, 0x10, "call {}", u32 addr, (&self, vm) {
-    if vm.cs.len() >= 100000 {
-        return AoStatus::CallStackOverflow;
+    if vm.cs.len() >= vm.max_call_depth {
+        return AoStatus::CallStackOverflow(vm.max_call_depth);
     }
     vm.cs.push(vm.pc);
     vm.pc = self.addr;
@@ -175,60 +250,198 @@ opcode!(Ret, 0x11, "ret", (&self, vm) {
     }
 });
 
+// Same as `ret`, but leaves the value on top of the frame in CA.
+opcode!(RetV, 0x1E, "retv", (&self, vm) {
+    if vm.cs.is_empty() {
+        return AoStatus::CallStackUnderflow;
+    }
+    let value = match vm.ds.get(vm.dsb as usize..) {
+        Some([.., value]) => value.clone(),
+        _ => return AoStatus::DataStackUnderflow,
+    };
+
+    match Ret.execute(vm) {
+        AoStatus::Ok => vm.ca = value,
+        status => return status,
+    }
+});
+
+// Number of calls that have not returned yet.
+opcode!(Depth, 0x1F, "depth", (&self, vm) {
+    vm.ca = AoType::AoInt(vm.cs.len() as i32);
+});
+
 opcode!(Jmp, 0x12, "jmp {}", i32 addr, (&self, vm) {
-    vm.pc = (vm.pc as i32 + self.addr - 1) as u32;
+    vm.pc = vm.pc.wrapping_add_signed(self.addr).wrapping_sub(1);
 });
 
 opcode!(Jmpa, 0x13, "jmpa {}", u32 addr, (&self, vm) {
     vm.pc = self.addr;
 });
 
-opcode!(Jt, 0x14, "jt {}", i32 addr, (&self, vm) {
-    if match vm.ca {
-        AoType::AoBool(b) => b,
-        AoType::AoInt(i) => i != 0,
-        AoType::AoFloat(f) => f != 0.0,
+// Whether conditional jumps and `assert` treat the value as true.
+fn truthy(value: &AoType) -> bool {
+    match value {
+        AoType::AoBool(b) => *b,
+        AoType::AoInt(i) => *i != 0,
+        AoType::AoFloat(f) => *f != 0.0,
         _ => false,
-    } {
-        vm.pc = (vm.pc as i32 + self.addr - 1) as u32;
+    }
+}
+
+opcode!(Jt, 0x14, "jt {}", i32 addr, (&self, vm) {
+    if truthy(&vm.ca) {
+        vm.pc = vm.pc.wrapping_add_signed(self.addr).wrapping_sub(1);
     }
 });
 
 opcode!(Jta, 0x15, "jta {}", u32 addr, (&self, vm) {
-    if match vm.ca {
-        AoType::AoBool(b) => b,
-        AoType::AoInt(i) => i != 0,
-        AoType::AoFloat(f) => f != 0.0,
-        _ => false,
-    } {
+    if truthy(&vm.ca) {
         vm.pc = self.addr;
     }
 });
 
 opcode!(Jf, 0x16, "jf {}", i32 addr, (&self, vm) {
-    if !match vm.ca {
-        AoType::AoBool(b) => b,
-        AoType::AoInt(i) => i != 0,
-        AoType::AoFloat(f) => f != 0.0,
-        _ => false,
-    } {
-        vm.pc = (vm.pc as i32 + self.addr - 1) as u32;
+    if !truthy(&vm.ca) {
+        vm.pc = vm.pc.wrapping_add_signed(self.addr).wrapping_sub(1);
     }
 });
 
 opcode!(Jfa, 0x17, "jfa {}", u32 addr, (&self, vm) {
-    if !match vm.ca {
-        AoType::AoBool(b) => b,
-        AoType::AoInt(i) => i != 0,
-        AoType::AoFloat(f) => f != 0.0,
-        _ => false,
-    } {
+    if !truthy(&vm.ca) {
         vm.pc = self.addr;
     }
 });
 
+// Jump to the absolute instruction index held in CA, like `Jmpa`.
+opcode!(Jmpr, 0x18, "jmpr", (&self, vm) {
+    if let AoType::AoPtr(p) = vm.ca {
+        vm.pc = p;
+    } else {
+        return AoStatus::InvalidOperation(format!("jmpr {}", vm.ca));
+    }
+});
+
+opcode!(Throw, 0x19, "throw", (&self, vm) {
+    let (message, code) = match (vm.pop(), vm.pop()) {
+        (Some(message), Some(code)) => (message, code),
+        _ => return AoStatus::DataStackUnderflow,
+    };
+
+    return match (message, code) {
+        (AoType::AoString(message), AoType::AoInt(code)) => AoStatus::ScriptError {
+            code,
+            message: message.to_string(),
+        },
+        _ => AoStatus::BadDataStack,
+    };
+});
+
+// Jump relatively when CA is an index in `0..len`, fall through otherwise.
+// Non-integer indices are an invalid operation.
+opcode!(Jbound, 0x1A, "jbound {},{}", u32_i32(u32 len, i32 addr), (&self, vm) {
+    let in_bounds = match vm.ca {
+        AoType::AoInt(i) => i >= 0 && (i as u32) < self.len,
+        AoType::AoPtr(p) => p < self.len,
+        _ => return AoStatus::InvalidOperation(format!("jbound {}", vm.ca)),
+    };
+    if in_bounds {
+        vm.pc = vm.pc.wrapping_add_signed(self.addr).wrapping_sub(1);
+    }
+});
+
+// Stop with `AssertionFailed` unless CA is true.
+#[derive(Clone, Default)]
+pub struct Assert {
+    pub message: Option<String>,
+}
+impl Display for Assert {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.message {
+            Some(message) => write!(f, "assert {}", AoType::from(message.as_str())),
+            None => write!(f, "assert"),
+        }
+    }
+}
+impl_ao_opcode!(Assert, (&self, vm) {
+    if !truthy(&vm.ca) {
+        return AoStatus::AssertionFailed(match &self.message {
+            Some(message) => message.clone(),
+            None => format!("ca is {}", vm.ca),
+        });
+    }
+});
+impl Serializable for Assert {
+    fn get_id(&self) -> u8 {
+        0x1B
+    }
+    fn get_args(&self) -> OpcodeArgType {
+        OpcodeArgType::AoTypes(
+            self.message
+                .iter()
+                .map(|m| AoType::from(m.as_str()))
+                .collect(),
+        )
+    }
+    fn set_args(&mut self, args: OpcodeArgType) {
+        if let OpcodeArgType::AoTypes(values) = args {
+            self.message = match values.first() {
+                Some(AoType::AoString(message)) => Some(message.to_string()),
+                _ => None,
+            };
+        }
+    }
+}
+
+// Stop the program without touching the stack frame. `halt ca` also hands
+// CA to the host as the result.
+opcode!(Halt, 0x1C, "halt ca", "halt", bool with_ca, (&self, vm) {
+    if self.with_ca {
+        return AoStatus::ExitWith(vm.ca.clone());
+    }
+    return AoStatus::Exit;
+});
+
+// Jump to the target at index CA, or fall through if it is out of range.
+#[derive(Clone, Default)]
+pub struct JmpTable {
+    pub targets: Vec<u32>,
+}
+impl Display for JmpTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "jmptable")?;
+        for (i, target) in self.targets.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { "," }, target)?;
+        }
+        Ok(())
+    }
+}
+impl_ao_opcode!(JmpTable, (&self, vm) {
+    let index = match vm.ca {
+        AoType::AoInt(index) => index,
+        _ => return AoStatus::InvalidOperation(format!("jmptable {}", vm.ca)),
+    };
+    if let Some(target) = usize::try_from(index).ok().and_then(|i| self.targets.get(i)) {
+        vm.pc = *target;
+    }
+});
+impl Serializable for JmpTable {
+    fn get_id(&self) -> u8 {
+        0x1D
+    }
+    fn get_args(&self) -> OpcodeArgType {
+        OpcodeArgType::u32s(self.targets.clone())
+    }
+    fn set_args(&mut self, args: OpcodeArgType) {
+        if let OpcodeArgType::u32s(targets) = args {
+            self.targets = targets;
+        }
+    }
+}
+
 opcode!(Mov, 0x20, "mov {},{}", dst, src, (&self, vm) {
-    match self.dst.set_value(vm, self.src.get_value(vm)) {
+    let value = arg_value!(self.src, vm);
+    match self.dst.set_value(vm, value) {
         AoStatus::Ok => (),
         err => return err,
     }
@@ -244,8 +457,22 @@ opcode!(Int, 0x21, "int {}", u8 id, (&self, vm) {
         args.push(arg.clone());
     }
 
-    if let Some(value) = (vm.interrupt)(self.id, args) {
-        vm.ca = value;
+    let result = if let Some(table) = &vm.interrupt_table {
+        match table.borrow_mut().get_mut(self.id) {
+            Some(handler) => handler(args),
+            None => return AoStatus::UnknownInterrupt(self.id),
+        }
+    } else {
+        (vm.interrupt.borrow_mut())(self.id, args)
+    };
+    let yielded = match result {
+        Ok(Some(value)) => {
+            vm.ca = value;
+            false
+        }
+        Ok(None) => false,
+        Err(AoStatus::Yielded) => true,
+        Err(status) => return status,
     };
 
     let dsb = vm.dsb - 1;
@@ -255,11 +482,15 @@ opcode!(Int, 0x21, "int {}", u8 id, (&self, vm) {
     } else {
         return AoStatus::BadDataStack;
     }
+    if yielded {
+        return AoStatus::Yielded;
+    }
 });
 
 opcode!(Push, 0x22, "push {}", src, (&self, vm) {
-    if !vm.push(self.src.get_value(vm)) {
-        return AoStatus::DataStackOverflow;
+    let value = arg_value!(self.src, vm);
+    if !vm.push(value) {
+        return AoStatus::DataStackOverflow(vm.max_stack);
     }
 });
 
@@ -283,8 +514,118 @@ opcode!(Popn, 0x24, "popn {}", u32 count, (&self, vm) {
     }
 });
 
+opcode!(Cswap, 0x25, "cswap {},{}", u32_u32(u32 i, u32 j), (&self, vm) {
+    let (i, j) = (self.i as usize, self.j as usize);
+    if i >= vm.ds.len() || j >= vm.ds.len() {
+        return AoStatus::DataStackUnderflow;
+    }
+
+    let (left, right) = promote_numeric(vm.ds[i].clone(), vm.ds[j].clone());
+    if !ordered(&left, &right) {
+        return AoStatus::InvalidOperation(format!("{} > {}", left, right));
+    }
+    if left > right {
+        vm.ds.swap(i, j);
+    }
+});
+
+// Read a memory address or length from a pointer or non-negative integer.
+fn mem_operand(value: &AoType) -> Option<u32> {
+    match value {
+        AoType::AoPtr(v) => Some(*v),
+        AoType::AoInt(v) if *v >= 0 => Some(*v as u32),
+        _ => None,
+    }
+}
+
+// Pop length, source and destination, then copy the memory block.
+opcode!(Memcpy, 0x26, "memcpy", (&self, vm) {
+    if vm.ds.len() < 3 {
+        return AoStatus::DataStackUnderflow;
+    }
+    let len = vm.pop().unwrap();
+    let src = vm.pop().unwrap();
+    let dst = vm.pop().unwrap();
+
+    let (dst, src, len) = match (mem_operand(&dst), mem_operand(&src), mem_operand(&len)) {
+        (Some(dst), Some(src), Some(len)) => (dst, src, len),
+        _ => return AoStatus::InvalidOperation(format!("memcpy {},{},{}", dst, src, len)),
+    };
+    // copy backwards when the destination overlaps the end of the source
+    if dst > src {
+        for i in (0..len).rev() {
+            if !vm.mem.set(dst.wrapping_add(i), vm.mem.get(src.wrapping_add(i))) {
+                return AoStatus::OutOfMemory;
+            }
+        }
+    } else {
+        for i in 0..len {
+            if !vm.mem.set(dst.wrapping_add(i), vm.mem.get(src.wrapping_add(i))) {
+                return AoStatus::OutOfMemory;
+            }
+        }
+    }
+});
+
+// Pop length, base and value, then fill the memory block with the value.
+opcode!(Memset, 0x27, "memset", (&self, vm) {
+    if vm.ds.len() < 3 {
+        return AoStatus::DataStackUnderflow;
+    }
+    let len = vm.pop().unwrap();
+    let base = vm.pop().unwrap();
+    let value = vm.pop().unwrap();
+
+    let (base, len) = match (mem_operand(&base), &len) {
+        (Some(base), AoType::AoInt(len)) if *len >= 0 => (base, *len as u32),
+        _ => return AoStatus::InvalidOperation(format!("memset {},{},{}", value, base, len)),
+    };
+    for i in 0..len {
+        if !vm.mem.set(base.wrapping_add(i), value.clone()) {
+            return AoStatus::OutOfMemory;
+        }
+    }
+});
+
+// Swap CA and CB, so that CB can hold a second operand.
+opcode!(Swab, 0x28, "swab", (&self, vm) {
+    std::mem::swap(&mut vm.ca, &mut vm.cb);
+});
+
+// Load the value `offset` slots below the top of the data stack into CA.
+opcode!(Peek, 0x29, "peek {}", u32 offset, (&self, vm) {
+    let len = vm.ds.len();
+    if self.offset as usize >= len {
+        return AoStatus::DataStackUnderflow;
+    }
+    vm.ca = vm.ds[len - 1 - self.offset as usize].clone();
+});
+
+// Copy the value `n` slots below the top of the data stack to the top.
+opcode!(Pick, 0x2A, "pick {}", u32 n, (&self, vm) {
+    let len = vm.ds.len();
+    if self.n as usize >= len {
+        return AoStatus::DataStackUnderflow;
+    }
+    if !vm.push(vm.ds[len - 1 - self.n as usize].clone()) {
+        return AoStatus::DataStackOverflow(vm.max_stack);
+    }
+});
+
+// Move the value `n` slots below the top of the data stack to the top.
+opcode!(Roll, 0x2B, "roll {}", u32 n, (&self, vm) {
+    let len = vm.ds.len();
+    if self.n as usize >= len {
+        return AoStatus::DataStackUnderflow;
+    }
+    let value = vm.ds.remove(len - 1 - self.n as usize);
+    vm.ds.push(value);
+});
+
+// Int `add`, `sub` and `mul` wrap on overflow; `addc`, `subc` and `mulc`
+// below are the checked forms.
 opcode!(Add, 0x30, "add {}", src, (&self, vm) {
-    let res = vm.ca.clone() + self.src.get_value(vm);
+    let res = vm.ca.clone() + arg_value!(self.src, vm);
     if let AoStatus::Return(value) = res {
         vm.ca = value;
     } else {
@@ -293,7 +634,7 @@ opcode!(Add, 0x30, "add {}", src, (&self, vm) {
 });
 
 opcode!(Sub, 0x31, "sub {}", src, (&self, vm) {
-    let res = vm.ca.clone() - self.src.get_value(vm);
+    let res = vm.ca.clone() - arg_value!(self.src, vm);
     if let AoStatus::Return(value) = res {
         vm.ca = value;
     } else {
@@ -302,7 +643,7 @@ opcode!(Sub, 0x31, "sub {}", src, (&self, vm) {
 });
 
 opcode!(Mul, 0x32, "mul {}", src, (&self, vm) {
-    let res = vm.ca.clone() * self.src.get_value(vm);
+    let res = vm.ca.clone() * arg_value!(self.src, vm);
     if let AoStatus::Return(value) = res {
         vm.ca = value;
     } else {
@@ -311,7 +652,7 @@ opcode!(Mul, 0x32, "mul {}", src, (&self, vm) {
 });
 
 opcode!(Div, 0x33, "div {}", src, (&self, vm) {
-    let res = vm.ca.clone() / self.src.get_value(vm);
+    let res = vm.ca.clone() / arg_value!(self.src, vm);
     if let AoStatus::Return(value) = res {
         vm.ca = value;
     } else {
@@ -320,7 +661,7 @@ opcode!(Div, 0x33, "div {}", src, (&self, vm) {
 });
 
 opcode!(Rem, 0x34, "rem {}", src, (&self, vm) {
-    let res = vm.ca.clone() % self.src.get_value(vm);
+    let res = vm.ca.clone() % arg_value!(self.src, vm);
     if let AoStatus::Return(value) = res {
         vm.ca = value;
     } else {
@@ -331,7 +672,7 @@ opcode!(Rem, 0x34, "rem {}", src, (&self, vm) {
 opcode!(Inc, 0x35, "inc", (&self, vm) {
     match vm.ca {
         AoType::AoInt(i) => {
-            vm.ca = AoType::AoInt(i + 1);
+            vm.ca = AoType::AoInt(i.wrapping_add(1));
         }
         AoType::AoFloat(f) => {
             vm.ca = AoType::AoFloat(f + 1.0);
@@ -343,7 +684,7 @@ opcode!(Inc, 0x35, "inc", (&self, vm) {
 opcode!(Dec, 0x36, "dec", (&self, vm) {
     match vm.ca {
         AoType::AoInt(i) => {
-            vm.ca = AoType::AoInt(i - 1);
+            vm.ca = AoType::AoInt(i.wrapping_sub(1));
         }
         AoType::AoFloat(f) => {
             vm.ca = AoType::AoFloat(f - 1.0);
@@ -353,7 +694,7 @@ opcode!(Dec, 0x36, "dec", (&self, vm) {
 });
 
 opcode!(Shl, 0x37, "shl {}", src, (&self, vm) {
-    let res = vm.ca.clone() << self.src.get_value(vm);
+    let res = vm.ca.clone() << arg_value!(self.src, vm);
     if let AoStatus::Return(value) = res {
         vm.ca = value;
     } else {
@@ -362,7 +703,56 @@ opcode!(Shl, 0x37, "shl {}", src, (&self, vm) {
 });
 
 opcode!(Shr, 0x38, "shr {}", src, (&self, vm) {
-    let res = vm.ca.clone() >> self.src.get_value(vm);
+    let res = vm.ca.clone() >> arg_value!(self.src, vm);
+    if let AoStatus::Return(value) = res {
+        vm.ca = value;
+    } else {
+        return res;
+    }
+});
+
+// Combine two ints with `int`, returning `AoStatus::ArithmeticOverflow` when
+// it gives `None`. Other operands use the default operator.
+fn int_arith(
+    left: AoType,
+    right: AoType,
+    int: fn(i32, i32) -> Option<i32>,
+    default: fn(AoType, AoType) -> AoStatus,
+) -> AoStatus {
+    match (&left, &right) {
+        (AoType::AoInt(l), AoType::AoInt(r)) => match int(*l, *r) {
+            Some(value) => AoStatus::Return(AoType::AoInt(value)),
+            None => AoStatus::ArithmeticOverflow,
+        },
+        _ => default(left, right),
+    }
+}
+
+// Int arithmetic that fails with `AoStatus::ArithmeticOverflow` on overflow,
+// for programs that must not wrap.
+opcode!(AddC, 0x3C, "addc {}", src, (&self, vm) {
+    let right = arg_value!(self.src, vm);
+    let res = int_arith(vm.ca.clone(), right, i32::checked_add, |l, r| l + r);
+    if let AoStatus::Return(value) = res {
+        vm.ca = value;
+    } else {
+        return res;
+    }
+});
+
+opcode!(SubC, 0x3D, "subc {}", src, (&self, vm) {
+    let right = arg_value!(self.src, vm);
+    let res = int_arith(vm.ca.clone(), right, i32::checked_sub, |l, r| l - r);
+    if let AoStatus::Return(value) = res {
+        vm.ca = value;
+    } else {
+        return res;
+    }
+});
+
+opcode!(MulC, 0x3E, "mulc {}", src, (&self, vm) {
+    let right = arg_value!(self.src, vm);
+    let res = int_arith(vm.ca.clone(), right, i32::checked_mul, |l, r| l * r);
     if let AoStatus::Return(value) = res {
         vm.ca = value;
     } else {
@@ -372,7 +762,7 @@ opcode!(Shr, 0x38, "shr {}", src, (&self, vm) {
 
 opcode!(And, 0x40, "and {}", src, (&self, vm) {
     let left = vm.ca.clone();
-    let right = self.src.get_value(vm);
+    let right = arg_value!(self.src, vm);
 
     if let (AoType::AoBool(l), AoType::AoBool(r)) = (&left, &right) {
         vm.ca = AoType::AoBool(*l && *r);
@@ -383,7 +773,7 @@ opcode!(And, 0x40, "and {}", src, (&self, vm) {
 
 opcode!(Or, 0x41, "or {}", src, (&self, vm) {
     let left = vm.ca.clone();
-    let right = self.src.get_value(vm);
+    let right = arg_value!(self.src, vm);
 
     if let (AoType::AoBool(l), AoType::AoBool(r)) = (&left, &right) {
         vm.ca = AoType::AoBool(*l || *r);
@@ -394,7 +784,7 @@ opcode!(Or, 0x41, "or {}", src, (&self, vm) {
 
 opcode!(Xor, 0x42, "xor {}", src, (&self, vm) {
     let left = vm.ca.clone();
-    let right = self.src.get_value(vm);
+    let right = arg_value!(self.src, vm);
 
     if let (AoType::AoBool(l), AoType::AoBool(r)) = (&left, &right) {
         vm.ca = AoType::AoBool(*l ^ *r);
@@ -412,7 +802,7 @@ opcode!(Not, 0x43, "not", (&self, vm) {
 });
 
 opcode!(Band, 0x44, "band {}", src, (&self, vm) {
-    let res = vm.ca.clone() & self.src.get_value(vm);
+    let res = vm.ca.clone() & arg_value!(self.src, vm);
     if let AoStatus::Return(value) = res {
         vm.ca = value;
     } else {
@@ -421,7 +811,7 @@ opcode!(Band, 0x44, "band {}", src, (&self, vm) {
 });
 
 opcode!(Bor, 0x45, "bor {}", src, (&self, vm) {
-    let res = vm.ca.clone() | self.src.get_value(vm);
+    let res = vm.ca.clone() | arg_value!(self.src, vm);
     if let AoStatus::Return(value) = res {
         vm.ca = value;
     } else {
@@ -430,7 +820,7 @@ opcode!(Bor, 0x45, "bor {}", src, (&self, vm) {
 });
 
 opcode!(Bxor, 0x46, "bxor {}", src, (&self, vm) {
-    let res = vm.ca.clone() ^ self.src.get_value(vm);
+    let res = vm.ca.clone() ^ arg_value!(self.src, vm);
     if let AoStatus::Return(value) = res {
         vm.ca = value;
     } else {
@@ -446,173 +836,219 @@ opcode!(Bnot, 0x47, "bnot", (&self, vm) {
     }
 });
 
-opcode!(Equ, 0x50, "equ {}", src, (&self, vm) {
-    let left = vm.ca.clone();
-    let right = self.src.get_value(vm);
+// Replace CA with whether `jt` would jump on it.
+opcode!(Truthy, 0x48, "truthy", (&self, vm) {
+    vm.ca = AoType::AoBool(truthy(&vm.ca));
+});
 
+// Promote an int compared with a float to float, as arithmetic does.
+fn promote_numeric(left: AoType, right: AoType) -> (AoType, AoType) {
     match (left, right) {
-        (AoType::AoBool(left), AoType::AoBool(right)) => {
-            vm.ca = AoType::AoBool(left == right);
-        }
-        (AoType::AoInt(left), AoType::AoInt(right)) => {
-            vm.ca = AoType::AoBool(left == right);
-        }
-        (AoType::AoFloat(left), AoType::AoFloat(right)) => {
-            vm.ca = AoType::AoBool(left == right);
-        }
-        (AoType::AoString(left), AoType::AoString(right)) => {
-            vm.ca = AoType::AoBool(left == right);
-        }
-        _ => vm.ca = AoType::AoBool(false),
+        (AoType::AoInt(l), AoType::AoFloat(r)) => (AoType::AoFloat(l as f32), AoType::AoFloat(r)),
+        (AoType::AoFloat(l), AoType::AoInt(r)) => (AoType::AoFloat(l), AoType::AoFloat(r as f32)),
+        pair => pair,
     }
+}
+
+opcode!(Equ, 0x50, "equ {}", src, (&self, vm) {
+    let right = arg_value!(self.src, vm);
+    let (left, right) = promote_numeric(vm.ca.clone(), right);
+    vm.ca = AoType::AoBool(left == right);
 });
 
-opcode!(Neq, 0x51, "neq {}", src, (&self, vm) {
-    let left = vm.ca.clone();
-    let right = self.src.get_value(vm);
+// Compare two strings ignoring ASCII case.
+opcode!(EquI, 0x57, "equi {}", src, (&self, vm) {
+    let right = arg_value!(self.src, vm);
 
-    match (left, right) {
-        (AoType::AoBool(left), AoType::AoBool(right)) => {
-            vm.ca = AoType::AoBool(left != right);
-        }
-        (AoType::AoInt(left), AoType::AoInt(right)) => {
-            vm.ca = AoType::AoBool(left != right);
-        }
-        (AoType::AoFloat(left), AoType::AoFloat(right)) => {
-            vm.ca = AoType::AoBool(left != right);
-        }
+    match (&vm.ca, &right) {
         (AoType::AoString(left), AoType::AoString(right)) => {
-            vm.ca = AoType::AoBool(left != right);
+            vm.ca = AoType::AoBool(left.eq_ignore_ascii_case(right));
         }
-        _ => vm.ca = AoType::AoBool(true),
+        _ => return AoStatus::InvalidOperation(format!("{} equi {}", vm.ca, right)),
     }
 });
 
+opcode!(Neq, 0x51, "neq {}", src, (&self, vm) {
+    let right = arg_value!(self.src, vm);
+    let (left, right) = promote_numeric(vm.ca.clone(), right);
+    vm.ca = AoType::AoBool(left != right);
+});
+
+// Whether the values can be ordered by `gt`, `lt`, `ge` and `le`. Arrays,
+// maps and values of different types cannot, while floats can even when one
+// is NaN, comparing as false.
+fn ordered(left: &AoType, right: &AoType) -> bool {
+    left.partial_cmp(right).is_some()
+        || matches!((left, right), (AoType::AoFloat(_), AoType::AoFloat(_)))
+}
+
 opcode!(Gt, 0x52, "gt {}", src, (&self, vm) {
-    let left = vm.ca.clone();
-    let right = self.src.get_value(vm);
+    let right = arg_value!(self.src, vm);
+    let (left, right) = promote_numeric(vm.ca.clone(), right);
 
-    match (&left, &right) {
-        (AoType::AoBool(left), AoType::AoBool(right)) => {
-            vm.ca = AoType::AoBool(left > right);
-        }
-        (AoType::AoInt(left), AoType::AoInt(right)) => {
-            vm.ca = AoType::AoBool(left > right);
-        }
-        (AoType::AoFloat(left), AoType::AoFloat(right)) => {
-            vm.ca = AoType::AoBool(left > right);
-        }
-        (AoType::AoString(left), AoType::AoString(right)) => {
-            vm.ca = AoType::AoBool(left > right);
-        }
-        _ => return AoStatus::InvalidOperation(format!("{} > {}", left, right)),
+    if !ordered(&left, &right) {
+        return AoStatus::InvalidOperation(format!("{} > {}", left, right));
     }
+    vm.ca = AoType::AoBool(left > right);
 });
 
 opcode!(Lt, 0x53, "lt {}", src, (&self, vm) {
-    let left = vm.ca.clone();
-    let right = self.src.get_value(vm);
+    let right = arg_value!(self.src, vm);
+    let (left, right) = promote_numeric(vm.ca.clone(), right);
 
-    match (&left, &right) {
-        (AoType::AoBool(left), AoType::AoBool(right)) => {
-            vm.ca = AoType::AoBool(left < right);
-        }
-        (AoType::AoInt(left), AoType::AoInt(right)) => {
-            vm.ca = AoType::AoBool(left < right);
-        }
-        (AoType::AoFloat(left), AoType::AoFloat(right)) => {
-            vm.ca = AoType::AoBool(left < right);
-        }
-        (AoType::AoString(left), AoType::AoString(right)) => {
-            vm.ca = AoType::AoBool(left < right);
-        }
-        _ => return AoStatus::InvalidOperation(format!("{} < {}", left, right)),
+    if !ordered(&left, &right) {
+        return AoStatus::InvalidOperation(format!("{} < {}", left, right));
     }
+    vm.ca = AoType::AoBool(left < right);
 });
 
 opcode!(Ge, 0x54, "ge {}", src, (&self, vm) {
-    let left = vm.ca.clone();
-    let right = self.src.get_value(vm);
+    let right = arg_value!(self.src, vm);
+    let (left, right) = promote_numeric(vm.ca.clone(), right);
 
-    match (&left, &right) {
-        (AoType::AoBool(left), AoType::AoBool(right)) => {
-            vm.ca = AoType::AoBool(left >= right);
-        }
-        (AoType::AoInt(left), AoType::AoInt(right)) => {
-            vm.ca = AoType::AoBool(left >= right);
-        }
-        (AoType::AoFloat(left), AoType::AoFloat(right)) => {
-            vm.ca = AoType::AoBool(left >= right);
-        }
-        (AoType::AoString(left), AoType::AoString(right)) => {
-            vm.ca = AoType::AoBool(left >= right);
-        }
-        _ => return AoStatus::InvalidOperation(format!("{} >= {}", left, right)),
+    if !ordered(&left, &right) {
+        return AoStatus::InvalidOperation(format!("{} >= {}", left, right));
     }
+    vm.ca = AoType::AoBool(left >= right);
 });
 
 opcode!(Le, 0x55, "le {}", src, (&self, vm) {
-    let left = vm.ca.clone();
-    let right = self.src.get_value(vm);
+    let right = arg_value!(self.src, vm);
+    let (left, right) = promote_numeric(vm.ca.clone(), right);
 
-    match (&left, &right) {
-        (AoType::AoBool(left), AoType::AoBool(right)) => {
-            vm.ca = AoType::AoBool(left <= right);
-        }
-        (AoType::AoInt(left), AoType::AoInt(right)) => {
-            vm.ca = AoType::AoBool(left <= right);
+    if !ordered(&left, &right) {
+        return AoStatus::InvalidOperation(format!("{} <= {}", left, right));
+    }
+    vm.ca = AoType::AoBool(left <= right);
+});
+
+// Set CA to whether it equals any value of the set.
+#[derive(Clone, Default)]
+pub struct Inset {
+    pub set: Vec<AoType>,
+}
+impl Display for Inset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "inset")?;
+        for (i, value) in self.set.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { "," }, value)?;
         }
-        (AoType::AoFloat(left), AoType::AoFloat(right)) => {
-            vm.ca = AoType::AoBool(left <= right);
+        Ok(())
+    }
+}
+impl_ao_opcode!(Inset, (&self, vm) {
+    vm.ca = AoType::AoBool(self.set.contains(&vm.ca));
+});
+impl Serializable for Inset {
+    fn get_id(&self) -> u8 {
+        0x56
+    }
+    fn get_args(&self) -> OpcodeArgType {
+        OpcodeArgType::AoTypes(self.set.clone())
+    }
+    fn set_args(&mut self, args: OpcodeArgType) {
+        if let OpcodeArgType::AoTypes(set) = args {
+            self.set = set;
         }
-        (AoType::AoString(left), AoType::AoString(right)) => {
-            vm.ca = AoType::AoBool(left <= right);
+    }
+}
+
+// Replace CA with its conversion, see `AoType::coerce_to`.
+fn coerce_ca(vm: &mut AoVM, target: AoTypeTag) -> AoStatus {
+    match vm.ca.coerce_to(target) {
+        Ok(value) => {
+            vm.ca = value;
+            AoStatus::Ok
         }
-        _ => return AoStatus::InvalidOperation(format!("{} <= {}", left, right)),
+        Err(status) => status,
     }
-});
+}
 
 opcode!(Csi, 0x61, "csi", (&self, vm) {
-    match &vm.ca {
-        AoType::AoBool(b) => vm.ca = AoType::AoInt(if *b { 1 } else { 0 }),
-        AoType::AoInt(_) => (),
-        AoType::AoFloat(f) => vm.ca = AoType::AoInt(*f as i32),
-        AoType::AoPtr(p) => vm.ca = AoType::AoInt(*p as i32),
-        AoType::AoString(s) => vm.ca = AoType::AoInt(s.parse::<i32>().unwrap_or(0)),
+    return coerce_ca(vm, AoTypeTag::Int);
+});
+
+// Same as `csi`: floats are truncated, NaN becomes 0 and values out of range
+// saturate to the i32 bounds.
+opcode!(CsiSat, 0x60, "csisat", (&self, vm) {
+    match vm.ca {
+        AoType::AoFloat(f) => vm.ca = AoType::AoInt(f as i32),
+        _ => return Csi.execute(vm),
     }
 });
 
-opcode!(Csf, 0x62, "csf", (&self, vm) {
-    match &vm.ca {
-        AoType::AoBool(b) => vm.ca = AoType::AoFloat(if *b { 1.0 } else { 0.0 }),
-        AoType::AoInt(i) => vm.ca = AoType::AoFloat(*i as f32),
-        AoType::AoFloat(_) => (),
-        AoType::AoPtr(p) => vm.ca = AoType::AoFloat(*p as f32),
-        AoType::AoString(s) => vm.ca = AoType::AoFloat(s.parse::<f32>().unwrap_or(0.0)),
+// Same as `csi`, but floats that are NaN, infinite or out of the i32 range
+// fail instead of saturating.
+opcode!(CsiTrap, 0x67, "csitrap", (&self, vm) {
+    match vm.ca {
+        AoType::AoFloat(f) if f.is_nan() || f < i32::MIN as f32 || f >= 2147483648.0 => {
+            return AoStatus::ConversionError(format!("{} to int", vm.ca));
+        }
+        AoType::AoFloat(f) => vm.ca = AoType::AoInt(f as i32),
+        _ => return Csi.execute(vm),
     }
 });
 
+opcode!(Csf, 0x62, "csf", (&self, vm) {
+    return coerce_ca(vm, AoTypeTag::Float);
+});
+
 opcode!(Csp, 0x63, "csp", (&self, vm) {
+    return coerce_ca(vm, AoTypeTag::Ptr);
+});
+
+opcode!(Css, 0x64, "css", (&self, vm) {
+    return coerce_ca(vm, AoTypeTag::String);
+});
+
+// Code point of the single character string in CA.
+opcode!(Scode, 0x65, "scode", (&self, vm) {
+    let mut chars = match &vm.ca {
+        AoType::AoString(s) => s.chars(),
+        _ => return AoStatus::InvalidOperation(format!("scode {}", vm.ca)),
+    };
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => vm.ca = AoType::AoInt(c as i32),
+        _ => return AoStatus::InvalidOperation(format!("scode {}", vm.ca)),
+    }
+});
+
+// Single character string from the code point in CA.
+opcode!(Cchar, 0x66, "cchar", (&self, vm) {
+    let c = match vm.ca {
+        AoType::AoInt(i) => u32::try_from(i).ok().and_then(char::from_u32),
+        _ => None,
+    };
+    match c {
+        Some(c) => vm.ca = AoType::from(c.to_string()),
+        None => return AoStatus::InvalidOperation(format!("cchar {}", vm.ca)),
+    }
+});
+
+// Type tag of CA, see `AoType::type_tag`.
+opcode!(Type, 0x6F, "type", (&self, vm) {
+    vm.ca = AoType::AoInt(vm.ca.type_tag() as i32);
+});
+
+// Parse the string in CA as an integer, failing if it is not one.
+opcode!(Ctoi, 0x6D, "ctoi", (&self, vm) {
     match &vm.ca {
-        AoType::AoBool(b) => vm.ca = AoType::AoPtr(if *b { 1 } else { 0 }),
-        AoType::AoInt(i) => vm.ca = AoType::AoPtr(*i as u32),
-        AoType::AoFloat(f) => vm.ca = AoType::AoPtr(*f as u32),
-        AoType::AoPtr(_) => (),
-        AoType::AoString(s) => vm.ca = AoType::AoPtr(s.parse::<u32>().unwrap_or(0)),
+        AoType::AoString(s) => match s.parse::<i32>() {
+            Ok(i) => vm.ca = AoType::AoInt(i),
+            Err(_) => return AoStatus::ConversionError(format!("{} to int", vm.ca)),
+        },
+        _ => return AoStatus::InvalidOperation(format!("ctoi {}", vm.ca)),
     }
 });
 
-opcode!(Css, 0x64, "css", (&self, vm) {
+// Parse the string in CA as a float, failing if it is not one.
+opcode!(Ctof, 0x6E, "ctof", (&self, vm) {
     match &vm.ca {
-        AoType::AoBool(b) => vm.ca = AoType::AoString(if *b {
-            "true".to_string()
-        } else {
-            "false".to_string()
-        }),
-        AoType::AoInt(i) => vm.ca = AoType::AoString(i.to_string()),
-        AoType::AoFloat(f) => vm.ca = AoType::AoString(f.to_string()),
-        AoType::AoPtr(p) => vm.ca = AoType::AoString(p.to_string()),
-        AoType::AoString(_) => (),
+        AoType::AoString(s) => match s.parse::<f32>() {
+            Ok(f) => vm.ca = AoType::AoFloat(f),
+            Err(_) => return AoStatus::ConversionError(format!("{} to float", vm.ca)),
+        },
+        _ => return AoStatus::InvalidOperation(format!("ctof {}", vm.ca)),
     }
 });
 
@@ -657,16 +1093,289 @@ opcode!(Iss, 0x6C, "iss", (&self, vm) {
 });
 
 opcode!(Arg, 0x70, "arg {}", u32 offset, (&self, vm) {
-    vm.dp = vm.dsb + self.offset;
+    vm.dp = vm.dsb.wrapping_add(self.offset);
 });
 
 opcode!(Cnf, 0x71, "cnf {}", u32 argc, (&self, vm) {
+    if self.argc > vm.ds.len() as u32 {
+        return AoStatus::BadDataStack;
+    }
     vm.dsb = vm.ds.len() as u32 - self.argc;
 });
 
+// Load the first two arguments of the current frame into CA and CB.
+opcode!(Args2, 0x72, "args2", (&self, vm) {
+    let dsb = vm.dsb as usize;
+    if dsb + 1 >= vm.ds.len() {
+        return AoStatus::DataStackUnderflow;
+    }
+    vm.ca = vm.ds[dsb].clone();
+    vm.cb = vm.ds[dsb + 1].clone();
+});
+
+// Drop everything on the data stack from the frame base up.
+opcode!(Clrf, 0x73, "clrf", (&self, vm) {
+    vm.ds.truncate(vm.dsb as usize);
+});
+
+opcode!(Sizeof, 0xB0, "sizeof", (&self, vm) {
+    if vm.ca.is_too_deep() {
+        return AoStatus::InvalidOperation(format!("sizeof {}", vm.ca));
+    }
+    vm.ca = AoType::AoInt(AoAsmSerializer::serialize_type(&vm.ca).len() as i32);
+});
+
+// Index popped for `aget` and `aset`, checked against the array length.
+fn array_index(index: &AoType, len: usize) -> Option<usize> {
+    mem_operand(index)
+        .map(|index| index as usize)
+        .filter(|index| *index < len)
+}
+
+// Replace the int in CA with an array of that many zeros, failing with
+// `OutOfMemory` past the VM's array length limit.
+opcode!(Newarr, 0xC0, "newarr", (&self, vm) {
+    match vm.ca {
+        AoType::AoInt(len) if len >= 0 => {
+            if len as usize > vm.max_array_len {
+                return AoStatus::OutOfMemory;
+            }
+            vm.ca = AoType::AoArray(Rc::new(RefCell::new(vec![AoType::default(); len as usize])));
+        }
+        _ => return AoStatus::InvalidOperation(format!("newarr {}", vm.ca)),
+    }
+});
+
+// Pop an index and load that element of the array in CA into CA.
+opcode!(Aget, 0xC1, "aget", (&self, vm) {
+    let array = match &vm.ca {
+        AoType::AoArray(array) => array.clone(),
+        _ => return AoStatus::InvalidOperation(format!("aget {}", vm.ca)),
+    };
+    let index = match vm.pop() {
+        Some(index) => index,
+        None => return AoStatus::DataStackUnderflow,
+    };
+    let value = match array_index(&index, array.borrow().len()) {
+        Some(index) => array.borrow()[index].clone(),
+        None => return AoStatus::BadDataAccess,
+    };
+    vm.ca = value;
+});
+
+// Pop a value and an index, then store the value in the array in CA. Values
+// containing the array, or nesting too deep, are refused.
+opcode!(Aset, 0xC2, "aset", (&self, vm) {
+    let array = match &vm.ca {
+        AoType::AoArray(array) => array.clone(),
+        _ => return AoStatus::InvalidOperation(format!("aset {}", vm.ca)),
+    };
+    let len = vm.ds.len();
+    if len < 2 {
+        return AoStatus::DataStackUnderflow;
+    }
+    // check before popping, so that a refused store leaves the stack as it was
+    if !vm.ca.can_hold(&vm.ds[len - 1]) {
+        return AoStatus::InvalidOperation(format!("aset {}", vm.ds[len - 1]));
+    }
+    let index = match array_index(&vm.ds[len - 2], array.borrow().len()) {
+        Some(index) => index,
+        None => return AoStatus::BadDataAccess,
+    };
+    let value = vm.pop().unwrap();
+    vm.pop();
+    array.borrow_mut()[index] = value;
+});
+
+// Replace the array in CA with its length.
+opcode!(Alen, 0xC3, "alen", (&self, vm) {
+    match &vm.ca {
+        AoType::AoArray(array) => {
+            let len = array.borrow().len();
+            vm.ca = AoType::AoInt(len as i32);
+        }
+        _ => return AoStatus::InvalidOperation(format!("alen {}", vm.ca)),
+    }
+});
+
+// Replace CA with a one-element array holding it, failing if that would nest
+// too deep.
+opcode!(Wrap, 0xC9, "wrap", (&self, vm) {
+    let array = Rc::new(RefCell::new(Vec::with_capacity(1)));
+    let wrapped = AoType::AoArray(array.clone());
+    if !wrapped.can_hold(&vm.ca) {
+        return AoStatus::InvalidOperation(format!("wrap {}", vm.ca));
+    }
+    array.borrow_mut().push(std::mem::take(&mut vm.ca));
+    vm.ca = wrapped;
+});
+
+// Key popped for the map opcodes, failing for values that cannot be keys.
+fn map_key(vm: &mut AoVM, name: &str) -> Result<AoMapKey, AoStatus> {
+    let key = vm.pop().ok_or(AoStatus::DataStackUnderflow)?;
+    AoMapKey::new(&key).ok_or_else(|| AoStatus::InvalidOperation(format!("{} {}", name, key)))
+}
+
+// Map in CA, or fail with the opcode name.
+fn map_in_ca(vm: &AoVM, name: &str) -> Result<Rc<RefCell<BTreeMap<AoMapKey, AoType>>>, AoStatus> {
+    match &vm.ca {
+        AoType::AoMap(map) => Ok(map.clone()),
+        _ => Err(AoStatus::InvalidOperation(format!("{} {}", name, vm.ca))),
+    }
+}
+
+// Replace CA with an empty map.
+opcode!(Newmap, 0xC4, "newmap", (&self, vm) {
+    vm.ca = AoType::AoMap(Rc::new(RefCell::new(BTreeMap::new())));
+});
+
+// Pop a key and load its value from the map in CA into CA.
+opcode!(Mget, 0xC5, "mget", (&self, vm) {
+    let result = map_in_ca(vm, "mget").and_then(|map| {
+        let key = map_key(vm, "mget")?;
+        let value = map.borrow().get(&key).cloned();
+        value.ok_or(AoStatus::BadDataAccess)
+    });
+    match result {
+        Ok(value) => vm.ca = value,
+        Err(status) => return status,
+    }
+});
+
+// Pop a value and a key, then store the value in the map in CA. Values
+// containing the map, or nesting too deep, are refused.
+opcode!(Mset, 0xC6, "mset", (&self, vm) {
+    let map = match map_in_ca(vm, "mset") {
+        Ok(map) => map,
+        Err(status) => return status,
+    };
+    let len = vm.ds.len();
+    if len < 2 {
+        return AoStatus::DataStackUnderflow;
+    }
+    // check before popping, so that a refused store leaves the stack as it was
+    if !vm.ca.can_hold(&vm.ds[len - 1]) {
+        return AoStatus::InvalidOperation(format!("mset {}", vm.ds[len - 1]));
+    }
+    let key = match AoMapKey::new(&vm.ds[len - 2]) {
+        Some(key) => key,
+        None => return AoStatus::InvalidOperation(format!("mset {}", vm.ds[len - 2])),
+    };
+    let value = vm.pop().unwrap();
+    vm.pop();
+    map.borrow_mut().insert(key, value);
+});
+
+// Pop a key and replace the map in CA with whether it contains the key.
+opcode!(Mhas, 0xC7, "mhas", (&self, vm) {
+    let result = map_in_ca(vm, "mhas").and_then(|map| {
+        let key = map_key(vm, "mhas")?;
+        let found = map.borrow().contains_key(&key);
+        Ok(found)
+    });
+    match result {
+        Ok(found) => vm.ca = AoType::AoBool(found),
+        Err(status) => return status,
+    }
+});
+
+// Replace the map in CA with its number of entries.
+opcode!(Mlen, 0xC8, "mlen", (&self, vm) {
+    match map_in_ca(vm, "mlen") {
+        Ok(map) => vm.ca = AoType::AoInt(map.borrow().len() as i32),
+        Err(status) => return status,
+    }
+});
+
+// Text of a value as `css` converts it.
+fn css_text(value: &AoType) -> Rc<str> {
+    match value.coerce_to(AoTypeTag::String) {
+        Ok(AoType::AoString(s)) => s,
+        _ => Rc::from(value.to_string()),
+    }
+}
+
+// Pop `count` values and then a template, and replace each `{}` in the
+// template with the text of the next value, as `css` converts it.
+opcode!(Format, 0xB1, "format {}", u32 count, (&self, vm) {
+    let count = self.count as usize;
+    if vm.ds.len() <= count {
+        return AoStatus::DataStackUnderflow;
+    }
+    let values = vm.ds.split_off(vm.ds.len() - count);
+    let template = vm.pop().unwrap();
+    let parts: Vec<&str> = match &template {
+        AoType::AoString(s) => s.split("{}").collect(),
+        _ => return AoStatus::InvalidOperation(format!("format {}", template)),
+    };
+    if parts.len() - 1 != count {
+        return AoStatus::InvalidOperation(format!("format {} with {} values", template, count));
+    }
+
+    let texts: Vec<Rc<str>> = values.iter().map(css_text).collect();
+    let len = parts.iter().map(|part| part.len()).sum::<usize>()
+        + texts.iter().map(|text| text.len()).sum::<usize>();
+    if len > MAX_STRING_LEN {
+        return AoStatus::InvalidOperation(format!("format {} over {} bytes", count, MAX_STRING_LEN));
+    }
+    let mut result = String::with_capacity(len);
+    result.push_str(parts[0]);
+    for (text, part) in texts.iter().zip(&parts[1..]) {
+        result.push_str(text);
+        result.push_str(part);
+    }
+    vm.ca = AoType::from(result);
+});
+
+// Pop `count` values and push their texts joined in order, as `css` converts
+// them.
+opcode!(Strcat, 0xB2, "strcat {}", u32 count, (&self, vm) {
+    let count = self.count as usize;
+    if vm.ds.len() < count {
+        return AoStatus::DataStackUnderflow;
+    }
+    let texts: Vec<Rc<str>> = vm.ds.drain(vm.ds.len() - count..).map(|v| css_text(&v)).collect();
+    let len = texts.iter().map(|text| text.len()).sum();
+    if len > MAX_STRING_LEN {
+        return AoStatus::InvalidOperation(format!("strcat {} over {} bytes", count, MAX_STRING_LEN));
+    }
+    let mut result = String::with_capacity(len);
+    for text in texts {
+        result.push_str(&text);
+    }
+    if !vm.push(AoType::from(result)) {
+        return AoStatus::DataStackOverflow(vm.max_stack);
+    }
+});
+
+// Reverse the characters of the string in CA.
+opcode!(Rev, 0xB3, "rev", (&self, vm) {
+    match &vm.ca {
+        AoType::AoString(s) => vm.ca = AoType::from(s.chars().rev().collect::<String>()),
+        _ => return AoStatus::InvalidOperation(format!("rev {}", vm.ca)),
+    }
+});
+
+// Next value of the VM's random number generator as a non-negative int.
+opcode!(Rand, 0xB4, "rand", (&self, vm) {
+    vm.ca = AoType::AoInt((vm.next_random() >> 33) as i32);
+});
+
+// Next value of the VM's random number generator as a float in [0, 1).
+opcode!(RandF, 0xB5, "randf", (&self, vm) {
+    vm.ca = AoType::AoFloat((vm.next_random() >> 40) as f32 / (1u64 << 24) as f32);
+});
+
+// Number of opcodes executed since the last reset, including this one,
+// saturating at the largest int.
+opcode!(Steps, 0xB6, "steps", (&self, vm) {
+    vm.ca = AoType::AoInt(i32::try_from(vm.steps).unwrap_or(i32::MAX));
+});
+
 pub fn create_opcode_by_id(id: u8) -> Option<Box<dyn AoOpcode>> {
     match id {
         0x00 => Some(Box::new(Nop)),
+        0x01 => Some(Box::new(NopN { pad: 0 })),
 
         0x10 => Some(Box::new(Call { addr: 0 })),
         0x11 => Some(Box::new(Ret)),
@@ -676,6 +1385,14 @@ pub fn create_opcode_by_id(id: u8) -> Option<Box<dyn AoOpcode>> {
         0x15 => Some(Box::new(Jta { addr: 0 })),
         0x16 => Some(Box::new(Jf { addr: 0 })),
         0x17 => Some(Box::new(Jfa { addr: 0 })),
+        0x18 => Some(Box::new(Jmpr)),
+        0x19 => Some(Box::new(Throw)),
+        0x1A => Some(Box::new(Jbound { len: 0, addr: 0 })),
+        0x1B => Some(Box::new(Assert { message: None })),
+        0x1C => Some(Box::new(Halt { with_ca: false })),
+        0x1D => Some(Box::new(JmpTable::default())),
+        0x1E => Some(Box::new(RetV)),
+        0x1F => Some(Box::new(Depth)),
 
         0x20 => Some(Box::new(Mov {
             src: AoArg::CA,
@@ -685,6 +1402,13 @@ pub fn create_opcode_by_id(id: u8) -> Option<Box<dyn AoOpcode>> {
         0x22 => Some(Box::new(Push { src: AoArg::CA })),
         0x23 => Some(Box::new(Pop { to_ca: false })),
         0x24 => Some(Box::new(Popn { count: 0 })),
+        0x25 => Some(Box::new(Cswap { i: 0, j: 0 })),
+        0x26 => Some(Box::new(Memcpy)),
+        0x27 => Some(Box::new(Memset)),
+        0x28 => Some(Box::new(Swab)),
+        0x29 => Some(Box::new(Peek { offset: 0 })),
+        0x2A => Some(Box::new(Pick { n: 0 })),
+        0x2B => Some(Box::new(Roll { n: 0 })),
 
         0x30 => Some(Box::new(Add { src: AoArg::CA })),
         0x31 => Some(Box::new(Sub { src: AoArg::CA })),
@@ -695,6 +1419,9 @@ pub fn create_opcode_by_id(id: u8) -> Option<Box<dyn AoOpcode>> {
         0x36 => Some(Box::new(Dec)),
         0x37 => Some(Box::new(Shl { src: AoArg::CA })),
         0x38 => Some(Box::new(Shr { src: AoArg::CA })),
+        0x3C => Some(Box::new(AddC { src: AoArg::CA })),
+        0x3D => Some(Box::new(SubC { src: AoArg::CA })),
+        0x3E => Some(Box::new(MulC { src: AoArg::CA })),
 
         0x40 => Some(Box::new(And { src: AoArg::CA })),
         0x41 => Some(Box::new(Or { src: AoArg::CA })),
@@ -704,6 +1431,7 @@ pub fn create_opcode_by_id(id: u8) -> Option<Box<dyn AoOpcode>> {
         0x45 => Some(Box::new(Bor { src: AoArg::CA })),
         0x46 => Some(Box::new(Bxor { src: AoArg::CA })),
         0x47 => Some(Box::new(Bnot)),
+        0x48 => Some(Box::new(Truthy)),
 
         0x50 => Some(Box::new(Equ { src: AoArg::CA })),
         0x51 => Some(Box::new(Neq { src: AoArg::CA })),
@@ -711,20 +1439,1434 @@ pub fn create_opcode_by_id(id: u8) -> Option<Box<dyn AoOpcode>> {
         0x53 => Some(Box::new(Lt { src: AoArg::CA })),
         0x54 => Some(Box::new(Ge { src: AoArg::CA })),
         0x55 => Some(Box::new(Le { src: AoArg::CA })),
+        0x56 => Some(Box::new(Inset { set: Vec::new() })),
+        0x57 => Some(Box::new(EquI::default())),
 
+        0x60 => Some(Box::new(CsiSat)),
         0x61 => Some(Box::new(Csi)),
         0x62 => Some(Box::new(Csf)),
         0x63 => Some(Box::new(Csp)),
         0x64 => Some(Box::new(Css)),
+        0x65 => Some(Box::new(Scode)),
+        0x66 => Some(Box::new(Cchar)),
+        0x67 => Some(Box::new(CsiTrap)),
         0x68 => Some(Box::new(Isb)),
         0x69 => Some(Box::new(Isi)),
         0x6A => Some(Box::new(Isf)),
         0x6B => Some(Box::new(Isp)),
         0x6C => Some(Box::new(Iss)),
+        0x6D => Some(Box::new(Ctoi)),
+        0x6E => Some(Box::new(Ctof)),
+        0x6F => Some(Box::new(Type)),
 
         0x70 => Some(Box::new(Arg { offset: 0 })),
         0x71 => Some(Box::new(Cnf { argc: 0 })),
+        0x72 => Some(Box::new(Args2)),
+        0x73 => Some(Box::new(Clrf)),
+
+        0xB0 => Some(Box::new(Sizeof)),
+        0xB1 => Some(Box::new(Format { count: 0 })),
+        0xB2 => Some(Box::new(Strcat { count: 0 })),
+        0xB3 => Some(Box::new(Rev)),
+        0xB4 => Some(Box::new(Rand)),
+        0xB5 => Some(Box::new(RandF)),
+        0xB6 => Some(Box::new(Steps)),
+
+        0xC0 => Some(Box::new(Newarr)),
+        0xC1 => Some(Box::new(Aget)),
+        0xC2 => Some(Box::new(Aset)),
+        0xC3 => Some(Box::new(Alen)),
+        0xC4 => Some(Box::new(Newmap)),
+        0xC5 => Some(Box::new(Mget)),
+        0xC6 => Some(Box::new(Mset)),
+        0xC7 => Some(Box::new(Mhas)),
+        0xC8 => Some(Box::new(Mlen)),
+        0xC9 => Some(Box::new(Wrap)),
 
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::*;
+
+    #[test]
+    fn test_jmpr() {
+        let program = ao_program![
+            /*  0 */ mov mp,0
+            /*  1 */ mov mem,10u32
+            /*  2 */ mov mp,1
+            /*  3 */ mov mem,12u32
+            /*  4 */ arg 0
+            /*  5 */ mov ca,ds
+            /*  6 */ csp
+            /*  7 */ mov mp,ca
+            /*  8 */ mov ca,mem
+            /*  9 */ jmpr
+            /* 10 */ mov cb,"first"
+            /* 11 */ jmpa 13
+            /* 12 */ mov cb,"second"
+        ];
+
+        for (index, expected) in [(0, "first"), (1, "second")] {
+            let mut vm = AoVM::default();
+            vm.push(AoType::AoInt(index));
+            assert_eq!(vm.run(&program), AoStatus::Exit);
+            assert_eq!(vm.cb, AoType::from(expected));
+        }
+
+        let mut vm = AoVM {
+            ca: AoType::AoInt(3),
+            ..AoVM::default()
+        };
+        assert_eq!(
+            vm.run(&ao_program![jmpr]),
+            AoStatus::InvalidOperation("jmpr 3".to_string())
+        );
+    }
+
+    #[test]
+    fn test_const_addr() {
+        const ENTRY: u32 = 5;
+        const END: u32 = 6;
+
+        let program = ao_program![
+            push dsb
+            cnf 0
+            call ENTRY
+            mov cb,ca
+            int 0
+            mov ca,42
+            ret
+        ];
+        let mut vm = AoVM::default();
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.cb, AoType::AoInt(42));
+
+        let program = ao_program![
+            mov ca,0
+            jfa (END - 3)
+            mov cb,1
+            jta END
+            jmpa END
+            mov cb,2
+        ];
+        let mut vm = AoVM::default();
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.cb, AoType::default());
+        assert_eq!(vm.pc, END);
+    }
+
+    #[test]
+    fn test_jmptable() {
+        let program = ao_program![
+            /* 0 */ jmptable [3, 5, 7]
+            /* 1 */ mov cb,"default"
+            /* 2 */ int 0
+            /* 3 */ mov cb,"zero"
+            /* 4 */ int 0
+            /* 5 */ mov cb,"one"
+            /* 6 */ int 0
+            /* 7 */ mov cb,"two"
+        ];
+        assert_eq!(program[0].to_string(), "jmptable 3,5,7");
+
+        for (index, expected) in [
+            (0, "zero"),
+            (1, "one"),
+            (2, "two"),
+            (3, "default"),
+            (-1, "default"),
+        ] {
+            let mut vm = AoVM {
+                ca: AoType::AoInt(index),
+                ..AoVM::default()
+            };
+            assert_eq!(vm.run(&program), AoStatus::Exit);
+            assert_eq!(vm.cb, AoType::from(expected));
+        }
+
+        let mut vm = AoVM {
+            ca: AoType::AoFloat(1.0),
+            ..AoVM::default()
+        };
+        assert_eq!(
+            vm.run(&program),
+            AoStatus::InvalidOperation("jmptable 1f".to_string())
+        );
+
+        let bin = AoAsmSerializer::serialize(&program);
+        assert_eq!(&bin[6..19], &[0x1D, 3, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0]);
+        let parsed = AoAsmSerializer::deserialize(&bin).unwrap();
+        assert_eq!(
+            AoAsmSerializer::disassemble(&parsed),
+            AoAsmSerializer::disassemble(&program)
+        );
+    }
+
+    #[test]
+    fn test_retv() {
+        let program = ao_program![
+            /*  0 */ push dsb
+            /*  1 */ push 3
+            /*  2 */ push 4
+            /*  3 */ cnf 2
+            /*  4 */ call 7
+            /*  5 */ mov cb,ca
+            /*  6 */ int 0
+            // sum(a, b)
+            /*  7 */ arg 0
+            /*  8 */ mov ca,ds
+            /*  9 */ arg 1
+            /* 10 */ add ds
+            /* 11 */ push ca
+            /* 12 */ mov ca,0
+            /* 13 */ retv
+        ];
+        let mut vm = AoVM::default();
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.cb, AoType::AoInt(7));
+        assert!(vm.ds.is_empty());
+        assert!(vm.cs.is_empty());
+
+        vm.reset();
+        assert_eq!(vm.run(&ao_program![retv]), AoStatus::CallStackUnderflow);
+        vm.reset();
+        let program = ao_program![
+            push dsb
+            cnf 0
+            call 3
+            retv
+        ];
+        assert_eq!(vm.run(&program), AoStatus::DataStackUnderflow);
+    }
+
+    #[test]
+    fn test_depth() {
+        let program = ao_program![
+            /*  0 */ mov cb,0
+            /*  1 */ push dsb
+            /*  2 */ cnf 0
+            /*  3 */ call 5
+            /*  4 */ int 0
+            // recurse until cb reaches 4
+            /*  5 */ depth
+            /*  6 */ mov ca,cb
+            /*  7 */ inc
+            /*  8 */ mov cb,ca
+            /*  9 */ lt 4
+            /* 10 */ jf 4
+            /* 11 */ push dsb
+            /* 12 */ cnf 0
+            /* 13 */ call 5
+            /* 14 */ ret
+        ];
+        let breakpoints = std::collections::HashSet::from([6]);
+        let mut vm = AoVM::default();
+        let mut depths = Vec::new();
+        loop {
+            match vm.run_until_breakpoint(&program, &breakpoints) {
+                AoStatus::Breakpoint(6) => depths.push(vm.ca.clone()),
+                AoStatus::Exit => break,
+                status => panic!("unexpected {}", status),
+            }
+        }
+        assert_eq!(depths, (1..=4).map(AoType::AoInt).collect::<Vec<_>>());
+        assert!(vm.cs.is_empty());
+
+        vm.reset();
+        assert_eq!(vm.run(&ao_program![depth]), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoInt(0));
+    }
+
+    #[test]
+    fn test_throw() {
+        let mut vm = AoVM::default();
+        let program = ao_program![
+            push 7
+            push "something went wrong"
+            throw
+            push 0
+        ];
+        assert_eq!(
+            vm.run(&program),
+            AoStatus::ScriptError {
+                code: 7,
+                message: "something went wrong".to_string()
+            }
+        );
+        assert!(vm.ds.is_empty());
+    }
+
+    #[test]
+    fn test_cswap() {
+        let mut vm = AoVM::default();
+        vm.push(AoType::AoInt(5));
+        vm.push(AoType::AoInt(2));
+        vm.push(AoType::AoInt(9));
+
+        assert_eq!(vm.run(&ao_program![cswap 0,1]), AoStatus::Exit);
+        assert_eq!(vm.stack_as_ints(), Ok(vec![2, 5, 9]));
+
+        vm.pc = 0;
+        assert_eq!(vm.run(&ao_program![cswap 1,2]), AoStatus::Exit);
+        assert_eq!(vm.stack_as_ints(), Ok(vec![2, 5, 9]));
+
+        vm.pc = 0;
+        assert_eq!(
+            vm.run(&ao_program![cswap 1,3]),
+            AoStatus::DataStackUnderflow
+        );
+
+        vm.reset();
+        vm.push(AoType::AoFloat(2.5));
+        vm.push(AoType::AoInt(2));
+        vm.push(AoType::AoPtr(7));
+        vm.push(AoType::AoPtr(3));
+        assert_eq!(vm.run(&ao_program![cswap 0,1 cswap 2,3]), AoStatus::Exit);
+        assert_eq!(
+            vm.ds,
+            vec![
+                AoType::AoInt(2),
+                AoType::AoFloat(2.5),
+                AoType::AoPtr(3),
+                AoType::AoPtr(7),
+            ]
+        );
+
+        vm.reset();
+        vm.push(AoType::AoInt(1));
+        vm.push(AoType::from("1"));
+        assert_eq!(
+            vm.run(&ao_program![cswap 0,1]),
+            AoStatus::InvalidOperation("1 > \"1\"".to_string())
+        );
+    }
+
+    #[test]
+    fn test_jbound() {
+        let program = ao_program![
+            jbound 3,3
+            mov cb,"out of bounds"
+            jmp 2
+            mov cb,"in bounds"
+        ];
+
+        for (index, expected) in [
+            (AoType::AoInt(0), "in bounds"),
+            (AoType::AoInt(2), "in bounds"),
+            (AoType::AoPtr(1), "in bounds"),
+            (AoType::AoInt(3), "out of bounds"),
+            (AoType::AoInt(-1), "out of bounds"),
+        ] {
+            let mut vm = AoVM {
+                ca: index,
+                ..AoVM::default()
+            };
+            assert_eq!(vm.run(&program), AoStatus::Exit);
+            assert_eq!(vm.cb, AoType::from(expected));
+        }
+
+        let mut vm = AoVM {
+            ca: AoType::from("0"),
+            ..AoVM::default()
+        };
+        assert_eq!(
+            vm.run(&program),
+            AoStatus::InvalidOperation("jbound \"0\"".to_string())
+        );
+    }
+
+    #[test]
+    fn test_sizeof() {
+        let mut vm = AoVM::default();
+        let program = ao_program![sizeof];
+
+        for (value, size) in [
+            (AoType::AoBool(true), 2),
+            (AoType::AoInt(7), 5),
+            (AoType::from("abc"), 8),
+        ] {
+            vm.reset();
+            vm.ca = value;
+            assert_eq!(vm.run(&program), AoStatus::Exit);
+            assert_eq!(vm.ca, AoType::AoInt(size));
+        }
+
+        // arrays nested too deep to serialize
+        let mut deep = AoType::AoInt(0);
+        for _ in 0..=MAX_NESTING_DEPTH {
+            deep = AoType::AoArray(Rc::new(RefCell::new(vec![deep])));
+        }
+        vm.reset();
+        vm.ca = deep;
+        assert!(matches!(vm.run(&program), AoStatus::InvalidOperation(_)));
+    }
+
+    #[test]
+    fn test_scode_cchar() {
+        let mut vm = AoVM::default();
+        let program = ao_program![
+            mov ca,"A"
+            scode
+            push ca
+            cchar
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.pop(), Some(AoType::AoInt(65)));
+        assert_eq!(vm.ca, AoType::from("A"));
+
+        vm.reset();
+        let program = ao_program![mov ca,0x110000 cchar];
+        assert_eq!(
+            vm.run(&program),
+            AoStatus::InvalidOperation("cchar 1114112".to_string())
+        );
+
+        vm.reset();
+        let program = ao_program![mov ca,"AB" scode];
+        assert_eq!(
+            vm.run(&program),
+            AoStatus::InvalidOperation("scode \"AB\"".to_string())
+        );
+    }
+
+    #[test]
+    fn test_csisat_csitrap() {
+        let cases = [
+            (
+                1e30_f32,
+                i32::MAX,
+                Some("1000000000000000000000000000000f to int"),
+            ),
+            (f32::NAN, 0, Some("NaNf to int")),
+            (
+                -1e30_f32,
+                i32::MIN,
+                Some("-1000000000000000000000000000000f to int"),
+            ),
+            (f32::INFINITY, i32::MAX, Some("inff to int")),
+            (-2147483648.0, i32::MIN, None),
+            (-7.9, -7, None),
+        ];
+        let mut vm = AoVM::default();
+        for (value, saturated, error) in cases {
+            vm.reset();
+            vm.ca = AoType::AoFloat(value);
+            assert_eq!(vm.run(&ao_program![csisat]), AoStatus::Exit);
+            assert_eq!(vm.ca, AoType::AoInt(saturated));
+
+            vm.reset();
+            vm.ca = AoType::AoFloat(value);
+            match error {
+                Some(message) => assert_eq!(
+                    vm.run(&ao_program![csitrap]),
+                    AoStatus::ConversionError(message.to_string())
+                ),
+                None => {
+                    assert_eq!(vm.run(&ao_program![csitrap]), AoStatus::Exit);
+                    assert_eq!(vm.ca, AoType::AoInt(saturated));
+                }
+            }
+        }
+
+        vm.reset();
+        let program = ao_program![
+            mov ca,"12"
+            csitrap
+            push ca
+            mov ca,"12"
+            csisat
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ds, vec![AoType::AoInt(12)]);
+        assert_eq!(vm.ca, AoType::AoInt(12));
+    }
+
+    #[test]
+    fn test_type() {
+        let mut vm = AoVM::default();
+        let program = ao_program![
+            mov ca,"Aoi"
+            type
+            push ca
+            mov ca,1.5
+            type
+            push ca
+            mov ca,dst
+            type
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ds, vec![AoType::AoInt(5), AoType::AoInt(3)]);
+        assert_eq!(vm.ca, AoType::AoInt(4));
+    }
+
+    #[test]
+    #[allow(clippy::approx_constant)]
+    fn test_ctoi_ctof() {
+        let mut vm = AoVM::default();
+        let program = ao_program![
+            mov ca,"42"
+            ctoi
+            push ca
+            mov ca,"3.14"
+            ctof
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.pop(), Some(AoType::AoInt(42)));
+        assert_eq!(vm.ca, AoType::AoFloat(3.14));
+
+        for (program, status) in [
+            (
+                ao_program![mov ca,"abc" ctoi],
+                AoStatus::ConversionError("\"abc\" to int".to_string()),
+            ),
+            (
+                ao_program![mov ca,"3.14" ctoi],
+                AoStatus::ConversionError("\"3.14\" to int".to_string()),
+            ),
+            (
+                ao_program![mov ca,"abc" ctof],
+                AoStatus::ConversionError("\"abc\" to float".to_string()),
+            ),
+            (
+                ao_program![mov ca,1 ctof],
+                AoStatus::InvalidOperation("ctof 1".to_string()),
+            ),
+        ] {
+            vm.reset();
+            assert_eq!(vm.run(&program), status);
+        }
+        // the lenient casts still fall back to zero
+        vm.reset();
+        assert_eq!(vm.run(&ao_program![mov ca,"abc" csi]), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoInt(0));
+    }
+
+    #[test]
+    fn test_args2() {
+        let mut vm = AoVM::default();
+        let program = ao_program![
+            push dsb
+            push 10
+            push 3
+            cnf 2
+            call 6
+            jmpa 9
+            args2
+            sub cb
+            ret
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoInt(7));
+        assert!(vm.ds.is_empty());
+
+        vm.reset();
+        let program = ao_program![push 1 cnf 1 args2];
+        assert_eq!(vm.run(&program), AoStatus::DataStackUnderflow);
+    }
+
+    #[test]
+    fn test_clrf() {
+        let mut vm = AoVM::default();
+        let program = ao_program![
+            push 1
+            push dsb
+            push 2
+            push 3
+            cnf 2
+            push 4
+            push 5
+            push 6
+            clrf
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.dsb, 2);
+        assert_eq!(vm.ds.len(), 2);
+
+        // no-op at or below the frame base
+        assert_eq!(vm.run(&ao_program![clrf]), AoStatus::Exit);
+        assert_eq!(vm.ds.len(), 2);
+        vm.reset();
+        vm.dsb = 5;
+        vm.push(AoType::AoInt(1));
+        assert_eq!(vm.run(&ao_program![clrf]), AoStatus::Exit);
+        assert_eq!(vm.ds, vec![AoType::AoInt(1)]);
+    }
+
+    #[test]
+    fn test_assert() {
+        let mut vm = AoVM::default();
+        let program = ao_program![
+            mov ca,1
+            assert
+            equ 1
+            assert "ca should be 1"
+            mov ca,2
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoInt(2));
+
+        vm.reset();
+        let program = ao_program![
+            mov ca,0
+            assert "ca should be 1"
+            mov ca,2
+        ];
+        assert_eq!(
+            vm.run(&program),
+            AoStatus::AssertionFailed("ca should be 1".to_string())
+        );
+        assert_eq!(vm.ca, AoType::AoInt(0));
+        assert_eq!(program[1].to_string(), "assert \"ca should be 1\"");
+
+        vm.reset();
+        let program = ao_program![mov ca,"" assert];
+        assert_eq!(
+            vm.run(&program),
+            AoStatus::AssertionFailed("ca is \"\"".to_string())
+        );
+
+        let bin = AoAsmSerializer::serialize(&program);
+        let parsed = AoAsmSerializer::deserialize(&bin).unwrap();
+        assert_eq!(parsed[1].to_string(), "assert");
+    }
+
+    #[test]
+    fn test_halt() {
+        let mut vm = AoVM::default();
+        assert_eq!(vm.run(&ao_program![halt]), AoStatus::Exit);
+        assert!(vm.ds.is_empty());
+        assert_eq!(vm.dsb, 0);
+
+        vm.reset();
+        let program = ao_program![
+            push 1
+            halt
+            push 2
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ds, vec![AoType::AoInt(1)]);
+        assert_eq!(vm.pc, 2);
+
+        vm.reset();
+        let program = ao_program![
+            mov ca,6
+            mul 7
+            halt ca
+            mov ca,0
+        ];
+        let status = vm.run(&program);
+        assert_eq!(status, AoStatus::ExitWith(AoType::AoInt(42)));
+        match status {
+            AoStatus::ExitWith(AoType::AoInt(result)) => assert_eq!(result, 42),
+            _ => unreachable!(),
+        }
+        assert_eq!(status.to_string(), "Exit With(42)");
+        vm.reset();
+        assert_eq!(vm.try_run(&program), Ok(()));
+    }
+
+    #[test]
+    fn test_cnf() {
+        let mut vm = AoVM::default();
+        let program = ao_program![
+            push 1
+            push 2
+            cnf 5
+        ];
+        assert_eq!(vm.run(&program), AoStatus::BadDataStack);
+        assert_eq!(vm.dsb, 0);
+
+        vm.reset();
+        let program = ao_program![
+            push 1
+            push 2
+            cnf 2
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.dsb, 0);
+    }
+
+    #[test]
+    fn test_swab() {
+        let mut vm = AoVM::default();
+        // (3 + 4) * (10 - 8)
+        let program = ao_program![
+            mov ca,3
+            add 4
+            swab
+            mov ca,10
+            sub 8
+            mul cb
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoInt(14));
+        assert_eq!(vm.cb, AoType::AoInt(7));
+
+        vm.reset();
+        let program = ao_program![
+            mov ca,"a"
+            mov cb,1
+            swab
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoInt(1));
+        assert_eq!(vm.cb, AoType::from("a"));
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut vm = AoVM::default();
+        let program = ao_program![
+            push 1
+            push 2
+            push 3
+            mov dp,dsb
+            peek 0
+            mov cb,ca
+            peek 1
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.cb, AoType::AoInt(3));
+        assert_eq!(vm.ca, AoType::AoInt(2));
+        assert_eq!(vm.ds.len(), 3);
+        assert_eq!(vm.dp, 0);
+
+        vm.reset();
+        let program = ao_program![
+            push 1
+            push 2
+            push 3
+            peek 3
+        ];
+        assert_eq!(vm.run(&program), AoStatus::DataStackUnderflow);
+    }
+
+    #[test]
+    fn test_pick_roll() {
+        let (a, b, c, d) = (
+            AoType::from("a"),
+            AoType::from("b"),
+            AoType::from("c"),
+            AoType::from("d"),
+        );
+        let mut vm = AoVM::default();
+        let program = ao_program![
+            push "a"
+            push "b"
+            push "c"
+            push "d"
+            pick 2
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(
+            vm.ds,
+            vec![a.clone(), b.clone(), c.clone(), d.clone(), b.clone()]
+        );
+
+        vm.reset();
+        let program = ao_program![
+            push "a"
+            push "b"
+            push "c"
+            push "d"
+            roll 2
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ds, vec![a, c, d, b]);
+
+        vm.reset();
+        assert_eq!(
+            vm.run(&ao_program![push 1 pick 1]),
+            AoStatus::DataStackUnderflow
+        );
+        vm.reset();
+        assert_eq!(
+            vm.run(&ao_program![push 1 roll 1]),
+            AoStatus::DataStackUnderflow
+        );
+    }
+
+    #[test]
+    fn test_memcpy() {
+        let values: Vec<AoType> = (1..=5).map(AoType::AoInt).collect();
+
+        let mut vm = AoVM::default();
+        vm.mem_load_slice(0, &values);
+        let program = ao_program![
+            push 10
+            push 0
+            push 5
+            memcpy
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert!(vm.ds.is_empty());
+        for (i, value) in values.iter().enumerate() {
+            assert_eq!(vm.mem_get(10 + i as u32), *value);
+        }
+
+        // destination overlaps the end of the source
+        let mut vm = AoVM::default();
+        vm.mem_load_slice(0, &values);
+        let program = ao_program![
+            push 2
+            push 0
+            push 5
+            memcpy
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        for (i, value) in values.iter().enumerate() {
+            assert_eq!(vm.mem_get(2 + i as u32), *value);
+        }
+        assert_eq!(vm.mem_get(1), AoType::AoInt(2));
+
+        let mut vm = AoVM::default();
+        let program = ao_program![
+            push 0
+            push "src"
+            push 1
+            memcpy
+        ];
+        assert_eq!(
+            vm.run(&program),
+            AoStatus::InvalidOperation("memcpy 0,\"src\",1".to_string())
+        );
+        vm.reset();
+        assert_eq!(
+            vm.run(&ao_program![push 0 push 0 memcpy]),
+            AoStatus::DataStackUnderflow
+        );
+    }
+
+    #[test]
+    fn test_memset() {
+        let mut vm = AoVM::default();
+        let program = ao_program![
+            push 7
+            push 100
+            push 10
+            memset
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert!(vm.ds.is_empty());
+        for addr in 100..110 {
+            assert_eq!(vm.mem_get(addr), AoType::AoInt(7));
+        }
+        assert_eq!(vm.mem_get(99), AoType::default());
+        assert_eq!(vm.mem_get(110), AoType::default());
+
+        vm.reset();
+        let program = ao_program![
+            push 7
+            push 100
+            push 0
+            memset
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.mem_get(100), AoType::default());
+
+        for (len, message) in [
+            (AoType::AoInt(-1), "memset 7,100,-1"),
+            (AoType::AoFloat(2.0), "memset 7,100,2f"),
+        ] {
+            vm.reset();
+            vm.push(AoType::AoInt(7));
+            vm.push(AoType::AoInt(100));
+            vm.push(len);
+            assert_eq!(
+                vm.run(&ao_program![memset]),
+                AoStatus::InvalidOperation(message.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_wrapping_checked() {
+        let mut vm = AoVM::default();
+        let cases = [
+            (ao_program![mov ca,2147483647 add 1], Ok(i32::MIN)),
+            (ao_program![mov ca,2147483647 addc 1], Err(i32::MAX)),
+            (ao_program![mov ca,2147483647 mul 2], Ok(-2)),
+            (ao_program![mov ca,2147483647 mulc 2], Err(i32::MAX)),
+            (ao_program![mov ca,2147483647 add 1 sub 1], Ok(i32::MAX)),
+            (ao_program![mov ca,2147483647 add 1 subc 1], Err(i32::MIN)),
+            (ao_program![mov ca,2147483647 subc 1 addc 1], Ok(i32::MAX)),
+            (ao_program![mov ca,6 mov cb,7 mulc cb], Ok(42)),
+        ];
+        for (program, expected) in cases {
+            vm.reset();
+            let text = AoAsmSerializer::disassemble(&program);
+            match expected {
+                Ok(value) => {
+                    assert_eq!(vm.run(&program), AoStatus::Exit, "{}", text);
+                    assert_eq!(vm.ca, AoType::AoInt(value), "{}", text);
+                }
+                Err(value) => {
+                    assert_eq!(vm.run(&program), AoStatus::ArithmeticOverflow, "{}", text);
+                    assert_eq!(vm.ca, AoType::AoInt(value), "{}", text);
+                }
+            }
+        }
+
+        // other types use the default operators
+        vm.reset();
+        assert_eq!(vm.run(&ao_program![mov ca,1.5 addc 1.0]), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoFloat(2.5));
+        vm.reset();
+        assert_eq!(vm.run(&ao_program![mov ca,"ab" addc "c"]), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::from("abc"));
+        vm.reset();
+        assert_eq!(
+            vm.run(&ao_program![mov ca,1 mulc "x"]),
+            AoStatus::InvalidOperation("1 * \"x\"".to_string())
+        );
+    }
+
+    #[test]
+    fn test_mixed_compare() {
+        let mut vm = AoVM::default();
+        let program = ao_program![
+            mov ca,2
+            equ 2.0
+            push ca
+            mov ca,3
+            gt 2.5
+            push ca
+            mov ca,2.5
+            le 2
+            push ca
+            mov ca,2
+            neq 2.0
+            push ca
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(
+            vm.ds,
+            vec![
+                AoType::AoBool(true),
+                AoType::AoBool(true),
+                AoType::AoBool(false),
+                AoType::AoBool(false),
+            ]
+        );
+
+        vm.reset();
+        assert_eq!(
+            vm.run(&ao_program![mov ca,1 lt "2"]),
+            AoStatus::InvalidOperation("1 < \"2\"".to_string())
+        );
+
+        vm.reset();
+        assert_eq!(
+            vm.run(&ao_program![mov ca,1 newarr mov cb,ca ge cb]),
+            AoStatus::InvalidOperation("[0] >= [0]".to_string())
+        );
+        vm.reset();
+        assert_eq!(
+            vm.run(&ao_program![newmap lt ca]),
+            AoStatus::InvalidOperation("{} < {}".to_string())
+        );
+    }
+
+    #[test]
+    fn test_equ_neq() {
+        let mut vm = AoVM::default();
+        let program = ao_program![
+            mov ca,2
+            newarr
+            mov cb,ca
+            equ cb
+            push ca
+            mov ca,2
+            newarr
+            equ cb
+            push ca
+            mov ca,1
+            newarr
+            neq cb
+            push ca
+            newmap
+            mov cb,ca
+            newmap
+            equ cb
+            push ca
+            mov ca,3u32
+            equ 3u32
+            push ca
+            mov ca,3u32
+            neq 4u32
+            push ca
+            mov ca,3u32
+            equ 3
+            push ca
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(
+            vm.ds,
+            vec![
+                AoType::AoBool(true),
+                AoType::AoBool(true),
+                AoType::AoBool(true),
+                AoType::AoBool(true),
+                AoType::AoBool(true),
+                AoType::AoBool(true),
+                AoType::AoBool(false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dst_underflow() {
+        let program = ao_program![
+            mov ca,dst
+            sub 2
+            mov dp,ca
+        ];
+
+        let mut vm = AoVM::default();
+        vm.push_all(&[AoType::AoInt(1), AoType::AoInt(2)]);
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.dp, 0);
+
+        vm.reset();
+        vm.push(AoType::AoInt(1));
+        assert_eq!(
+            vm.run(&program),
+            AoStatus::InvalidOperation("1p - 2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_equi() {
+        let mut vm = AoVM::default();
+        let program = ao_program![
+            mov ca,"Yes"
+            equi "yes"
+            push ca
+            mov ca,"Yes"
+            equi "no"
+            push ca
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ds, vec![AoType::AoBool(true), AoType::AoBool(false)]);
+
+        vm.reset();
+        assert_eq!(
+            vm.run(&ao_program![mov ca,"1" equi 1]),
+            AoStatus::InvalidOperation("\"1\" equi 1".to_string())
+        );
+        vm.reset();
+        assert_eq!(vm.run(&ao_program![mov ca,"Yes" equ "yes"]), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoBool(false));
+    }
+
+    #[test]
+    fn test_truthy() {
+        let cases = [
+            (AoType::AoInt(0), false),
+            (AoType::AoInt(-3), true),
+            (AoType::AoFloat(0.0), false),
+            (AoType::AoFloat(0.5), true),
+            (AoType::AoBool(true), true),
+            (AoType::from("yes"), false),
+            (AoType::AoPtr(1), false),
+        ];
+        for (value, expected) in cases {
+            let mut vm = AoVM {
+                ca: value,
+                ..AoVM::default()
+            };
+            assert_eq!(vm.run(&ao_program![truthy]), AoStatus::Exit);
+            assert_eq!(vm.ca, AoType::AoBool(expected));
+        }
+    }
+
+    #[test]
+    fn test_inset() {
+        let mut vm = AoVM::default();
+        let program = ao_program![
+            mov ca,"b"
+            inset "a","b","c"
+            push ca
+            mov ca,"d"
+            inset "a","b","c"
+            push ca
+            mov ca,2
+            inset 1,2,3
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ds, vec![AoType::AoBool(true), AoType::AoBool(false)]);
+        assert_eq!(vm.ca, AoType::AoBool(true));
+        assert_eq!(program[1].to_string(), "inset \"a\",\"b\",\"c\"");
+
+        let bin = AoAsmSerializer::serialize(&program);
+        let parsed = AoAsmSerializer::deserialize(&bin).unwrap();
+        assert_eq!(
+            AoAsmSerializer::disassemble(&parsed),
+            AoAsmSerializer::disassemble(&program)
+        );
+    }
+
+    #[test]
+    fn test_array() {
+        // squares of 0..4
+        let program = ao_program![
+            mov ca,4
+            newarr
+            mov cb,ca
+            push 0
+            push 0
+            push 1
+            push 1
+            push 2
+            push 4
+            push 3
+            push 9
+            aset
+            aset
+            aset
+            aset
+            push 2
+            aget
+            push ca
+            mov ca,cb
+            alen
+        ];
+        let mut vm = AoVM::default();
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoInt(4));
+        assert_eq!(vm.ds, vec![AoType::AoInt(4)]);
+        assert_eq!(vm.cb.to_string(), "[0, 1, 4, 9]");
+
+        // copies share the elements
+        let mut vm = AoVM::default();
+        let program = ao_program![
+            mov ca,1
+            newarr
+            push ca
+            push 0
+            push "x"
+            aset
+            mov ca,ds
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ca.to_string(), "[\"x\"]");
+
+        for (program, status) in [
+            (
+                ao_program![mov ca,0 dec newarr],
+                AoStatus::InvalidOperation("newarr -1".to_string()),
+            ),
+            (
+                ao_program![mov ca,2 newarr push 2 aget],
+                AoStatus::BadDataAccess,
+            ),
+            (
+                ao_program![mov ca,0 dec push ca mov ca,2 newarr aget],
+                AoStatus::BadDataAccess,
+            ),
+            (
+                ao_program![mov ca,2 newarr aget],
+                AoStatus::DataStackUnderflow,
+            ),
+            (
+                ao_program![mov ca,2 newarr push 0 aset],
+                AoStatus::DataStackUnderflow,
+            ),
+            (
+                ao_program![mov ca,2 alen],
+                AoStatus::InvalidOperation("alen 2".to_string()),
+            ),
+            (ao_program![mov ca,2147483647 newarr], AoStatus::OutOfMemory),
+            // an array cannot hold itself
+            (
+                ao_program![mov ca,1 newarr push 0 push ca aset],
+                AoStatus::InvalidOperation("aset [0]".to_string()),
+            ),
+            // nor an array holding it
+            (
+                ao_program![
+                    mov ca,1
+                    newarr
+                    mov cb,ca
+                    mov ca,1
+                    newarr
+                    push 0
+                    push cb
+                    aset
+                    push 0
+                    push ca
+                    mov ca,cb
+                    aset
+                ],
+                AoStatus::InvalidOperation("aset [[0]]".to_string()),
+            ),
+        ] {
+            let mut vm = AoVM::default();
+            assert_eq!(vm.run(&program), status);
+        }
+
+        let mut vm = AoVM::default();
+        vm.set_max_array_len(3);
+        assert_eq!(vm.run(&ao_program![mov ca,3 newarr]), AoStatus::Exit);
+        vm.reset();
+        assert_eq!(vm.run(&ao_program![mov ca,4 newarr]), AoStatus::OutOfMemory);
+        assert_eq!(vm.ca, AoType::AoInt(4));
+
+        // failed stores leave the index and value on the stack
+        vm.reset();
+        let program = ao_program![mov ca,1 newarr push 0 push ca aset];
+        assert!(matches!(vm.run(&program), AoStatus::InvalidOperation(_)));
+        assert_eq!(vm.ds.len(), 2);
+        assert_eq!(vm.ds[0], AoType::AoInt(0));
+        vm.reset();
+        let program = ao_program![mov ca,1 newarr push 5 push 1 aset];
+        assert_eq!(vm.run(&program), AoStatus::BadDataAccess);
+        assert_eq!(vm.ds, vec![AoType::AoInt(5), AoType::AoInt(1)]);
+    }
+
+    #[test]
+    fn test_array_immediate() {
+        let mut program: AoProgram = vec![Box::new(opcodes::Mov {
+            dst: AoArg::CA,
+            src: AoArg::Imm(AoType::AoArray(Rc::new(RefCell::new(vec![AoType::AoInt(
+                1,
+            )])))),
+        })];
+        program.extend(ao_program![push 0 push 99 aset]);
+
+        let mut vm = AoVM::default();
+        for _ in 0..2 {
+            vm.reset();
+            assert_eq!(vm.run(&program), AoStatus::Exit);
+            assert_eq!(vm.ca.to_string(), "[99]");
+            assert_eq!(program[0].to_string(), "mov ca,[1]");
+        }
+    }
+
+    #[test]
+    fn test_wrap() {
+        let mut vm = AoVM::default();
+        assert_eq!(vm.run(&ao_program![mov ca,7 wrap]), AoStatus::Exit);
+        assert_eq!(vm.ca.to_string(), "[7]");
+
+        vm.reset();
+        let program = ao_program![mov ca,"a" wrap wrap push 0 aget alen];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoInt(1));
+
+        vm.reset();
+        let program = ao_program![mov ca,0 wrap jmpa 1];
+        assert!(matches!(vm.run(&program), AoStatus::InvalidOperation(_)));
+        assert!(!vm.ca.is_too_deep());
+    }
+
+    #[test]
+    fn test_map() {
+        let program = ao_program![
+            newmap
+            push "one"
+            push 1
+            mset
+            push 2
+            push "two"
+            mset
+            push "one"
+            push 10
+            mset
+            mov cb,ca
+            push "one"
+            mget
+            push ca
+            mov ca,cb
+            push 3
+            mhas
+            push ca
+            mov ca,cb
+            push 2
+            mhas
+            push ca
+            mov ca,cb
+            mlen
+        ];
+        let mut vm = AoVM::default();
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoInt(2));
+        assert_eq!(
+            vm.ds,
+            vec![
+                AoType::AoInt(10),
+                AoType::AoBool(false),
+                AoType::AoBool(true)
+            ]
+        );
+        assert_eq!(vm.cb.to_string(), "{2: \"two\", \"one\": 10}");
+
+        for (program, status) in [
+            (ao_program![newmap push 1 mget], AoStatus::BadDataAccess),
+            (ao_program![newmap mget], AoStatus::DataStackUnderflow),
+            (
+                ao_program![newmap push 1 mset],
+                AoStatus::DataStackUnderflow,
+            ),
+            (
+                ao_program![newmap push 1.5 mhas],
+                AoStatus::InvalidOperation("mhas 1.5f".to_string()),
+            ),
+            (
+                ao_program![newmap push ca push 1 mset],
+                AoStatus::InvalidOperation("mset {}".to_string()),
+            ),
+            (
+                ao_program![push 1 mget],
+                AoStatus::InvalidOperation("mget 0".to_string()),
+            ),
+            // a map cannot hold itself
+            (
+                ao_program![newmap push 1 push ca mset],
+                AoStatus::InvalidOperation("mset {}".to_string()),
+            ),
+            // nor a map holding it
+            (
+                ao_program![
+                    newmap
+                    mov cb,ca
+                    newmap
+                    push 1
+                    push cb
+                    mset
+                    push 1
+                    push ca
+                    mov ca,cb
+                    mset
+                ],
+                AoStatus::InvalidOperation("mset {1: {}}".to_string()),
+            ),
+        ] {
+            let mut vm = AoVM::default();
+            assert_eq!(vm.run(&program), status);
+        }
+
+        // failed stores leave the key and value on the stack
+        let mut vm = AoVM::default();
+        let program = ao_program![newmap push 1 push ca mset];
+        assert!(matches!(vm.run(&program), AoStatus::InvalidOperation(_)));
+        assert_eq!(vm.ds.len(), 2);
+        assert_eq!(vm.ds[0], AoType::AoInt(1));
+        vm.reset();
+        let program = ao_program![newmap push 1.5 push 1 mset];
+        assert!(matches!(vm.run(&program), AoStatus::InvalidOperation(_)));
+        assert_eq!(vm.ds, vec![AoType::AoFloat(1.5), AoType::AoInt(1)]);
+    }
+
+    #[test]
+    fn test_format() {
+        let program = ao_program![
+            push "x={} y={}"
+            push 3
+            push 4
+            format 2
+        ];
+        let mut vm = AoVM::default();
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::from("x=3 y=4"));
+        assert!(vm.ds.is_empty());
+
+        let program = ao_program![
+            push "{}, {}!"
+            push "Hello"
+            push 1.5
+            format 2
+        ];
+        vm.reset();
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::from("Hello, 1.5!"));
+
+        let program = ao_program![
+            push "x={} y={}"
+            push 3
+            format 1
+        ];
+        vm.reset();
+        assert_eq!(
+            vm.run(&program),
+            AoStatus::InvalidOperation("format \"x={} y={}\" with 1 values".to_string())
+        );
+
+        vm.reset();
+        assert_eq!(
+            vm.run(&ao_program![push 3 format 1]),
+            AoStatus::DataStackUnderflow
+        );
+
+        let program = ao_program![mov ca,"ab" mul 8388608 push "{}{}" push ca push ca format 2];
+        vm.reset();
+        assert_eq!(
+            vm.run(&program),
+            AoStatus::InvalidOperation("format 2 over 16777216 bytes".to_string())
+        );
+    }
+
+    #[test]
+    fn test_strcat() {
+        let program = ao_program![
+            push 0
+            push "Hello"
+            push ", "
+            push "Aoi"
+            strcat 3
+        ];
+        let mut vm = AoVM::default();
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ds, vec![AoType::AoInt(0), AoType::from("Hello, Aoi")]);
+
+        vm.reset();
+        assert_eq!(
+            vm.run(&ao_program![push "a" push 2 strcat 2]),
+            AoStatus::Exit
+        );
+        assert_eq!(vm.ds, vec![AoType::from("a2")]);
+
+        vm.reset();
+        assert_eq!(
+            vm.run(&ao_program![push "a" strcat 2]),
+            AoStatus::DataStackUnderflow
+        );
+        assert_eq!(vm.ds, vec![AoType::from("a")]);
+
+        // 16 MiB strings are the longest that can be built
+        let program = ao_program![mov ca,"ab" mul 8388608 push ca push ca strcat 2];
+        vm.reset();
+        assert_eq!(
+            vm.run(&program),
+            AoStatus::InvalidOperation("strcat 2 over 16777216 bytes".to_string())
+        );
+        vm.reset();
+        assert!(matches!(
+            vm.run(&ao_program![mov ca,"ab" mul 8388608 add "c"]),
+            AoStatus::InvalidOperation(_)
+        ));
+    }
+
+    #[test]
+    fn test_rev() {
+        let mut vm = AoVM::default();
+        assert_eq!(vm.run(&ao_program![mov ca,"abc" rev]), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::from("cba"));
+
+        vm.reset();
+        assert_eq!(vm.run(&ao_program![mov ca,"café" rev]), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::from("éfac"));
+
+        vm.reset();
+        assert_eq!(
+            vm.run(&ao_program![mov ca,12 rev]),
+            AoStatus::InvalidOperation("rev 12".to_string())
+        );
+    }
+}
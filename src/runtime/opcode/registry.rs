@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+use super::opcodes::create_opcode_by_id;
+use super::AoOpcode;
+
+/// Ids that are never used by built-in opcodes and can be registered by hosts.
+pub const USER_OPCODE_IDS: RangeInclusive<u8> = 0x80..=0xAF;
+
+type OpcodeConstructor = Box<dyn Fn() -> Box<dyn AoOpcode>>;
+
+/// Maps opcode ids to constructors, used when deserializing programs.
+///
+/// The default registry contains the built-in opcodes. Custom opcodes can be
+/// added with ids in [`USER_OPCODE_IDS`].
+///
+/// # Examples
+/// ```
+/// use aoi::opcode::registry::OpcodeRegistry;
+/// use aoi::opcodes::Nop;
+///
+/// let mut registry = OpcodeRegistry::default();
+/// assert!(registry.register(0x90, || Box::new(Nop)));
+/// assert!(!registry.register(0x90, || Box::new(Nop)));
+/// assert!(!registry.register(0x00, || Box::new(Nop)));
+/// assert!(registry.create(0x90).is_some());
+/// ```
+pub struct OpcodeRegistry {
+    constructors: HashMap<u8, OpcodeConstructor>,
+}
+
+impl OpcodeRegistry {
+    /// Create a registry with no opcodes.
+    pub fn empty() -> OpcodeRegistry {
+        OpcodeRegistry {
+            constructors: HashMap::new(),
+        }
+    }
+
+    /// Register a custom opcode.
+    ///
+    /// Returns `false` without registering if `id` is outside
+    /// [`USER_OPCODE_IDS`] or already taken.
+    pub fn register<F>(&mut self, id: u8, constructor: F) -> bool
+    where
+        F: Fn() -> Box<dyn AoOpcode> + 'static,
+    {
+        if !USER_OPCODE_IDS.contains(&id) || self.constructors.contains_key(&id) {
+            return false;
+        }
+        self.constructors.insert(id, Box::new(constructor));
+        true
+    }
+
+    /// Create an opcode with default arguments.
+    pub fn create(&self, id: u8) -> Option<Box<dyn AoOpcode>> {
+        self.constructors.get(&id).map(|constructor| constructor())
+    }
+
+    pub fn contains(&self, id: u8) -> bool {
+        self.constructors.contains_key(&id)
+    }
+}
+
+impl Default for OpcodeRegistry {
+    fn default() -> OpcodeRegistry {
+        let mut registry = OpcodeRegistry::empty();
+        for id in 0..=u8::MAX {
+            if create_opcode_by_id(id).is_some() {
+                registry
+                    .constructors
+                    .insert(id, Box::new(move || create_opcode_by_id(id).unwrap()));
+            }
+        }
+        registry
+    }
+}
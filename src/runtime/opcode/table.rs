@@ -0,0 +1,119 @@
+use std::sync::OnceLock;
+
+use super::opcodes::{create_opcode_by_id, OpcodeArgType};
+
+/// The kind of arguments an opcode takes, without their values.
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpcodeArgShape {
+    NoArg,
+    u8,
+    i32,
+    u32,
+    bool,
+    AoArg,
+    AoArg2,
+    u32_u32,
+    u32_i32,
+    AoTypes,
+    u32s,
+}
+
+impl OpcodeArgShape {
+    /// Number of operands written after the mnemonic.
+    ///
+    /// Lists such as the values of `inset` count as one operand.
+    pub fn arity(&self) -> usize {
+        match self {
+            OpcodeArgShape::NoArg => 0,
+            OpcodeArgShape::AoArg2 | OpcodeArgShape::u32_u32 | OpcodeArgShape::u32_i32 => 2,
+            _ => 1,
+        }
+    }
+}
+
+impl From<&OpcodeArgType> for OpcodeArgShape {
+    fn from(args: &OpcodeArgType) -> OpcodeArgShape {
+        match args {
+            OpcodeArgType::NoArg => OpcodeArgShape::NoArg,
+            OpcodeArgType::u8(_) => OpcodeArgShape::u8,
+            OpcodeArgType::i32(_) => OpcodeArgShape::i32,
+            OpcodeArgType::u32(_) => OpcodeArgShape::u32,
+            OpcodeArgType::bool(_) => OpcodeArgShape::bool,
+            OpcodeArgType::AoArg(_) => OpcodeArgShape::AoArg,
+            OpcodeArgType::AoArg2(_, _) => OpcodeArgShape::AoArg2,
+            OpcodeArgType::u32_u32(_, _) => OpcodeArgShape::u32_u32,
+            OpcodeArgType::u32_i32(_, _) => OpcodeArgShape::u32_i32,
+            OpcodeArgType::AoTypes(_) => OpcodeArgShape::AoTypes,
+            OpcodeArgType::u32s(_) => OpcodeArgShape::u32s,
+        }
+    }
+}
+
+/// Description of a built-in opcode.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OpcodeInfo {
+    /// First word of the opcode's text form, as accepted by the assembler.
+    pub mnemonic: String,
+    pub id: u8,
+    pub args: OpcodeArgShape,
+}
+
+/// All built-in opcodes, ordered by id.
+///
+/// Built once from [`create_opcode_by_id`], so it always matches the opcodes
+/// the deserializer knows.
+///
+/// # Examples
+/// ```
+/// use aoi::opcode::{opcode_table, OpcodeArgShape};
+///
+/// let mov = opcode_table().iter().find(|info| info.mnemonic == "mov").unwrap();
+/// assert_eq!(mov.id, 0x20);
+/// assert_eq!(mov.args, OpcodeArgShape::AoArg2);
+/// assert_eq!(mov.args.arity(), 2);
+/// ```
+pub fn opcode_table() -> &'static [OpcodeInfo] {
+    static TABLE: OnceLock<Vec<OpcodeInfo>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        (0..=u8::MAX)
+            .filter_map(|id| {
+                let opcode = create_opcode_by_id(id)?;
+                let text = opcode.to_string();
+                Some(OpcodeInfo {
+                    mnemonic: text.split_whitespace().next()?.to_string(),
+                    id,
+                    args: OpcodeArgShape::from(&opcode.get_args()),
+                })
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opcode_table() {
+        let table = opcode_table();
+        let mov = table.iter().find(|info| info.mnemonic == "mov").unwrap();
+        assert_eq!(mov.id, 0x20);
+        assert_eq!(mov.args, OpcodeArgShape::AoArg2);
+
+        let nop = &table[0];
+        assert_eq!((nop.mnemonic.as_str(), nop.id), ("nop", 0x00));
+        assert_eq!(nop.args.arity(), 0);
+
+        for id in 0..=u8::MAX {
+            let count = table.iter().filter(|info| info.id == id).count();
+            let expected = if create_opcode_by_id(id).is_some() {
+                1
+            } else {
+                0
+            };
+            assert_eq!(count, expected, "{:#04X}", id);
+        }
+        assert!(table.windows(2).all(|pair| pair[0].id < pair[1].id));
+    }
+}
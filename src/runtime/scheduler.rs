@@ -0,0 +1,175 @@
+use std::collections::VecDeque;
+
+use super::AoStatus;
+use super::AoVM;
+use crate::AoProgram;
+
+/// A VM and the program it runs, managed by an `AoScheduler`.
+pub struct AoTask {
+    pub id: usize,
+    pub vm: AoVM,
+    pub program: AoProgram,
+}
+
+/// Round-robin scheduler for cooperative multitasking across multiple VMs.
+///
+/// Every task runs for at most `quantum` steps per round before the next one
+/// gets its turn. A task can end its turn early by returning
+/// `Err(AoStatus::Yielded)` from an interrupt handler. Tasks that stop for
+/// any other reason than running out of their quantum or yielding are
+/// removed from the queue.
+pub struct AoScheduler {
+    quantum: u64,
+    next_id: usize,
+    tasks: VecDeque<AoTask>,
+}
+
+impl AoScheduler {
+    /// Create a new scheduler running each task for `quantum` steps per round.
+    ///
+    /// A quantum of 0 is raised to 1, so that every round makes progress.
+    pub fn new(quantum: u64) -> AoScheduler {
+        AoScheduler {
+            quantum: quantum.max(1),
+            next_id: 0,
+            tasks: VecDeque::new(),
+        }
+    }
+
+    /// Add a task to the end of the queue and return its id.
+    pub fn spawn(&mut self, vm: AoVM, program: AoProgram) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.tasks.push_back(AoTask { id, vm, program });
+        id
+    }
+
+    /// Number of tasks still waiting to be scheduled.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Whether all tasks have finished.
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Run every queued task for one quantum.
+    ///
+    /// Returns the tasks that finished in this round with their final status.
+    pub fn tick(&mut self) -> Vec<(AoTask, AoStatus)> {
+        let mut finished = Vec::new();
+        for _ in 0..self.tasks.len() {
+            let mut task = self.tasks.pop_front().unwrap();
+
+            let status = task.vm.run_with_limit(&task.program, self.quantum);
+            match status {
+                AoStatus::StepLimitExceeded | AoStatus::Yielded => self.tasks.push_back(task),
+                _ => finished.push((task, status)),
+            }
+        }
+        finished
+    }
+
+    /// Run all tasks until every one of them has finished.
+    ///
+    /// Returns the tasks in the order they finished with their final status.
+    pub fn run(&mut self) -> Vec<(AoTask, AoStatus)> {
+        let mut finished = Vec::new();
+        while !self.tasks.is_empty() {
+            finished.extend(self.tick());
+        }
+        finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::*;
+
+    fn counter(n: i32) -> AoProgram {
+        let mut program = ao_program![
+            push 0
+            arg 0
+            mov ca,ds
+            inc
+            mov ds,ca
+        ];
+        program.push(Box::new(opcodes::Lt {
+            src: AoArg::from(n),
+        }));
+        program.push(ao_asm!(jta 1));
+        program
+    }
+
+    #[test]
+    fn test_run_to_completion() {
+        let mut scheduler = AoScheduler::new(4);
+        scheduler.spawn(AoVM::default(), counter(30));
+        scheduler.spawn(AoVM::default(), counter(10));
+        scheduler.spawn(AoVM::default(), counter(20));
+
+        let finished = scheduler.run();
+        assert!(scheduler.is_empty());
+        assert_eq!(finished.len(), 3);
+
+        let order: Vec<usize> = finished.iter().map(|(task, _)| task.id).collect();
+        assert_eq!(order, vec![1, 2, 0]);
+
+        for (task, status) in finished {
+            assert_eq!(status, AoStatus::Exit);
+            let expected = [30, 10, 20][task.id];
+            assert_eq!(task.vm.ds[0], AoType::AoInt(expected));
+        }
+    }
+
+    #[test]
+    fn test_zero_quantum() {
+        let mut scheduler = AoScheduler::new(0);
+        scheduler.spawn(AoVM::default(), counter(3));
+
+        assert!(scheduler.tick().is_empty());
+        assert_eq!(scheduler.tasks[0].vm.pc, 1);
+        let finished = scheduler.run();
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].1, AoStatus::Exit);
+        assert_eq!(finished[0].0.vm.ds[0], AoType::AoInt(3));
+    }
+
+    #[test]
+    fn test_yield() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut scheduler = AoScheduler::new(100);
+        for id in 0..2 {
+            let log = log.clone();
+            let vm = AoVM::with_interrupt(move |_, _| {
+                log.borrow_mut().push(id);
+                Err(AoStatus::Yielded)
+            });
+            let program = ao_program![
+                push dsb
+                cnf 0
+                int 1
+                push dsb
+                cnf 0
+                int 1
+            ];
+            scheduler.spawn(vm, program);
+        }
+
+        let finished = scheduler.tick();
+        assert!(finished.is_empty());
+        assert_eq!(scheduler.len(), 2);
+
+        let finished = scheduler.run();
+        assert_eq!(*log.borrow(), vec![0, 1, 0, 1]);
+        for (task, status) in finished {
+            assert_eq!(status, AoStatus::Exit);
+            assert!(task.vm.ds.is_empty());
+        }
+    }
+}
@@ -0,0 +1,22 @@
+use std::fmt::Display;
+
+/// Position in the source code an instruction was compiled from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SourceSpan {
+    /// Line, starting from 1.
+    pub line: u32,
+    /// Column, starting from 1.
+    pub column: u32,
+}
+
+impl SourceSpan {
+    pub fn new(line: u32, column: u32) -> SourceSpan {
+        SourceSpan { line, column }
+    }
+}
+
+impl Display for SourceSpan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
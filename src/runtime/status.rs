@@ -2,6 +2,7 @@ use std::cmp::PartialEq;
 use std::fmt::Display;
 
 use super::AoType;
+use super::SourceSpan;
 
 /// Status in the runtime.
 #[derive(Debug, PartialEq)]
@@ -10,18 +11,37 @@ pub enum AoStatus {
     Ok,
     /// The program is finished.
     Exit,
+    /// The program is finished with a result, from `halt ca`.
+    ExitWith(AoType),
     /// The operation returned a value.
     Return(AoType),
+    /// The step limit was reached before the program finished.
+    StepLimitExceeded,
+    /// Not enough gas left to execute the next opcode.
+    OutOfGas,
+    /// A memory write would exceed the memory limit.
+    OutOfMemory,
+    /// Execution stopped before the instruction at this pc.
+    Breakpoint(u32),
+    /// The program gave up the rest of its turn, and running the VM again
+    /// resumes it. Returned by an interrupt handler as `Err`, it stops the
+    /// VM after the `int` completes.
+    Yielded,
 
     /// The data stack not match the expected type.
     BadDataStack,
+    /// A global variable was accessed out of range.
+    BadDataAccess,
+
+    /// An absolute jump or call targets an address outside the program.
+    InvalidJumpTarget(u32),
 
     /// The call stack is full.
-    CallStackOverflow,
+    CallStackOverflow(usize),
     /// The call stack is empty.
     CallStackUnderflow,
     /// The data stack is full.
-    DataStackOverflow,
+    DataStackOverflow(usize),
     /// The data stack is empty.
     DataStackUnderflow,
 
@@ -32,9 +52,29 @@ pub enum AoStatus {
 
     /// Attempt to perform an incompatible operation between two types.
     InvalidOperation(String),
+    /// A string could not be parsed as the requested type.
+    ConversionError(String),
+    /// A checked int operation overflowed.
+    ArithmeticOverflow,
+
+    /// No handler is registered for the interrupt id.
+    UnknownInterrupt(u8),
+
+    /// An `assert` found CA false.
+    AssertionFailed(String),
+
+    /// The program raised an error.
+    ScriptError { code: i32, message: String },
 
     /// Internal error.
     InternalError,
+
+    /// An error raised by the instruction compiled from `span`, reported
+    /// when the VM has source spans.
+    AtSource {
+        status: Box<AoStatus>,
+        span: SourceSpan,
+    },
 }
 
 impl Display for AoStatus {
@@ -42,13 +82,22 @@ impl Display for AoStatus {
         match self {
             AoStatus::Ok => write!(f, "Ok"),
             AoStatus::Exit => write!(f, "Exit"),
+            AoStatus::ExitWith(v) => write!(f, "Exit With({})", v),
             AoStatus::Return(v) => write!(f, "Return({})", v),
+            AoStatus::StepLimitExceeded => write!(f, "Step Limit Exceeded"),
+            AoStatus::OutOfGas => write!(f, "Out Of Gas"),
+            AoStatus::OutOfMemory => write!(f, "Out Of Memory"),
+            AoStatus::Breakpoint(pc) => write!(f, "Breakpoint({})", pc),
+            AoStatus::Yielded => write!(f, "Yielded"),
 
             AoStatus::BadDataStack => write!(f, "Bad Data Stack"),
+            AoStatus::BadDataAccess => write!(f, "Bad Data Access"),
 
-            AoStatus::CallStackOverflow => write!(f, "Call Stack Overflow"),
+            AoStatus::InvalidJumpTarget(addr) => write!(f, "Invalid Jump Target({})", addr),
+
+            AoStatus::CallStackOverflow(max) => write!(f, "Call Stack Overflow({})", max),
             AoStatus::CallStackUnderflow => write!(f, "Call Stack Underflow"),
-            AoStatus::DataStackOverflow => write!(f, "Data Stack Overflow"),
+            AoStatus::DataStackOverflow(max) => write!(f, "Data Stack Overflow({})", max),
             AoStatus::DataStackUnderflow => write!(f, "Data Stack Underflow"),
 
             AoStatus::SetValueInvalidType(v) => write!(f, "Set Value Invalid Type({})", v),
@@ -57,8 +106,36 @@ impl Display for AoStatus {
             }
 
             AoStatus::InvalidOperation(v) => write!(f, "Invalid Operation({})", v),
+            AoStatus::ConversionError(v) => write!(f, "Conversion Error({})", v),
+            AoStatus::ArithmeticOverflow => write!(f, "Arithmetic Overflow"),
+
+            AoStatus::UnknownInterrupt(id) => write!(f, "Unknown Interrupt({})", id),
+
+            AoStatus::AssertionFailed(v) => write!(f, "Assertion Failed({})", v),
+
+            AoStatus::ScriptError { code, message } => {
+                write!(f, "Script Error({}: {})", code, message)
+            }
 
             AoStatus::InternalError => write!(f, "Internal Error"),
+
+            AoStatus::AtSource { status, span } => write!(f, "{} at {}", status, span),
         }
     }
 }
+
+/// A status that stopped execution, with the pc of the instruction that
+/// produced it.
+#[derive(Debug, PartialEq)]
+pub struct AoFault {
+    pub pc: u32,
+    pub status: AoStatus,
+}
+
+impl Display for AoFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at pc={}", self.status, self.pc)
+    }
+}
+
+impl std::error::Error for AoFault {}
@@ -1,5 +1,6 @@
 use crate::AoStatus;
 use crate::AoType;
+use crate::MAX_STRING_LEN;
 
 pub(super) struct AoTypeBinOper {
     name: &'static str,
@@ -7,8 +8,11 @@ pub(super) struct AoTypeBinOper {
     bool_oper: Option<fn(bool, bool) -> bool>,
     int_oper: Option<fn(i32, i32) -> i32>,
     float_oper: Option<fn(f32, f32) -> f32>,
-    ptr_oper: Option<fn(u32, u32) -> u32>,
-    string_oper: Option<fn(&String, &String) -> String>,
+    // `None` from the operation means the result is not a valid pointer
+    ptr_oper: Option<fn(u32, i64) -> Option<u32>>,
+    // `None` from the string operations means the result would be too long
+    string_oper: Option<fn(&str, &str) -> Option<String>>,
+    string_int_oper: Option<fn(&str, i32) -> Option<String>>,
 }
 
 impl AoTypeBinOper {
@@ -40,12 +44,12 @@ impl AoTypeBinOper {
                 }
             }
             (AoType::AoPtr(l), AoType::AoPtr(r)) => {
-                if let Some(res) = self.ptr_oper.map(|oper| oper(*l, *r)) {
+                if let Some(Some(res)) = self.ptr_oper.map(|oper| oper(*l, *r as i64)) {
                     return AoStatus::Return(AoType::AoPtr(res));
                 }
             }
             (AoType::AoPtr(l), AoType::AoInt(r)) => {
-                if let Some(res) = self.ptr_oper.map(|oper| oper(*l, *r as u32)) {
+                if let Some(Some(res)) = self.ptr_oper.map(|oper| oper(*l, *r as i64)) {
                     return AoStatus::Return(AoType::AoPtr(res));
                 }
             }
@@ -55,8 +59,13 @@ impl AoTypeBinOper {
                 }
             }
             (AoType::AoString(l), AoType::AoString(r)) => {
-                if let Some(res) = self.string_oper.map(|oper| oper(l, r)) {
-                    return AoStatus::Return(AoType::AoString(res));
+                if let Some(Some(res)) = self.string_oper.map(|oper| oper(l, r)) {
+                    return AoStatus::Return(AoType::from(res));
+                }
+            }
+            (AoType::AoString(l), AoType::AoInt(r)) => {
+                if let Some(Some(res)) = self.string_int_oper.map(|oper| oper(l, *r)) {
+                    return AoStatus::Return(AoType::from(res));
                 }
             }
             _ => (),
@@ -72,6 +81,7 @@ static BIN_OPER_NONE: AoTypeBinOper = AoTypeBinOper {
     float_oper: None,
     ptr_oper: None,
     string_oper: None,
+    string_int_oper: None,
 };
 
 macro_rules! bop {
@@ -93,34 +103,52 @@ macro_rules! op {
     };
 }
 
+// Integer operations wrap on overflow in both debug and release builds.
+// Pointer operations fail instead when the result is below zero or above
+// `u32::MAX`, so stack-relative arithmetic such as `dst - 2` on a short stack
+// is reported rather than wrapping to a huge address.
 bop!(BIN_OPER_ADD, +,
     bool_oper: op!(||),
-    int_oper: op!(+),
+    int_oper: Some(i32::wrapping_add),
     float_oper: op!(+),
-    ptr_oper: op!(+),
-    string_oper: Some(|l, r| format!("{}{}", l, r)),
+    ptr_oper: Some(|l, r| u32::try_from(l as i64 + r).ok()),
+    string_oper: Some(|l, r| {
+        if l.len() + r.len() > MAX_STRING_LEN {
+            return None;
+        }
+        Some(format!("{}{}", l, r))
+    }),
 );
 
 bop!(BIN_OPER_SUB, -,
-    int_oper: op!(-),
+    int_oper: Some(i32::wrapping_sub),
     float_oper: op!(-),
-    ptr_oper: op!(-),
+    ptr_oper: Some(|l, r| u32::try_from(l as i64 - r).ok()),
 );
 
+// A string times an int repeats the string, as in Python: counts below one
+// give an empty string.
 bop!(BIN_OPER_MUL, *,
     bool_oper: op!(&&),
-    int_oper: op!(*),
+    int_oper: Some(i32::wrapping_mul),
     float_oper: op!(*),
-    ptr_oper: op!(*),
+    ptr_oper: Some(|l, r| u32::try_from((l as i64).checked_mul(r)?).ok()),
+    string_int_oper: Some(|l, r| {
+        let count = r.max(0) as usize;
+        match l.len().checked_mul(count) {
+            Some(len) if len <= MAX_STRING_LEN => Some(l.repeat(count)),
+            _ => None,
+        }
+    }),
 );
 
 bop!(BIN_OPER_DIV, /,
-    int_oper: op!(/),
+    int_oper: Some(i32::wrapping_div),
     float_oper: op!(/),
 );
 
 bop!(BIN_OPER_REM, %,
-    int_oper: op!(%),
+    int_oper: Some(i32::wrapping_rem),
     float_oper: op!(%),
 );
 
@@ -137,11 +165,11 @@ bop!(BIN_OPER_BXOR, ^,
 );
 
 bop!(BIN_OPER_SHL, <<,
-    int_oper: op!(<<),
+    int_oper: Some(|l, r| l.wrapping_shl(r as u32)),
 );
 
 bop!(BIN_OPER_SHR, >>,
-    int_oper: op!(>>),
+    int_oper: Some(|l, r| l.wrapping_shr(r as u32)),
 );
 
 #[cfg(test)]
@@ -150,13 +178,7 @@ mod tests {
 
     macro_rules! test_op {
         ( $op:ident, $lv:expr, $rv:expr, $res:expr ) => {
-            test_op!(
-                $op,
-                AoString,
-                $lv.to_string(),
-                $rv.to_string(),
-                $res.to_string()
-            );
+            test_op!($op, AoString, $lv.into(), $rv.into(), $res.into());
         };
         ( $op:ident, $t:ident, $lv:expr, $rv:expr, $res:expr ) => {
             let left = AoType::$t($lv);
@@ -167,7 +189,7 @@ mod tests {
 
     macro_rules! test_op_fail {
         ( $op:ident, $lv:expr, $rv:expr, $msg:expr ) => {
-            test_op_fail!($op, AoString, $lv.to_string(), $rv.to_string(), $msg);
+            test_op_fail!($op, AoString, $lv.into(), $rv.into(), $msg);
         };
         ( $op:ident, $t:ident, $lv:expr, $rv:expr, $msg:expr ) => {
             let left = AoType::$t($lv);
@@ -208,6 +230,48 @@ mod tests {
         test_op_fail!(BIN_OPER_MUL, "Hello", "World", "\"Hello\" * \"World\"");
     }
 
+    #[test]
+    fn test_repeat() {
+        let mul = |l: &str, r| BIN_OPER_MUL.apply(AoType::from(l), AoType::AoInt(r));
+        assert_eq!(mul("ab", 3), AoStatus::Return(AoType::from("ababab")));
+        assert_eq!(mul("x", 0), AoStatus::Return(AoType::from("")));
+        assert_eq!(mul("x", -2), AoStatus::Return(AoType::from("")));
+        assert_eq!(
+            mul("ab", i32::MAX),
+            AoStatus::InvalidOperation(format!("\"ab\" * {}", i32::MAX))
+        );
+        assert_eq!(mul("", i32::MAX), AoStatus::Return(AoType::from("")));
+        assert_eq!(
+            BIN_OPER_ADD.apply(AoType::from("ab"), AoType::AoInt(3)),
+            AoStatus::InvalidOperation("\"ab\" + 3".to_string())
+        );
+    }
+
+    #[test]
+    fn test_wrapping() {
+        test_op!(BIN_OPER_ADD, AoInt, i32::MAX, 1, i32::MIN);
+        test_op!(BIN_OPER_MUL, AoInt, i32::MAX, 2, -2);
+        test_op!(BIN_OPER_DIV, AoInt, i32::MIN, -1, i32::MIN);
+        test_op!(BIN_OPER_SHL, AoInt, 1, 33, 2);
+    }
+
+    #[test]
+    fn test_ptr_range() {
+        test_op_fail!(BIN_OPER_SUB, AoPtr, 0, 1, "0p - 1p");
+        test_op_fail!(BIN_OPER_ADD, AoPtr, u32::MAX, 1, "4294967295p + 1p");
+        test_op_fail!(BIN_OPER_MUL, AoPtr, u32::MAX, 2, "4294967295p * 2p");
+
+        let sub = |l, r| BIN_OPER_SUB.apply(AoType::AoPtr(l), AoType::AoInt(r));
+        assert_eq!(sub(1, 2), AoStatus::InvalidOperation("1p - 2".to_string()));
+        assert_eq!(sub(1, -2), AoStatus::Return(AoType::AoPtr(3)));
+        let add = |l, r| BIN_OPER_ADD.apply(AoType::AoPtr(l), AoType::AoInt(r));
+        assert_eq!(add(3, -1), AoStatus::Return(AoType::AoPtr(2)));
+        assert_eq!(
+            add(0, -1),
+            AoStatus::InvalidOperation("0p + -1".to_string())
+        );
+    }
+
     #[test]
     fn test_div() {
         test_op!(BIN_OPER_DIV, AoInt, 3, 2, 1);
@@ -1,13 +1,29 @@
 mod bin_oper;
 
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
 use std::ops::*;
+use std::rc::Rc;
+use std::str::FromStr;
 
 use super::AoStatus;
 use bin_oper::*;
 
+/// Deepest nesting of arrays and maps that can be built by storing values,
+/// and that is displayed, compared and serialized in full.
+pub const MAX_NESTING_DEPTH: usize = 64;
+
+/// Longest string, in bytes, that `+`, `*`, `format` and `strcat` may
+/// produce. Longer results fail with `AoStatus::InvalidOperation`.
+pub const MAX_STRING_LEN: usize = 1 << 24;
+
 /// The data type of the AOI virtual machine.
-#[derive(Clone, Debug, PartialEq)]
+///
+/// With the `serde` feature, values are serialized externally tagged, e.g.
+/// `{"AoInt":1}`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AoType {
     /// Boolean
     AoBool(bool),
@@ -17,11 +33,95 @@ pub enum AoType {
     AoFloat(f32),
     /// Pointer
     AoPtr(u32),
-    /// String
-    AoString(String),
+    /// String, shared so that copying the value does not copy the text
+    AoString(Rc<str>),
+    /// Array, shared so that copies of the value see the same elements;
+    /// compared by contents
+    AoArray(Rc<RefCell<Vec<AoType>>>),
+    /// Map ordered by key, shared like arrays; compared by contents
+    ///
+    /// With the `serde` feature, entries are serialized as a list of
+    /// key-value pairs.
+    AoMap(
+        #[cfg_attr(feature = "serde", serde(with = "map_entries"))]
+        Rc<RefCell<BTreeMap<AoMapKey, AoType>>>,
+    ),
 }
 
-impl AoType {
+/// Key of an `AoMap`.
+///
+/// Only values that compare exactly can be keys, so floats, arrays and maps
+/// are rejected.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AoMapKey {
+    Bool(bool),
+    Int(i32),
+    Ptr(u32),
+    String(Rc<str>),
+}
+
+impl AoMapKey {
+    /// Returns the key for the value, or `None` if it cannot be a key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use aoi::runtime::types::{AoMapKey, AoType};
+    ///
+    /// assert_eq!(AoMapKey::new(&AoType::AoInt(1)), Some(AoMapKey::Int(1)));
+    /// assert_eq!(AoMapKey::new(&AoType::AoFloat(1.0)), None);
+    /// ```
+    pub fn new(value: &AoType) -> Option<AoMapKey> {
+        match value {
+            AoType::AoBool(v) => Some(AoMapKey::Bool(*v)),
+            AoType::AoInt(v) => Some(AoMapKey::Int(*v)),
+            AoType::AoPtr(v) => Some(AoMapKey::Ptr(*v)),
+            AoType::AoString(v) => Some(AoMapKey::String(v.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl From<AoMapKey> for AoType {
+    fn from(key: AoMapKey) -> AoType {
+        match key {
+            AoMapKey::Bool(v) => AoType::AoBool(v),
+            AoMapKey::Int(v) => AoType::AoInt(v),
+            AoMapKey::Ptr(v) => AoType::AoPtr(v),
+            AoMapKey::String(v) => AoType::AoString(v),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+mod map_entries {
+    use super::*;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    type Map = Rc<RefCell<BTreeMap<AoMapKey, AoType>>>;
+
+    pub fn serialize<S: Serializer>(map: &Map, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            map.borrow()
+                .iter()
+                .map(|(key, value)| (AoType::from(key.clone()), value)),
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Map, D::Error> {
+        let mut map = BTreeMap::new();
+        for (key, value) in Vec::<(AoType, AoType)>::deserialize(deserializer)? {
+            match AoMapKey::new(&key) {
+                Some(key) => map.insert(key, value),
+                None => return Err(D::Error::custom(format!("invalid map key {}", key))),
+            };
+        }
+        Ok(Rc::new(RefCell::new(map)))
+    }
+}
+
+impl Default for AoType {
     /// Create a default AoType.
     ///
     /// # Examples
@@ -31,19 +131,422 @@ impl AoType {
     ///
     /// assert_eq!(AoType::default(), AoType::AoInt(0));
     /// ```
-    pub fn default() -> AoType {
+    fn default() -> AoType {
         AoType::AoInt(0)
     }
 }
 
+/// The variants of [`AoType`], numbered as [`AoType::type_tag`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AoTypeTag {
+    Bool = 1,
+    Int = 2,
+    Float = 3,
+    Ptr = 4,
+    String = 5,
+    Array = 6,
+    Map = 7,
+}
+
+impl Display for AoTypeTag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            AoTypeTag::Bool => "bool",
+            AoTypeTag::Int => "int",
+            AoTypeTag::Float => "float",
+            AoTypeTag::Ptr => "ptr",
+            AoTypeTag::String => "string",
+            AoTypeTag::Array => "array",
+            AoTypeTag::Map => "map",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl AoType {
+    /// Returns a small integer identifying the variant.
+    ///
+    /// The tags are stable and also mark values in serialized programs:
+    ///
+    /// | Variant    | Tag |
+    /// |------------|-----|
+    /// | `AoBool`   | 1   |
+    /// | `AoInt`    | 2   |
+    /// | `AoFloat`  | 3   |
+    /// | `AoPtr`    | 4   |
+    /// | `AoString` | 5   |
+    /// | `AoArray`  | 6   |
+    /// | `AoMap`    | 7   |
+    pub fn type_tag(&self) -> u8 {
+        match self {
+            AoType::AoBool(_) => 1,
+            AoType::AoInt(_) => 2,
+            AoType::AoFloat(_) => 3,
+            AoType::AoPtr(_) => 4,
+            AoType::AoString(_) => 5,
+            AoType::AoArray(_) => 6,
+            AoType::AoMap(_) => 7,
+        }
+    }
+
+    /// Convert the value the way the cast opcodes do.
+    ///
+    /// `Int`, `Float`, `Ptr` and `String` behave as `csi`, `csf`, `csp` and
+    /// `css`: numbers are truncated or wrapped, booleans become 0 or 1 and
+    /// strings that do not parse become 0. There is no cast opcode to bool, so
+    /// only booleans can be coerced to `Bool`. Arrays and maps only coerce to
+    /// their own type and to their `String` representation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use aoi::runtime::types::{AoType, AoTypeTag};
+    ///
+    /// assert_eq!(AoType::from("12").coerce_to(AoTypeTag::Int), Ok(AoType::AoInt(12)));
+    /// assert_eq!(AoType::AoBool(true).coerce_to(AoTypeTag::Float), Ok(AoType::AoFloat(1.0)));
+    /// assert!(AoType::AoInt(1).coerce_to(AoTypeTag::Bool).is_err());
+    /// ```
+    pub fn coerce_to(&self, target: AoTypeTag) -> Result<AoType, AoStatus> {
+        let bit = |b: bool| if b { 1 } else { 0 };
+        let result = match (target, self) {
+            (AoTypeTag::Bool, AoType::AoBool(_)) => self.clone(),
+
+            (AoTypeTag::Int, AoType::AoBool(b)) => AoType::AoInt(bit(*b)),
+            (AoTypeTag::Int, AoType::AoInt(_)) => self.clone(),
+            (AoTypeTag::Int, AoType::AoFloat(f)) => AoType::AoInt(*f as i32),
+            (AoTypeTag::Int, AoType::AoPtr(p)) => AoType::AoInt(*p as i32),
+            (AoTypeTag::Int, AoType::AoString(s)) => AoType::AoInt(s.parse().unwrap_or(0)),
+
+            (AoTypeTag::Float, AoType::AoBool(b)) => AoType::AoFloat(bit(*b) as f32),
+            (AoTypeTag::Float, AoType::AoInt(i)) => AoType::AoFloat(*i as f32),
+            (AoTypeTag::Float, AoType::AoFloat(_)) => self.clone(),
+            (AoTypeTag::Float, AoType::AoPtr(p)) => AoType::AoFloat(*p as f32),
+            (AoTypeTag::Float, AoType::AoString(s)) => AoType::AoFloat(s.parse().unwrap_or(0.0)),
+
+            (AoTypeTag::Ptr, AoType::AoBool(b)) => AoType::AoPtr(bit(*b) as u32),
+            (AoTypeTag::Ptr, AoType::AoInt(i)) => AoType::AoPtr(*i as u32),
+            (AoTypeTag::Ptr, AoType::AoFloat(f)) => AoType::AoPtr(*f as u32),
+            (AoTypeTag::Ptr, AoType::AoPtr(_)) => self.clone(),
+            (AoTypeTag::Ptr, AoType::AoString(s)) => AoType::AoPtr(s.parse().unwrap_or(0)),
+
+            (AoTypeTag::String, AoType::AoBool(b)) => AoType::from(b.to_string()),
+            (AoTypeTag::String, AoType::AoInt(i)) => AoType::from(i.to_string()),
+            (AoTypeTag::String, AoType::AoFloat(f)) => AoType::from(f.to_string()),
+            (AoTypeTag::String, AoType::AoPtr(p)) => AoType::from(p.to_string()),
+            (AoTypeTag::String, AoType::AoString(_)) => self.clone(),
+            (AoTypeTag::String, _) => AoType::from(self.to_string()),
+
+            (AoTypeTag::Array, AoType::AoArray(_)) => self.clone(),
+            (AoTypeTag::Map, AoType::AoMap(_)) => self.clone(),
+            _ => return Err(AoStatus::ConversionError(format!("{} to {}", self, target))),
+        };
+        Ok(result)
+    }
+
+    /// Returns the value if it is an `AoBool`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use aoi::runtime::types::AoType;
+    ///
+    /// assert_eq!(AoType::AoBool(true).as_bool(), Some(true));
+    /// assert_eq!(AoType::AoInt(1).as_bool(), None);
+    /// ```
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            AoType::AoBool(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is an `AoInt`.
+    pub fn as_int(&self) -> Option<i32> {
+        match self {
+            AoType::AoInt(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is an `AoFloat`.
+    pub fn as_float(&self) -> Option<f32> {
+        match self {
+            AoType::AoFloat(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is an `AoPtr`.
+    pub fn as_ptr(&self) -> Option<u32> {
+        match self {
+            AoType::AoPtr(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is an `AoString`.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            AoType::AoString(v) => Some(v.as_ref()),
+            _ => None,
+        }
+    }
+
+    // Address of the shared elements of an array or map.
+    fn container(&self) -> Option<*const ()> {
+        match self {
+            AoType::AoArray(v) => Some(Rc::as_ptr(v) as *const ()),
+            AoType::AoMap(v) => Some(Rc::as_ptr(v) as *const ()),
+            _ => None,
+        }
+    }
+
+    // Walk the arrays and maps in the value, with the value at `depth`.
+    // Fails on reaching `target` or a container deeper than
+    // `MAX_NESTING_DEPTH`. Shared containers are walked again only when
+    // reached at a greater depth.
+    fn check_nesting(
+        &self,
+        depth: usize,
+        target: Option<*const ()>,
+        seen: &mut HashMap<*const (), usize>,
+    ) -> bool {
+        let container = match self.container() {
+            Some(container) => container,
+            None => return true,
+        };
+        if Some(container) == target || depth > MAX_NESTING_DEPTH {
+            return false;
+        }
+        if seen.get(&container).is_some_and(|seen| *seen >= depth) {
+            return true;
+        }
+        seen.insert(container, depth);
+        match self {
+            AoType::AoArray(v) => v
+                .borrow()
+                .iter()
+                .all(|item| item.check_nesting(depth + 1, target, seen)),
+            AoType::AoMap(v) => v
+                .borrow()
+                .values()
+                .all(|item| item.check_nesting(depth + 1, target, seen)),
+            _ => true,
+        }
+    }
+
+    /// Whether arrays and maps in the value nest deeper than
+    /// [`MAX_NESTING_DEPTH`].
+    pub fn is_too_deep(&self) -> bool {
+        !self.check_nesting(1, None, &mut HashMap::new())
+    }
+
+    /// Whether `value` can be stored in this array or map.
+    ///
+    /// The value must not contain this array or map, which would make a
+    /// cycle, and storing it must not nest deeper than
+    /// [`MAX_NESTING_DEPTH`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    /// use aoi::runtime::types::AoType;
+    ///
+    /// let array = AoType::AoArray(Rc::new(RefCell::new(vec![AoType::AoInt(0)])));
+    /// assert!(array.can_hold(&AoType::AoInt(1)));
+    /// assert!(!array.can_hold(&array));
+    /// ```
+    pub fn can_hold(&self, value: &AoType) -> bool {
+        match self.container() {
+            Some(target) => value.check_nesting(2, Some(target), &mut HashMap::new()),
+            None => false,
+        }
+    }
+
+    /// Copy of the value with arrays and maps of its own, so that storing
+    /// into the copy leaves the value unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    /// use aoi::runtime::types::AoType;
+    ///
+    /// let items = Rc::new(RefCell::new(vec![AoType::AoInt(0)]));
+    /// let copy = AoType::AoArray(items.clone()).deep_clone();
+    /// items.borrow_mut()[0] = AoType::AoInt(7);
+    /// assert_eq!(copy.to_string(), "[0]");
+    /// ```
+    pub fn deep_clone(&self) -> AoType {
+        self.deep_clone_shared(&mut HashMap::new())
+    }
+
+    // Deep copy, with `copies` holding the copy of every array and map
+    // copied so far, so that containers shared between values stay shared
+    // between their copies.
+    pub(crate) fn deep_clone_shared(&self, copies: &mut HashMap<*const (), AoType>) -> AoType {
+        let container = match self.container() {
+            Some(container) => container,
+            None => return self.clone(),
+        };
+        if let Some(copy) = copies.get(&container) {
+            return copy.clone();
+        }
+        match self {
+            AoType::AoArray(v) => {
+                let array = Rc::new(RefCell::new(Vec::new()));
+                copies.insert(container, AoType::AoArray(array.clone()));
+                let items = v
+                    .borrow()
+                    .iter()
+                    .map(|item| item.deep_clone_shared(copies))
+                    .collect();
+                *array.borrow_mut() = items;
+                AoType::AoArray(array)
+            }
+            AoType::AoMap(v) => {
+                let map = Rc::new(RefCell::new(BTreeMap::new()));
+                copies.insert(container, AoType::AoMap(map.clone()));
+                let entries = v
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.deep_clone_shared(copies)))
+                    .collect();
+                *map.borrow_mut() = entries;
+                AoType::AoMap(map)
+            }
+            _ => self.clone(),
+        }
+    }
+
+    // Compare by contents down to `MAX_NESTING_DEPTH`, and deeper arrays and
+    // maps by identity.
+    fn eq_nested(&self, other: &AoType, depth: usize) -> bool {
+        match (self, other) {
+            (AoType::AoBool(l), AoType::AoBool(r)) => l == r,
+            (AoType::AoInt(l), AoType::AoInt(r)) => l == r,
+            (AoType::AoFloat(l), AoType::AoFloat(r)) => l == r,
+            (AoType::AoPtr(l), AoType::AoPtr(r)) => l == r,
+            (AoType::AoString(l), AoType::AoString(r)) => l == r,
+            (AoType::AoArray(l), AoType::AoArray(r)) if depth > MAX_NESTING_DEPTH => {
+                Rc::ptr_eq(l, r)
+            }
+            (AoType::AoArray(l), AoType::AoArray(r)) => {
+                let (l, r) = (l.borrow(), r.borrow());
+                l.len() == r.len()
+                    && l.iter()
+                        .zip(r.iter())
+                        .all(|(l, r)| l.eq_nested(r, depth + 1))
+            }
+            (AoType::AoMap(l), AoType::AoMap(r)) if depth > MAX_NESTING_DEPTH => Rc::ptr_eq(l, r),
+            (AoType::AoMap(l), AoType::AoMap(r)) => {
+                let (l, r) = (l.borrow(), r.borrow());
+                l.len() == r.len()
+                    && l.iter()
+                        .zip(r.iter())
+                        .all(|((lk, lv), (rk, rv))| lk == rk && lv.eq_nested(rv, depth + 1))
+            }
+            _ => false,
+        }
+    }
+
+    // Write the value, with arrays and maps deeper than `MAX_NESTING_DEPTH`
+    // elided.
+    fn fmt_nested(&self, f: &mut std::fmt::Formatter<'_>, depth: usize) -> std::fmt::Result {
+        match self {
+            AoType::AoArray(_) if depth > MAX_NESTING_DEPTH => write!(f, "[...]"),
+            AoType::AoArray(v) => {
+                write!(f, "[")?;
+                for (index, item) in v.borrow().iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    item.fmt_nested(f, depth + 1)?;
+                }
+                write!(f, "]")
+            }
+            AoType::AoMap(_) if depth > MAX_NESTING_DEPTH => write!(f, "{{...}}"),
+            AoType::AoMap(v) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in v.borrow().iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    Display::fmt(&AoType::from(key.clone()), f)?;
+                    write!(f, ": ")?;
+                    value.fmt_nested(f, depth + 1)?;
+                }
+                write!(f, "}}")
+            }
+            _ => Display::fmt(self, f),
+        }
+    }
+}
+
+impl PartialEq for AoType {
+    /// Compare two values of the same variant, arrays and maps by contents.
+    ///
+    /// Arrays and maps nested deeper than [`MAX_NESTING_DEPTH`] are compared
+    /// by identity.
+    fn eq(&self, other: &AoType) -> bool {
+        self.eq_nested(other, 1)
+    }
+}
+
+impl PartialOrd for AoType {
+    /// Compare two values of the same variant. Values of different variants
+    /// are not comparable, though the `gt`, `lt`, `ge` and `le` opcodes
+    /// promote an int compared with a float to float first.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use aoi::runtime::types::AoType;
+    ///
+    /// assert!(AoType::AoInt(1) < AoType::AoInt(2));
+    /// assert_eq!(AoType::AoInt(1).partial_cmp(&AoType::AoBool(true)), None);
+    /// ```
+    fn partial_cmp(&self, other: &AoType) -> Option<std::cmp::Ordering> {
+        match (self, other) {
+            (AoType::AoBool(l), AoType::AoBool(r)) => l.partial_cmp(r),
+            (AoType::AoInt(l), AoType::AoInt(r)) => l.partial_cmp(r),
+            (AoType::AoFloat(l), AoType::AoFloat(r)) => l.partial_cmp(r),
+            (AoType::AoPtr(l), AoType::AoPtr(r)) => l.partial_cmp(r),
+            (AoType::AoString(l), AoType::AoString(r)) => l.partial_cmp(r),
+            _ => None,
+        }
+    }
+}
+
+/// Values are written the way the assembler reads them. The alternate form
+/// `{:#}` writes pointers in hex, e.g. `0xdeadp`. Arrays and maps nested
+/// deeper than [`MAX_NESTING_DEPTH`] are written as `[...]` and `{...}`.
 impl Display for AoType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             AoType::AoBool(v) => write!(f, "{}", v),
             AoType::AoInt(v) => write!(f, "{}", v),
             AoType::AoFloat(v) => write!(f, "{}f", v),
+            AoType::AoPtr(v) if f.alternate() => write!(f, "{:#x}p", v),
             AoType::AoPtr(v) => write!(f, "{}p", v),
-            AoType::AoString(v) => write!(f, "\"{}\"", v),
+            AoType::AoString(v) => {
+                write!(f, "\"")?;
+                for c in v.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\t' => write!(f, "\\t")?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            AoType::AoArray(_) | AoType::AoMap(_) => self.fmt_nested(f, 1),
         }
     }
 }
@@ -75,7 +578,14 @@ macro_rules! impl_from {
     ( $at:ident, &str ) => {
         impl From<&str> for AoType {
             fn from(t: &str) -> AoType {
-                AoType::$at(t.to_string())
+                AoType::$at(t.into())
+            }
+        }
+    };
+    ( $at:ident, String ) => {
+        impl From<String> for AoType {
+            fn from(t: String) -> AoType {
+                AoType::$at(t.into())
             }
         }
     };
@@ -92,5 +602,470 @@ impl_from!(AoBool, bool);
 impl_from!(AoInt, i32);
 impl_from!(AoFloat, f32);
 impl_from!(AoPtr, u32);
+impl_from!(AoString, Rc<str>);
 impl_from!(AoString, String);
 impl_from!(AoString, &str);
+
+/// Error returned when converting an `AoType` of a different variant.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AoTypeError {
+    /// Name of the expected variant.
+    pub expected: &'static str,
+    /// The value that was converted.
+    pub found: AoType,
+}
+
+impl Display for AoTypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "expected {}, found {}", self.expected, self.found)
+    }
+}
+
+impl std::error::Error for AoTypeError {}
+
+/// Error returned when text is not a value as `AoType` displays it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseAoTypeError {
+    /// The text that was parsed.
+    pub input: String,
+}
+
+impl Display for ParseAoTypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid value {}", self.input)
+    }
+}
+
+impl std::error::Error for ParseAoTypeError {}
+
+// Undo the escaping of `"`, `\`, newlines and tabs done by `Display`,
+// failing on unknown escapes and unescaped quotes.
+fn unescape(s: &str) -> Option<String> {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.push(match chars.next()? {
+                '"' => '"',
+                '\\' => '\\',
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                _ => return None,
+            }),
+            '"' => return None,
+            c => result.push(c),
+        }
+    }
+    Some(result)
+}
+
+impl FromStr for AoType {
+    type Err = ParseAoTypeError;
+
+    /// Parse a bool, int, float, ptr or string written the way it is
+    /// displayed, e.g. `true`, `-3`, `2.5f`, `7p` or `"Aoi"`. Strings
+    /// escape `"`, `\`, newlines and tabs with a backslash, e.g.
+    /// `"a\"b\n"`.
+    ///
+    /// Arrays and maps have no text form.
+    ///
+    /// # Examples
+    /// ```
+    /// use aoi::AoType;
+    ///
+    /// assert_eq!("2.5f".parse(), Ok(AoType::AoFloat(2.5)));
+    /// assert_eq!("7p".parse(), Ok(AoType::AoPtr(7)));
+    /// assert!("2pf".parse::<AoType>().is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<AoType, ParseAoTypeError> {
+        let value = if s == "true" || s == "false" {
+            Some(AoType::AoBool(s == "true"))
+        } else if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+            unescape(&s[1..s.len() - 1]).map(AoType::from)
+        } else if let Some(v) = s.strip_suffix('f') {
+            v.parse().ok().map(AoType::AoFloat)
+        } else if let Some(v) = s.strip_suffix('p') {
+            v.parse().ok().map(AoType::AoPtr)
+        } else {
+            s.parse().ok().map(AoType::AoInt)
+        };
+        value.ok_or_else(|| ParseAoTypeError {
+            input: s.to_string(),
+        })
+    }
+}
+
+macro_rules! impl_try_from {
+    ( $at:ident, String ) => {
+        impl TryFrom<AoType> for String {
+            type Error = AoTypeError;
+
+            fn try_from(t: AoType) -> Result<String, AoTypeError> {
+                Rc::<str>::try_from(t).map(|v| v.to_string())
+            }
+        }
+    };
+    ( $at:ident, $rt:ty ) => {
+        impl TryFrom<AoType> for $rt {
+            type Error = AoTypeError;
+
+            fn try_from(t: AoType) -> Result<$rt, AoTypeError> {
+                match t {
+                    AoType::$at(v) => Ok(v),
+                    _ => Err(AoTypeError {
+                        expected: stringify!($at),
+                        found: t,
+                    }),
+                }
+            }
+        }
+    };
+}
+
+impl_try_from!(AoBool, bool);
+impl_try_from!(AoInt, i32);
+impl_try_from!(AoFloat, f32);
+impl_try_from!(AoPtr, u32);
+impl_try_from!(AoString, Rc<str>);
+impl_try_from!(AoString, String);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_type_tag() {
+        assert_eq!(AoType::AoBool(true).type_tag(), 1);
+        assert_eq!(AoType::AoInt(-1).type_tag(), 2);
+        assert_eq!(AoType::AoFloat(0.5).type_tag(), 3);
+        assert_eq!(AoType::AoPtr(7).type_tag(), 4);
+        assert_eq!(AoType::from("Aoi").type_tag(), 5);
+    }
+
+    #[test]
+    fn test_coerce_to() {
+        use crate::*;
+
+        let cases = [
+            (AoType::from("42"), AoTypeTag::Int, ao_program![csi]),
+            (AoType::from("x"), AoTypeTag::Int, ao_program![csi]),
+            (AoType::AoBool(true), AoTypeTag::Float, ao_program![csf]),
+            (AoType::AoFloat(3.7), AoTypeTag::Ptr, ao_program![csp]),
+            (AoType::AoFloat(-1.5), AoTypeTag::Ptr, ao_program![csp]),
+            (AoType::AoPtr(9), AoTypeTag::String, ao_program![css]),
+        ];
+        assert_eq!(
+            AoType::AoFloat(1.5).coerce_to(AoTypeTag::String),
+            Ok(AoType::from("1.5"))
+        );
+        assert_eq!(
+            AoType::AoPtr(9).coerce_to(AoTypeTag::String),
+            Ok(AoType::from("9"))
+        );
+        for (value, tag, program) in cases {
+            let mut vm = AoVM {
+                ca: value.clone(),
+                ..AoVM::default()
+            };
+            assert_eq!(vm.run(&program), AoStatus::Exit);
+            assert_eq!(value.coerce_to(tag), Ok(vm.ca));
+        }
+
+        assert_eq!(
+            AoType::from("42").coerce_to(AoTypeTag::Int),
+            Ok(AoType::AoInt(42))
+        );
+        assert_eq!(
+            AoType::AoFloat(3.7).coerce_to(AoTypeTag::Ptr),
+            Ok(AoType::AoPtr(3))
+        );
+        assert_eq!(
+            AoType::AoBool(false).coerce_to(AoTypeTag::Bool),
+            Ok(AoType::AoBool(false))
+        );
+        assert_eq!(
+            AoType::AoInt(1).coerce_to(AoTypeTag::Bool),
+            Err(AoStatus::ConversionError("1 to bool".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_accessors() {
+        assert_eq!(AoType::AoBool(true).as_bool(), Some(true));
+        assert_eq!(AoType::AoInt(1).as_bool(), None);
+        assert_eq!(AoType::AoInt(-3).as_int(), Some(-3));
+        assert_eq!(AoType::AoPtr(3).as_int(), None);
+        assert_eq!(AoType::AoFloat(1.5).as_float(), Some(1.5));
+        assert_eq!(AoType::AoInt(1).as_float(), None);
+        assert_eq!(AoType::AoPtr(7).as_ptr(), Some(7));
+        assert_eq!(AoType::AoInt(7).as_ptr(), None);
+        assert_eq!(AoType::from("Aoi").as_str(), Some("Aoi"));
+        assert_eq!(AoType::AoBool(false).as_str(), None);
+    }
+
+    #[test]
+    fn test_partial_ord() {
+        assert!(AoType::AoInt(1) < AoType::AoInt(2));
+        assert!(AoType::AoBool(false) < AoType::AoBool(true));
+        assert!(AoType::AoFloat(2.5) >= AoType::AoFloat(2.5));
+        assert!(AoType::AoPtr(3) > AoType::AoPtr(1));
+        assert!(AoType::from("Aoi") < AoType::from("aoi"));
+        assert!(AoType::from("ab") < AoType::from("abc"));
+        assert_eq!(AoType::AoInt(1).partial_cmp(&AoType::AoBool(true)), None);
+        assert_eq!(
+            AoType::AoFloat(f32::NAN).partial_cmp(&AoType::AoFloat(1.0)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_string_shared() {
+        use crate::opcodes::OpcodeArgType;
+        use crate::*;
+
+        let program = ao_program![
+            mov ca,"Aoi"
+            push ca
+            push ca
+            mov cb,ds
+        ];
+        let mut vm = AoVM::default();
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+
+        let text = match program[0].get_args() {
+            OpcodeArgType::AoArg2(_, AoArg::Imm(AoType::AoString(text))) => text,
+            _ => unreachable!(),
+        };
+        for value in [&vm.ca, &vm.cb, &vm.ds[0], &vm.ds[1]] {
+            match value {
+                AoType::AoString(v) => assert!(Rc::ptr_eq(v, &text)),
+                _ => panic!("{} is not a string", value),
+            }
+        }
+        // the immediate, the copy from `get_args`, CA, CB and both slots
+        assert_eq!(Rc::strong_count(&text), 6);
+    }
+
+    #[test]
+    fn test_array() {
+        let array = |items: Vec<AoType>| AoType::AoArray(Rc::new(RefCell::new(items)));
+
+        let a = array(vec![AoType::AoInt(1), AoType::AoInt(2), AoType::AoInt(3)]);
+        let b = array(vec![AoType::AoInt(1), AoType::AoInt(2), AoType::AoInt(3)]);
+        assert_eq!(a, b);
+        assert_ne!(a, array(vec![AoType::AoInt(1)]));
+        assert_eq!(a.type_tag(), 6);
+        assert_eq!(a.to_string(), "[1, 2, 3]");
+        assert_eq!(
+            array(vec![AoType::from("a"), array(vec![]), AoType::AoFloat(1.5)]).to_string(),
+            "[\"a\", [], 1.5f]"
+        );
+
+        assert_eq!(
+            a.coerce_to(AoTypeTag::String),
+            Ok(AoType::from("[1, 2, 3]"))
+        );
+        assert_eq!(
+            a.coerce_to(AoTypeTag::Int),
+            Err(AoStatus::ConversionError("[1, 2, 3] to int".to_string()))
+        );
+        assert_eq!(
+            AoType::AoInt(1).coerce_to(AoTypeTag::Array),
+            Err(AoStatus::ConversionError("1 to array".to_string()))
+        );
+
+        let inner = array(vec![AoType::AoInt(0)]);
+        let outer = array(vec![inner.clone(), inner.clone()]);
+        let copy = outer.deep_clone();
+        assert_eq!(copy, outer);
+        if let AoType::AoArray(items) = &inner {
+            items.borrow_mut()[0] = AoType::AoInt(1);
+        }
+        assert_eq!(outer.to_string(), "[[1], [1]]");
+        assert_eq!(copy.to_string(), "[[0], [0]]");
+        if let AoType::AoArray(items) = &copy {
+            let items = items.borrow();
+            match (&items[0], &items[1]) {
+                (AoType::AoArray(l), AoType::AoArray(r)) => assert!(Rc::ptr_eq(l, r)),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_map() {
+        let map = |entries: Vec<(AoMapKey, AoType)>| {
+            AoType::AoMap(Rc::new(RefCell::new(entries.into_iter().collect())))
+        };
+
+        let a = map(vec![
+            (AoMapKey::String("b".into()), AoType::AoInt(2)),
+            (AoMapKey::Bool(true), AoType::AoFloat(0.5)),
+            (AoMapKey::Ptr(3), AoType::from("p")),
+        ]);
+        let b = map(vec![
+            (AoMapKey::Ptr(3), AoType::from("p")),
+            (AoMapKey::Bool(true), AoType::AoFloat(0.5)),
+            (AoMapKey::String("b".into()), AoType::AoInt(2)),
+        ]);
+        assert_eq!(a, b);
+        assert_ne!(a, map(vec![]));
+        assert_eq!(a.type_tag(), 7);
+        assert_eq!(a.to_string(), "{true: 0.5f, 3p: \"p\", \"b\": 2}");
+        assert_eq!(map(vec![]).to_string(), "{}");
+
+        assert_eq!(
+            AoMapKey::new(&AoType::from("b")),
+            Some(AoMapKey::String("b".into()))
+        );
+        assert_eq!(AoMapKey::new(&AoType::AoFloat(1.0)), None);
+        assert_eq!(AoMapKey::new(&a), None);
+        assert_eq!(AoType::from(AoMapKey::Ptr(3)), AoType::AoPtr(3));
+    }
+
+    #[test]
+    fn test_nesting() {
+        let array = |items: Vec<AoType>| AoType::AoArray(Rc::new(RefCell::new(items)));
+
+        // nested far past the limit by filling the outer arrays first
+        let root = array(vec![AoType::AoInt(0)]);
+        let mut inner = root.clone();
+        for _ in 0..1000 {
+            let next = array(vec![AoType::AoInt(0)]);
+            assert!(inner.can_hold(&next));
+            if let AoType::AoArray(v) = &inner {
+                v.borrow_mut()[0] = next.clone();
+            }
+            inner = next;
+        }
+        assert!(root.is_too_deep());
+        assert!(!inner.is_too_deep());
+
+        let text = root.to_string();
+        assert!(text.starts_with("[[[["));
+        assert!(text.contains("[...]"));
+        assert_eq!(root, root.clone());
+        assert_ne!(root, array(vec![AoType::AoInt(0)]));
+        assert_eq!(
+            root.coerce_to(AoTypeTag::String).unwrap(),
+            AoType::from(text)
+        );
+
+        // cycles are refused, including through other arrays
+        assert!(!inner.can_hold(&inner));
+        assert!(!inner.can_hold(&root));
+        assert!(!inner.can_hold(&array(vec![AoType::AoInt(1), root.clone()])));
+        assert!(!AoType::AoInt(1).can_hold(&AoType::AoInt(2)));
+
+        // values that would nest too deep are refused
+        let mut deep = AoType::AoInt(0);
+        for _ in 0..MAX_NESTING_DEPTH - 1 {
+            deep = array(vec![deep]);
+        }
+        let outer = array(vec![AoType::AoInt(0)]);
+        assert!(outer.can_hold(&deep));
+        assert!(!outer.can_hold(&array(vec![deep])));
+    }
+
+    #[test]
+    fn test_try_from() {
+        assert_eq!(bool::try_from(AoType::AoBool(true)), Ok(true));
+        assert_eq!(i32::try_from(AoType::AoInt(-3)), Ok(-3));
+        assert_eq!(f32::try_from(AoType::AoFloat(1.5)), Ok(1.5));
+        assert_eq!(u32::try_from(AoType::AoPtr(7)), Ok(7));
+        assert_eq!(String::try_from(AoType::from("Aoi")), Ok("Aoi".to_string()));
+
+        let err = i32::try_from(AoType::AoPtr(7)).unwrap_err();
+        assert_eq!(
+            err,
+            AoTypeError {
+                expected: "AoInt",
+                found: AoType::AoPtr(7),
+            }
+        );
+        assert_eq!(err.to_string(), "expected AoInt, found 7p");
+    }
+
+    #[test]
+    fn test_display_alternate() {
+        assert_eq!(format!("{}", AoType::AoPtr(0xDEAD)), "57005p");
+        assert!(format!("{:#}", AoType::AoPtr(0xDEAD)).contains("0xdead"));
+        assert_eq!(format!("{:#}", AoType::AoPtr(u32::MAX)), "0xffffffffp");
+        assert_eq!(format!("{:#}", AoType::AoInt(255)), "255");
+
+        let array = AoType::AoArray(Rc::new(RefCell::new(vec![
+            AoType::AoPtr(16),
+            AoType::from("a"),
+        ])));
+        assert_eq!(format!("{}", array), "[16p, \"a\"]");
+        assert_eq!(format!("{:#}", array), "[0x10p, \"a\"]");
+    }
+
+    #[test]
+    fn test_from_str() {
+        let values = [
+            AoType::AoBool(true),
+            AoType::AoBool(false),
+            AoType::AoInt(-42),
+            AoType::AoFloat(2.2),
+            AoType::AoFloat(-3.0),
+            AoType::AoPtr(2),
+            AoType::from("Hello, Aoi"),
+            AoType::from(""),
+            AoType::from("say \"hi\"\\n\nnext\r\tline"),
+        ];
+        for value in values {
+            assert_eq!(value.to_string().parse(), Ok(value));
+        }
+        assert_eq!(
+            AoType::from("a\"b\\c\nd").to_string(),
+            "\"a\\\"b\\\\c\\nd\""
+        );
+        assert_eq!("1e3f".parse(), Ok(AoType::AoFloat(1000.0)));
+
+        for text in [
+            "2pf", "2fp", "1.5", "-1p", "f", "p", "\"", "True", "0x10", " 1", "", "\"a\"b\"",
+            "\"a\\\"", "\"\\q\"",
+        ] {
+            assert_eq!(
+                text.parse::<AoType>(),
+                Err(ParseAoTypeError {
+                    input: text.to_string()
+                }),
+                "{}",
+                text
+            );
+        }
+        let err = "2pf".parse::<AoType>().unwrap_err();
+        assert_eq!(err.to_string(), "invalid value 2pf");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let values = vec![
+            AoType::AoBool(true),
+            AoType::AoInt(-7),
+            AoType::AoFloat(2.5),
+            AoType::AoPtr(42),
+            AoType::from("Hello, \"Aoi\""),
+        ];
+        let json = serde_json::to_string(&values).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"AoBool":true},{"AoInt":-7},{"AoFloat":2.5},{"AoPtr":42},{"AoString":"Hello, \"Aoi\""}]"#
+        );
+        let parsed: Vec<AoType> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, values);
+
+        let map = AoType::AoMap(Rc::new(RefCell::new(BTreeMap::from([(
+            AoMapKey::Int(1),
+            AoType::AoBool(false),
+        )]))));
+        let json = serde_json::to_string(&map).unwrap();
+        assert_eq!(json, r#"{"AoMap":[[{"AoInt":1},{"AoBool":false}]]}"#);
+        assert_eq!(serde_json::from_str::<AoType>(&json).unwrap(), map);
+        assert!(serde_json::from_str::<AoType>(r#"{"AoMap":[[{"AoFloat":1.0},1]]}"#).is_err());
+    }
+}
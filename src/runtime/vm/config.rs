@@ -0,0 +1,27 @@
+/// Storage used for the VM memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryBackend {
+    /// Sparse memory split into sections, pages and chips of 256 cells.
+    Paged,
+}
+
+/// Snapshot of the limits and modes active on a VM.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VmConfig {
+    /// Maximum number of values on the data stack.
+    pub max_data_stack: usize,
+    /// Maximum depth of the call stack.
+    pub max_call_stack: usize,
+    /// Maximum length of an array created by `newarr`.
+    pub max_array_len: usize,
+    /// Gas available for execution, `None` if not metered.
+    pub gas_limit: Option<u64>,
+    /// Storage used for the memory.
+    pub memory_backend: MemoryBackend,
+    /// Maximum number of allocated memory cells, `None` if unlimited.
+    pub max_memory_cells: Option<usize>,
+    /// Whether every step is checked so that no program can panic the VM.
+    pub safe_mode: bool,
+    /// Whether absolute jumps and calls are checked against the program.
+    pub strict_jumps: bool,
+}
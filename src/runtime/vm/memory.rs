@@ -1,7 +1,9 @@
 use crate::AoType;
 
+#[derive(Clone)]
 struct Chip {
-    data: Vec<AoType>,
+    // `None` marks cells below the highest written index that were never set
+    data: Vec<Option<AoType>>,
 }
 
 impl Chip {
@@ -12,21 +14,28 @@ impl Chip {
     }
 
     fn get(&self, index: u8) -> AoType {
-        if index as usize >= self.data.len() {
-            AoType::default()
-        } else {
-            self.data[index as usize].clone()
+        match self.data.get(index as usize) {
+            Some(Some(value)) => value.clone(),
+            _ => AoType::default(),
         }
     }
 
     fn set(&mut self, index: u8, value: AoType) {
         while index as usize >= self.data.len() {
-            self.data.push(AoType::default());
+            self.data.push(None);
         }
-        self.data[index as usize] = value;
+        self.data[index as usize] = Some(value);
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (u32, AoType)> + '_ {
+        self.data
+            .iter()
+            .enumerate()
+            .filter_map(|(i, value)| value.clone().map(|value| (i as u32, value)))
     }
 }
 
+#[derive(Clone)]
 struct Page {
     chips: Vec<Option<Box<Chip>>>,
 }
@@ -39,12 +48,9 @@ impl Page {
     }
 
     fn get(&self, index: u16) -> AoType {
-        let chip_index = (index >> 8) as usize;
-        if chip_index >= self.chips.len() {
-            AoType::default()
-        } else {
-            let chip = self.chips[chip_index].as_ref().unwrap();
-            chip.get((index & 0xff) as u8)
+        match self.chips.get((index >> 8) as usize) {
+            Some(Some(chip)) => chip.get((index & 0xff) as u8),
+            _ => AoType::default(),
         }
     }
 
@@ -62,8 +68,20 @@ impl Page {
             .unwrap()
             .set((index & 0xFF) as u8, value);
     }
+
+    fn iter(&self) -> impl Iterator<Item = (u32, AoType)> + '_ {
+        self.chips
+            .iter()
+            .enumerate()
+            .filter_map(|(i, chip)| chip.as_ref().map(|chip| (i as u32, chip)))
+            .flat_map(|(i, chip)| {
+                chip.iter()
+                    .map(move |(index, value)| (i << 8 | index, value))
+            })
+    }
 }
 
+#[derive(Clone)]
 struct Section {
     pages: Vec<Option<Box<Page>>>,
 }
@@ -76,12 +94,9 @@ impl Section {
     }
 
     fn get(&self, index: u32) -> AoType {
-        let page_index = ((index >> 16) & 0xFF) as usize;
-        if page_index >= self.pages.len() {
-            AoType::default()
-        } else {
-            let page = self.pages[page_index].as_ref().unwrap();
-            page.get((index & 0xFFFF) as u16)
+        match self.pages.get(((index >> 16) & 0xFF) as usize) {
+            Some(Some(page)) => page.get((index & 0xFFFF) as u16),
+            _ => AoType::default(),
         }
     }
 
@@ -99,31 +114,84 @@ impl Section {
             .unwrap()
             .set((index & 0xFFFF) as u16, value);
     }
+
+    fn iter(&self) -> impl Iterator<Item = (u32, AoType)> + '_ {
+        self.pages
+            .iter()
+            .enumerate()
+            .filter_map(|(i, page)| page.as_ref().map(|page| (i as u32, page)))
+            .flat_map(|(i, page)| {
+                page.iter()
+                    .map(move |(index, value)| (i << 16 | index, value))
+            })
+    }
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct Memory {
     sections: Vec<Option<Box<Section>>>,
+    cells: usize,
+    max_cells: Option<usize>,
 }
 
 impl Memory {
     pub fn new() -> Self {
         Memory {
             sections: Vec::with_capacity(256),
+            cells: 0,
+            max_cells: None,
+        }
+    }
+
+    /// Limit the number of allocated cells, `None` for no limit.
+    pub fn set_max_cells(&mut self, max_cells: Option<usize>) {
+        self.max_cells = max_cells;
+    }
+
+    pub fn max_cells(&self) -> Option<usize> {
+        self.max_cells
+    }
+
+    /// Free all cells, keeping the limit.
+    pub fn clear(&mut self) {
+        self.sections.clear();
+        self.cells = 0;
+    }
+
+    fn chip_len(&self, index: u32) -> usize {
+        let section = match self.sections.get((index >> 24) as usize) {
+            Some(Some(section)) => section,
+            _ => return 0,
+        };
+        let page = match section.pages.get(((index >> 16) & 0xFF) as usize) {
+            Some(Some(page)) => page,
+            _ => return 0,
+        };
+        match page.chips.get(((index >> 8) & 0xFF) as usize) {
+            Some(Some(chip)) => chip.data.len(),
+            _ => 0,
         }
     }
 
     pub fn get(&self, index: u32) -> AoType {
-        let section_index = ((index >> 24) & 0xFF) as usize;
-        if section_index >= self.sections.len() {
-            AoType::default()
-        } else {
-            let section = self.sections[section_index].as_ref().unwrap();
-            section.get(index)
+        match self.sections.get(((index >> 24) & 0xFF) as usize) {
+            Some(Some(section)) => section.get(index),
+            _ => AoType::default(),
         }
     }
 
-    pub fn set(&mut self, index: u32, value: AoType) {
+    /// Write a value, returning `false` without writing if the cells it
+    /// needs would exceed the limit.
+    pub fn set(&mut self, index: u32, value: AoType) -> bool {
+        let needed = (index & 0xFF) as usize + 1;
+        let grown = needed.saturating_sub(self.chip_len(index));
+        if let Some(max_cells) = self.max_cells {
+            if self.cells + grown > max_cells {
+                return false;
+            }
+        }
+        self.cells += grown;
+
         let section_index = ((index >> 24) & 0xFF) as usize;
         while section_index >= self.sections.len() {
             self.sections.push(None);
@@ -136,5 +204,25 @@ impl Memory {
             .as_mut()
             .unwrap()
             .set(index & 0xFFFFFF, value);
+        true
+    }
+
+    /// Number of cells allocated by writes, including the unwritten cells
+    /// below the highest written address of each 256-cell chip.
+    pub fn allocated_cells(&self) -> usize {
+        self.cells
+    }
+
+    /// Iterate over the cells that were written, in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, AoType)> + '_ {
+        self.sections
+            .iter()
+            .enumerate()
+            .filter_map(|(i, section)| section.as_ref().map(|section| (i as u32, section)))
+            .flat_map(|(i, section)| {
+                section
+                    .iter()
+                    .map(move |(index, value)| (i << 24 | index, value))
+            })
     }
 }
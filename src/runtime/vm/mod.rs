@@ -1,11 +1,45 @@
+pub mod config;
 pub mod memory;
+mod safe;
+pub mod snapshot;
 
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use super::AoFault;
 use super::AoOpcode;
 use super::AoStatus;
 use super::AoType;
+use super::InterruptResult;
+use super::InterruptTable;
+use super::SharedInterrupt;
+use super::SourceSpan;
+use config::{MemoryBackend, VmConfig};
 use memory::Memory;
 
+/// Callback invoked before each executed opcode, shared between clones of a
+/// VM.
+pub type SharedTrace = Rc<RefCell<dyn FnMut(u32, &dyn AoOpcode, &AoVM)>>;
+
+/// Default maximum number of values on the data stack.
+pub const MAX_DATA_STACK: usize = 1000000;
+/// Default maximum depth of the call stack.
+pub const MAX_CALL_STACK: usize = 100000;
+/// Default maximum length of an array created by `newarr`.
+pub const MAX_ARRAY_LEN: usize = 1000000;
+/// Memory cells `memcpy` and `memset` may fill up to in safe mode when
+/// memory is not limited.
+pub const MAX_SAFE_MEMORY_CELLS: usize = 1 << 20;
+
+/// Seed of the random number generator of a new VM.
+pub const DEFAULT_RNG_SEED: u64 = 0x2545_F491_4F6C_DD1D;
+
 /// Aoi VM.
+///
+/// Cloning the VM copies all registers, stacks and memory, including the
+/// arrays and maps in them, while the interrupt handlers are shared between
+/// the clones.
 pub struct AoVM {
     pub pc: u32,
     pub dp: u32,
@@ -18,39 +52,99 @@ pub struct AoVM {
 
     pub ds: Vec<AoType>,
     pub mem: Memory,
+    /// Global variables, accessed through `AoArg::GVS` at index `mp`.
+    pub gvs: Vec<AoType>,
+
+    /// Gas left for execution, only consumed when `gas_limit` is set.
+    pub gas: u64,
+    pub gas_limit: Option<u64>,
+
+    /// Maximum number of values on the data stack.
+    pub max_stack: usize,
+    /// Maximum number of calls that have not returned yet.
+    pub max_call_depth: usize,
+    /// Maximum length of an array created by `newarr`.
+    pub max_array_len: usize,
 
-    pub interrupt: fn(u8, Vec<AoType>) -> Option<AoType>,
+    /// Check every step for out of range stack accesses, broken frames and
+    /// integer division by zero, returning a status instead of panicking.
+    /// `memcpy` and `memset` longer than the cells left under the memory
+    /// limit, or under [`MAX_SAFE_MEMORY_CELLS`] without one, fail with
+    /// `AoStatus::OutOfMemory` up front instead of allocating.
+    ///
+    /// Costs one extra check per executed opcode, so it is off by default,
+    /// but [`validate_and_run`](crate::validate_and_run) turns it on.
+    pub safe_mode: bool,
+    /// Return `AoStatus::InvalidJumpTarget` from `jmpa`, `jta`, `jfa`,
+    /// `jmptable` and `call` when a target is not an instruction of the
+    /// program, instead of exiting or running the wrong code.
+    pub strict_jumps: bool,
+
+    pub interrupt: SharedInterrupt,
+    /// Handlers looked up by id, used instead of `interrupt` when present.
+    pub interrupt_table: Option<Rc<RefCell<InterruptTable>>>,
+    /// Called with the pc and the opcode before it is executed.
+    pub trace: Option<SharedTrace>,
+    /// Number of executed opcodes by id, counted once profiling is enabled.
+    pub profile: Option<HashMap<u8, u64>>,
+    /// State of the xorshift generator used by `rand` and `randf`, never 0.
+    pub rng: u64,
+    /// Number of opcodes executed since the last reset.
+    pub steps: u64,
+    /// Source position of every instruction of the program, by pc.
+    pub spans: Option<Rc<[SourceSpan]>>,
 }
 
 impl AoVM {
-    fn default_interrupt(id: u8, args: Vec<AoType>) -> Option<AoType> {
+    fn default_interrupt(id: u8, args: Vec<AoType>) -> InterruptResult {
         match id {
             1 => {
-                match &args[0] {
-                    AoType::AoBool(v) => print!("{}", v),
-                    AoType::AoInt(v) => print!("{}", v),
-                    AoType::AoFloat(v) => print!("{}", v),
-                    AoType::AoString(v) => print!("{}", v),
+                match args.first() {
+                    Some(AoType::AoBool(v)) => print!("{}", v),
+                    Some(AoType::AoInt(v)) => print!("{}", v),
+                    Some(AoType::AoFloat(v)) => print!("{}", v),
+                    Some(AoType::AoString(v)) => print!("{}", v),
                     _ => (),
                 }
-                None
+                Ok(None)
             }
             2 => {
-                match &args[0] {
-                    AoType::AoBool(v) => println!("{}", v),
-                    AoType::AoInt(v) => println!("{}", v),
-                    AoType::AoFloat(v) => println!("{}", v),
-                    AoType::AoString(v) => println!("{}", v),
+                match args.first() {
+                    Some(AoType::AoBool(v)) => println!("{}", v),
+                    Some(AoType::AoInt(v)) => println!("{}", v),
+                    Some(AoType::AoFloat(v)) => println!("{}", v),
+                    Some(AoType::AoString(v)) => println!("{}", v),
                     _ => (),
                 }
-                None
+                Ok(None)
             }
-            _ => None,
+            _ => Ok(None),
         }
     }
 
-    /// Create a new AoVM.
-    pub fn new(int: fn(u8, Vec<AoType>) -> Option<AoType>) -> AoVM {
+    /// Create a new AoVM with `gv_count` global variables.
+    pub fn new(int: fn(u8, Vec<AoType>) -> InterruptResult, gv_count: usize) -> AoVM {
+        let mut vm = AoVM::with_interrupt(int);
+        vm.gvs = vec![AoType::default(); gv_count];
+        vm
+    }
+
+    /// Create a new AoVM with a closure as interrupt handler.
+    ///
+    /// # Examples
+    /// ```
+    /// use aoi::runtime::vm::AoVM;
+    ///
+    /// let mut count = 0;
+    /// let vm = AoVM::with_interrupt(move |_, _| {
+    ///     count += 1;
+    ///     Ok(None)
+    /// });
+    /// ```
+    pub fn with_interrupt<F>(int: F) -> AoVM
+    where
+        F: FnMut(u8, Vec<AoType>) -> InterruptResult + 'static,
+    {
         AoVM {
             pc: 0,
             dp: 0,
@@ -63,18 +157,144 @@ impl AoVM {
 
             ds: Vec::new(),
             mem: Memory::new(),
+            gvs: Vec::new(),
+
+            gas: 0,
+            gas_limit: None,
 
-            interrupt: int,
+            max_stack: MAX_DATA_STACK,
+            max_call_depth: MAX_CALL_STACK,
+            max_array_len: MAX_ARRAY_LEN,
+
+            safe_mode: false,
+            strict_jumps: false,
+
+            interrupt: Rc::new(RefCell::new(int)),
+            interrupt_table: None,
+            trace: None,
+            profile: None,
+            rng: DEFAULT_RNG_SEED,
+            steps: 0,
+            spans: None,
         }
     }
 
-    /// Create a new AoVM with default interrupt.
-    pub fn default() -> AoVM {
-        AoVM::new(AoVM::default_interrupt)
+    /// Create a new AoVM dispatching interrupts through a table.
+    pub fn with_interrupt_table(table: InterruptTable) -> AoVM {
+        let mut vm = AoVM::with_interrupt(|_, _| Ok(None));
+        vm.interrupt_table = Some(Rc::new(RefCell::new(table)));
+        vm
+    }
+
+    /// Limit the number of values on the data stack, [`MAX_DATA_STACK`] by
+    /// default.
+    ///
+    /// Pushes past the limit fail with `AoStatus::DataStackOverflow`
+    /// carrying the limit. Values already on the stack are kept.
+    pub fn set_max_stack(&mut self, max_stack: usize) {
+        self.max_stack = max_stack;
+    }
+
+    /// Limit the depth of the call stack, [`MAX_CALL_STACK`] by default.
+    ///
+    /// Calls past the limit fail with `AoStatus::CallStackOverflow` carrying
+    /// the limit.
+    pub fn set_max_call_depth(&mut self, max_call_depth: usize) {
+        self.max_call_depth = max_call_depth;
+    }
+
+    /// Attach the source position of every instruction of the program, by
+    /// pc.
+    ///
+    /// Errors raised by an instruction with a span are then returned as
+    /// `AoStatus::AtSource`, wrapping the original status. The spans are
+    /// kept on reset.
+    ///
+    /// # Examples
+    /// ```
+    /// use aoi::*;
+    ///
+    /// let mut vm = AoVM::default();
+    /// vm.set_source_spans(vec![SourceSpan::new(1, 1), SourceSpan::new(2, 5)]);
+    /// let status = vm.run(&ao_program![mov ca,1 sub "one"]);
+    /// assert_eq!(
+    ///     status.to_string(),
+    ///     "Invalid Operation(1 - \"one\") at line 2, column 5"
+    /// );
+    /// ```
+    pub fn set_source_spans(&mut self, spans: Vec<SourceSpan>) {
+        self.spans = Some(spans.into());
+    }
+
+    /// Source position of the instruction at `pc`, if spans are attached.
+    pub fn source_span(&self, pc: u32) -> Option<SourceSpan> {
+        self.spans.as_ref()?.get(pc as usize).copied()
+    }
+
+    /// Limit the length of arrays created by `newarr`, [`MAX_ARRAY_LEN`] by
+    /// default.
+    ///
+    /// Longer arrays fail with `AoStatus::OutOfMemory` without allocating.
+    pub fn set_max_array_len(&mut self, max_array_len: usize) {
+        self.max_array_len = max_array_len;
+    }
+
+    /// Number of opcodes executed since the VM was created or reset.
+    pub fn step_count(&self) -> u64 {
+        self.steps
+    }
+
+    /// Restart the random number generator from `seed`, so that `rand` and
+    /// `randf` repeat the same sequence for the same seed.
+    ///
+    /// A seed of 0 is replaced by [`DEFAULT_RNG_SEED`], since xorshift never
+    /// leaves the zero state.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = if seed == 0 { DEFAULT_RNG_SEED } else { seed };
+    }
+
+    /// Advance the random number generator and return its new state.
+    pub fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    /// Start counting executed opcodes by id, keeping the counts so far if
+    /// profiling is already enabled.
+    pub fn enable_profiling(&mut self) {
+        self.profile.get_or_insert_with(HashMap::new);
+    }
+
+    /// Number of executed opcodes by id, or `None` if profiling is not
+    /// enabled.
+    ///
+    /// # Examples
+    /// ```
+    /// use aoi::*;
+    ///
+    /// let mut vm = AoVM::default();
+    /// vm.enable_profiling();
+    /// vm.run(&ao_program![nop nop inc]);
+    /// let report = vm.profile_report().unwrap();
+    /// assert_eq!(report[&0x00], 2);
+    /// assert_eq!(report[&0x35], 1);
+    /// ```
+    pub fn profile_report(&self) -> Option<&HashMap<u8, u64>> {
+        self.profile.as_ref()
+    }
+
+    /// Reserve room for at least `additional` more values on the data stack,
+    /// so that early pushes do not reallocate it.
+    pub fn reserve_stack(&mut self, additional: usize) {
+        self.ds.reserve(additional);
     }
 
     /// Push a value to the data stack.
     ///
+    /// Returns `false` without pushing if the stack is full.
+    ///
     /// # Examples
     /// ```
     /// use aoi::runtime::vm::AoVM;
@@ -86,7 +306,7 @@ impl AoVM {
     /// assert_eq!(vm.ds[0], AoType::AoInt(1));
     /// ```
     pub fn push(&mut self, value: AoType) -> bool {
-        if self.ds.len() > 1000000 {
+        if self.ds.len() >= self.max_stack {
             return false;
         }
 
@@ -94,6 +314,24 @@ impl AoVM {
         true
     }
 
+    /// Push values to the data stack in order.
+    ///
+    /// Returns `false` if the stack overflows, leaving the values pushed
+    /// before the overflow on the stack.
+    ///
+    /// # Examples
+    /// ```
+    /// use aoi::runtime::vm::AoVM;
+    /// use aoi::runtime::types::AoType;
+    ///
+    /// let mut vm = AoVM::default();
+    /// vm.push_all(&[AoType::AoInt(1), AoType::AoInt(2)]);
+    /// assert_eq!(vm.ds, vec![AoType::AoInt(1), AoType::AoInt(2)]);
+    /// ```
+    pub fn push_all(&mut self, values: &[AoType]) -> bool {
+        values.iter().all(|value| self.push(value.clone()))
+    }
+
     /// Pop a value from the data stack.
     ///
     /// # Examples
@@ -130,21 +368,368 @@ impl AoVM {
         self.ds.last()
     }
 
+    /// Get the whole data stack, bottom first.
+    ///
+    /// # Examples
+    /// ```
+    /// use aoi::runtime::vm::AoVM;
+    /// use aoi::runtime::types::AoType;
+    ///
+    /// let mut vm = AoVM::default();
+    /// vm.push(AoType::AoInt(1));
+    /// vm.push(AoType::AoInt(2));
+    ///
+    /// assert_eq!(vm.stack(), [AoType::AoInt(1), AoType::AoInt(2)]);
+    /// ```
+    pub fn stack(&self) -> &[AoType] {
+        &self.ds
+    }
+
+    /// Copy the data stack, bottom first.
+    pub fn stack_snapshot(&self) -> Vec<AoType> {
+        self.ds.clone()
+    }
+
+    /// Get an argument of the current stack frame, as `arg offset` followed
+    /// by reading `ds` would, or `None` if it is out of range.
+    ///
+    /// A frame is set up by pushing the caller's `dsb`, then the arguments,
+    /// then running `cnf` with the argument count, so argument 0 is the
+    /// first one pushed.
+    ///
+    /// # Examples
+    /// ```
+    /// use aoi::*;
+    ///
+    /// let mut vm = AoVM::default();
+    /// vm.run(&ao_program![push dsb push 10 push 20 cnf 2]);
+    /// assert_eq!(vm.frame_arg(1), Some(&AoType::AoInt(20)));
+    /// assert_eq!(vm.frame_arg(2), None);
+    /// ```
+    pub fn frame_arg(&self, offset: u32) -> Option<&AoType> {
+        let index = self.dsb.checked_add(offset)?;
+        self.ds.get(index as usize)
+    }
+
+    /// Get the values of the current stack frame, the arguments followed by
+    /// any values pushed since `cnf`.
+    pub fn frame_args(&self) -> &[AoType] {
+        self.ds.get(self.dsb as usize..).unwrap_or_default()
+    }
+
+    /// Read the value at `addr` in memory.
+    pub fn mem_get(&self, addr: u32) -> AoType {
+        self.mem.get(addr)
+    }
+
+    /// Write a value to `addr` in memory.
+    ///
+    /// Returns `false` if the write would exceed the memory limit.
+    pub fn mem_set(&mut self, addr: u32, value: AoType) -> bool {
+        self.mem.set(addr, value)
+    }
+
+    /// Write consecutive values to memory, starting at `base`.
+    ///
+    /// Stops and returns `false` at the first write exceeding the memory
+    /// limit.
+    ///
+    /// # Examples
+    /// ```
+    /// use aoi::runtime::vm::AoVM;
+    /// use aoi::runtime::types::AoType;
+    ///
+    /// let mut vm = AoVM::default();
+    /// vm.mem_load_slice(10, &[AoType::AoInt(1), AoType::AoInt(2)]);
+    /// assert_eq!(vm.mem_get(11), AoType::AoInt(2));
+    /// ```
+    pub fn mem_load_slice(&mut self, base: u32, values: &[AoType]) -> bool {
+        values
+            .iter()
+            .enumerate()
+            .all(|(offset, value)| self.mem_set(base.wrapping_add(offset as u32), value.clone()))
+    }
+
+    /// Limit the number of memory cells the program can allocate.
+    ///
+    /// Writes that would allocate past the limit fail with
+    /// `AoStatus::OutOfMemory`. `None` removes the limit.
+    pub fn set_max_memory_cells(&mut self, max_cells: Option<usize>) {
+        self.mem.set_max_cells(max_cells);
+    }
+
+    /// Number of memory cells allocated by the program, see
+    /// [`Memory::allocated_cells`].
+    pub fn memory_stats(&self) -> usize {
+        self.mem.allocated_cells()
+    }
+
+    fn stack_as<T>(&self, convert: fn(&AoType) -> Option<T>) -> Result<Vec<T>, AoStatus> {
+        self.ds
+            .iter()
+            .map(|value| convert(value).ok_or(AoStatus::BadDataStack))
+            .collect()
+    }
+
+    /// Convert the data stack to a vector of integers.
+    ///
+    /// Returns `AoStatus::BadDataStack` if any value is not an integer.
+    ///
+    /// # Examples
+    /// ```
+    /// use aoi::runtime::status::AoStatus;
+    /// use aoi::runtime::types::AoType;
+    /// use aoi::runtime::vm::AoVM;
+    ///
+    /// let mut vm = AoVM::default();
+    /// vm.push(AoType::AoInt(1));
+    /// vm.push(AoType::AoInt(2));
+    /// assert_eq!(vm.stack_as_ints(), Ok(vec![1, 2]));
+    ///
+    /// vm.push(AoType::AoBool(true));
+    /// assert_eq!(vm.stack_as_ints(), Err(AoStatus::BadDataStack));
+    /// ```
+    pub fn stack_as_ints(&self) -> Result<Vec<i32>, AoStatus> {
+        self.stack_as(AoType::as_int)
+    }
+
+    /// Convert the data stack to a vector of floats.
+    ///
+    /// Returns `AoStatus::BadDataStack` if any value is not a float.
+    pub fn stack_as_floats(&self) -> Result<Vec<f32>, AoStatus> {
+        self.stack_as(AoType::as_float)
+    }
+
+    /// Convert the data stack to a vector of strings.
+    ///
+    /// Returns `AoStatus::BadDataStack` if any value is not a string.
+    pub fn stack_as_strings(&self) -> Result<Vec<String>, AoStatus> {
+        self.stack_as(|value| value.as_str().map(String::from))
+    }
+
+    /// Observe every opcode right before it is executed.
+    ///
+    /// The callback gets the pc of the opcode, the opcode and a shared
+    /// reference to the VM, so it can read but not change the state.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    /// use aoi::*;
+    ///
+    /// let trace = Rc::new(RefCell::new(Vec::new()));
+    /// let captured = trace.clone();
+    /// let mut vm = AoVM::default();
+    /// vm.set_trace(move |pc, opcode, _| captured.borrow_mut().push(format!("{pc}: {opcode}")));
+    ///
+    /// vm.run(&ao_program![mov ca,1 inc]);
+    /// assert_eq!(*trace.borrow(), vec!["0: mov ca,1", "1: inc"]);
+    /// ```
+    pub fn set_trace<F>(&mut self, trace: F)
+    where
+        F: FnMut(u32, &dyn AoOpcode, &AoVM) + 'static,
+    {
+        self.trace = Some(Rc::new(RefCell::new(trace)));
+    }
+
+    /// Limit the gas available for execution and refill it.
+    ///
+    /// Every step consumes the cost of the executed opcode. When the gas left
+    /// is not enough for the next opcode, `step` returns `AoStatus::OutOfGas`
+    /// without executing it. `None` disables metering.
+    pub fn set_gas_limit(&mut self, limit: Option<u64>) {
+        self.gas_limit = limit;
+        self.gas = limit.unwrap_or(0);
+    }
+
+    /// Get the limits and modes active on the VM.
+    ///
+    /// # Examples
+    /// ```
+    /// use aoi::runtime::vm::config::MemoryBackend;
+    /// use aoi::runtime::vm::AoVM;
+    ///
+    /// let vm = AoVM::default();
+    /// assert_eq!(vm.config().memory_backend, MemoryBackend::Paged);
+    /// ```
+    pub fn config(&self) -> VmConfig {
+        VmConfig {
+            max_data_stack: self.max_stack,
+            max_call_stack: self.max_call_depth,
+            max_array_len: self.max_array_len,
+            gas_limit: self.gas_limit,
+            memory_backend: MemoryBackend::Paged,
+            max_memory_cells: self.mem.max_cells(),
+            safe_mode: self.safe_mode,
+            strict_jumps: self.strict_jumps,
+        }
+    }
+
     /// Use the VM to execute a program.
-    pub fn run(&mut self, program: &[Box<dyn AoOpcode>]) -> AoStatus {
+    ///
+    /// The program is either an `AoProgram` or a slice of
+    /// [`Instruction`](crate::Instruction)s, which avoids a virtual call per
+    /// step.
+    pub fn run<O: AoOpcode>(&mut self, program: &[O]) -> AoStatus {
+        self.run_with_limit(program, u64::MAX)
+    }
+
+    /// Use the VM to execute a program, reporting where it failed.
+    ///
+    /// Returns `Ok(())` when the program exits, with or without a result,
+    /// otherwise the status that stopped it together with the pc of the
+    /// instruction that produced it.
+    ///
+    /// # Examples
+    /// ```
+    /// use aoi::*;
+    ///
+    /// let program = ao_program![
+    ///     mov ca,1
+    ///     sub "one"
+    /// ];
+    /// let mut vm = AoVM::default();
+    /// let fault = vm.try_run(&program).unwrap_err();
+    /// assert_eq!(fault.pc, 1);
+    /// assert_eq!(fault.to_string(), "Invalid Operation(1 - \"one\") at pc=1");
+    /// ```
+    pub fn try_run<O: AoOpcode>(&mut self, program: &[O]) -> Result<(), AoFault> {
         loop {
+            let pc = self.pc;
+            match self.step(program) {
+                AoStatus::Ok => (),
+                AoStatus::Exit | AoStatus::ExitWith(_) => return Ok(()),
+                status => return Err(AoFault { pc, status }),
+            }
+        }
+    }
+
+    /// Use the VM to execute a program starting at `entry`.
+    ///
+    /// # Examples
+    /// ```
+    /// use aoi::*;
+    ///
+    /// let program = ao_program![
+    ///     mov ca,1
+    ///     halt
+    ///     mov ca,2
+    ///     halt
+    /// ];
+    /// let mut vm = AoVM::default();
+    /// assert_eq!(vm.run_from(&program, 2), AoStatus::Exit);
+    /// assert_eq!(vm.ca, AoType::AoInt(2));
+    /// ```
+    pub fn run_from<O: AoOpcode>(&mut self, program: &[O], entry: u32) -> AoStatus {
+        self.pc = entry;
+        self.run(program)
+    }
+
+    /// Use the VM to execute a program for at most `max_steps` steps.
+    ///
+    /// Returns `AoStatus::StepLimitExceeded` if the program is still running
+    /// after `max_steps` steps. The VM is left at the next instruction, so
+    /// execution can be resumed by calling this method again.
+    pub fn run_with_limit<O: AoOpcode>(&mut self, program: &[O], max_steps: u64) -> AoStatus {
+        match self.step_n(program, max_steps) {
+            (AoStatus::Ok, _) => AoStatus::StepLimitExceeded,
+            (status, _) => status,
+        }
+    }
+
+    /// Use the VM to execute a program until it reaches a breakpoint.
+    ///
+    /// Returns `AoStatus::Breakpoint` with the pc when the next instruction
+    /// is in `breakpoints`, leaving `pc` at it. The instruction at the
+    /// current pc is always executed first, so calling this method again
+    /// resumes from the breakpoint.
+    pub fn run_until_breakpoint<O: AoOpcode>(
+        &mut self,
+        program: &[O],
+        breakpoints: &HashSet<u32>,
+    ) -> AoStatus {
+        loop {
+            match self.step(program) {
+                AoStatus::Ok if breakpoints.contains(&self.pc) => {
+                    return AoStatus::Breakpoint(self.pc)
+                }
+                AoStatus::Ok => (),
+                status => return status,
+            }
+        }
+    }
+
+    /// Go at most `n` steps in the program.
+    ///
+    /// Returns the status that stopped execution, or `AoStatus::Ok` if the
+    /// program is still running, together with the number of instructions
+    /// that completed successfully.
+    pub fn step_n<O: AoOpcode>(&mut self, program: &[O], n: u64) -> (AoStatus, u64) {
+        for taken in 0..n {
             let status = self.step(program);
             match status {
                 AoStatus::Ok => (),
-                _ => return status,
+                _ => return (status, taken),
             }
         }
+        (AoStatus::Ok, n)
     }
 
     /// Go one step in the program.
-    pub fn step(&mut self, program: &[Box<dyn AoOpcode>]) -> AoStatus {
+    pub fn step<O: AoOpcode>(&mut self, program: &[O]) -> AoStatus {
+        if self.spans.is_none() {
+            return self.step_unspanned(program);
+        }
+        let pc = self.pc;
+        match self.step_unspanned(program) {
+            status @ (AoStatus::Ok
+            | AoStatus::Exit
+            | AoStatus::ExitWith(_)
+            | AoStatus::Return(_)
+            | AoStatus::Breakpoint(_)
+            | AoStatus::Yielded
+            | AoStatus::AtSource { .. }) => status,
+            status => match self.source_span(pc) {
+                Some(span) => AoStatus::AtSource {
+                    status: Box::new(status),
+                    span,
+                },
+                None => status,
+            },
+        }
+    }
+
+    fn step_unspanned<O: AoOpcode>(&mut self, program: &[O]) -> AoStatus {
         if self.pc < program.len() as u32 {
             let current = self.pc as usize;
+            if self.gas_limit.is_some() {
+                let cost = program[current].cost();
+                if self.gas < cost {
+                    return AoStatus::OutOfGas;
+                }
+                self.gas -= cost;
+            }
+            if self.safe_mode {
+                let status = self.check_safe(&program[current]);
+                if status != AoStatus::Ok {
+                    return status;
+                }
+            }
+            if self.strict_jumps {
+                let status = AoVM::check_jump(&program[current], program.len());
+                if status != AoStatus::Ok {
+                    return status;
+                }
+            }
+            if let Some(trace) = &self.trace {
+                (trace.borrow_mut())(self.pc, &program[current], self);
+            }
+            if let Some(profile) = &mut self.profile {
+                *profile.entry(program[current].get_id()).or_insert(0) += 1;
+            }
+            self.steps += 1;
+
             self.pc += 1;
             program[current].execute(self)
         } else {
@@ -154,6 +739,14 @@ impl AoVM {
 
     /// Reset the VM.
     pub fn reset(&mut self) {
+        self.reset_registers();
+        self.mem.clear();
+        self.gvs.fill(AoType::default());
+    }
+
+    /// Reset the registers and both stacks and refill the gas, but keep
+    /// the memory, so the same program can be re-run against loaded data.
+    pub fn reset_registers(&mut self) {
         self.pc = 0;
         self.dp = 0;
         self.mp = 0;
@@ -164,6 +757,889 @@ impl AoVM {
         self.cb = AoType::default();
 
         self.ds.clear();
-        self.mem = Memory::new();
+
+        self.gas = self.gas_limit.unwrap_or(0);
+        self.steps = 0;
+    }
+}
+
+impl Clone for AoVM {
+    fn clone(&self) -> AoVM {
+        // arrays and maps shared within the VM stay shared within the clone
+        let mut copies = HashMap::new();
+        let mut mem = self.mem.clone();
+        for (addr, value) in self.mem.iter() {
+            if let AoType::AoArray(_) | AoType::AoMap(_) = value {
+                mem.set(addr, value.deep_clone_shared(&mut copies));
+            }
+        }
+
+        AoVM {
+            pc: self.pc,
+            dp: self.dp,
+            mp: self.mp,
+            cs: self.cs.clone(),
+            dsb: self.dsb,
+            ca: self.ca.deep_clone_shared(&mut copies),
+            cb: self.cb.deep_clone_shared(&mut copies),
+            ds: self
+                .ds
+                .iter()
+                .map(|value| value.deep_clone_shared(&mut copies))
+                .collect(),
+            mem,
+            gvs: self
+                .gvs
+                .iter()
+                .map(|value| value.deep_clone_shared(&mut copies))
+                .collect(),
+            gas: self.gas,
+            gas_limit: self.gas_limit,
+            max_stack: self.max_stack,
+            max_call_depth: self.max_call_depth,
+            max_array_len: self.max_array_len,
+            safe_mode: self.safe_mode,
+            strict_jumps: self.strict_jumps,
+            interrupt: self.interrupt.clone(),
+            interrupt_table: self.interrupt_table.clone(),
+            trace: self.trace.clone(),
+            profile: self.profile.clone(),
+            rng: self.rng,
+            steps: self.steps,
+            spans: self.spans.clone(),
+        }
+    }
+}
+
+impl Default for AoVM {
+    /// Create a new AoVM with default interrupt.
+    fn default() -> AoVM {
+        AoVM::new(AoVM::default_interrupt, 0)
+    }
+}
+
+impl std::fmt::Debug for AoVM {
+    /// Print the registers and the depth of both stacks. Memory and the
+    /// interrupt handlers are left out.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AoVM")
+            .field("pc", &self.pc)
+            .field("dp", &self.dp)
+            .field("mp", &self.mp)
+            .field("dsb", &self.dsb)
+            .field("ca", &self.ca)
+            .field("cb", &self.cb)
+            .field("cs_depth", &self.cs.len())
+            .field("ds_depth", &self.ds.len())
+            .field("gas", &self.gas)
+            .field("gas_limit", &self.gas_limit)
+            .field("safe_mode", &self.safe_mode)
+            .field("strict_jumps", &self.strict_jumps)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::HashSet;
+    use std::rc::Rc;
+
+    use crate::*;
+
+    #[test]
+    fn test_interrupt_closure() {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let captured = output.clone();
+        let mut vm = AoVM::with_interrupt(move |id, args| {
+            if id == 1 {
+                captured.borrow_mut().push(args[0].to_string());
+            }
+            Ok(None)
+        });
+
+        let program = ao_program![
+            push dsb
+            push "Hello"
+            cnf 1
+            int 1
+            push dsb
+            push 42
+            cnf 1
+            int 1
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(*output.borrow(), vec!["\"Hello\"", "42"]);
+    }
+
+    #[test]
+    fn test_interrupt_closure_state() {
+        // the closure owns the counter and returns it from every call
+        let mut count = 0;
+        let mut vm = AoVM::with_interrupt(move |_, _| {
+            count += 1;
+            Ok(Some(AoType::AoInt(count)))
+        });
+
+        let program = ao_program![
+            push dsb
+            cnf 0
+            int 2
+            push dsb
+            cnf 0
+            int 2
+            push dsb
+            cnf 0
+            int 2
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoInt(3));
+
+        // the state lives on across runs
+        vm.reset();
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoInt(6));
+    }
+
+    #[test]
+    fn test_interrupt_error() {
+        let mut vm = AoVM::new(
+            |id, _| match id {
+                3 => Err(AoStatus::InvalidOperation("host failure".to_string())),
+                _ => Ok(None),
+            },
+            0,
+        );
+        let program = ao_program![
+            push dsb
+            cnf 0
+            int 3
+            mov ca,1
+        ];
+        assert_eq!(
+            vm.run(&program),
+            AoStatus::InvalidOperation("host failure".to_string())
+        );
+        assert_eq!(vm.pc, 3);
+        assert_eq!(vm.ca, AoType::default());
+    }
+
+    #[test]
+    fn test_run_until_breakpoint() {
+        let mut vm = AoVM::default();
+        // calculate 1 + 2 + ... + 10
+        let program = ao_program![
+            push 1
+            push 0
+            arg 0
+            mov ca,ds
+            le 10
+            jfa 15
+            mov ca,ds
+            arg 1
+            add ds
+            mov ds,ca
+            arg 0
+            mov ca,ds
+            inc
+            mov ds,ca
+            jmpa 2
+        ];
+        let breakpoints = HashSet::from([6]);
+
+        let mut sum = 0;
+        for a in 1..=10 {
+            assert_eq!(
+                vm.run_until_breakpoint(&program, &breakpoints),
+                AoStatus::Breakpoint(6)
+            );
+            assert_eq!(vm.pc, 6);
+            assert_eq!(vm.ds, vec![AoType::AoInt(a), AoType::AoInt(sum)]);
+            sum += a;
+        }
+        assert_eq!(
+            vm.run_until_breakpoint(&program, &breakpoints),
+            AoStatus::Exit
+        );
+        assert_eq!(vm.ds[1], AoType::AoInt(55));
+    }
+
+    #[test]
+    fn test_trace() {
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        let captured = trace.clone();
+        let mut vm = AoVM::new(|_, _| Ok(None), 0);
+        vm.set_trace(move |pc, _, vm| {
+            assert_eq!(pc, vm.pc);
+            captured.borrow_mut().push(pc);
+        });
+
+        // calculate 1 + 2 + ... + 10, then print it
+        let program = ao_program![
+            push 1
+            push 0
+            arg 0
+            mov ca,ds
+            le 10
+            jfa 15
+            mov ca,ds
+            arg 1
+            add ds
+            mov ds,ca
+            arg 0
+            mov ca,ds
+            inc
+            mov ds,ca
+            jmpa 2
+            push dsb
+            arg 1
+            push ds
+            cnf 1
+            int 2
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+
+        let mut expected = vec![0, 1];
+        for _ in 0..10 {
+            expected.extend(2..=14);
+        }
+        expected.extend(2..=5);
+        expected.extend(15..=19);
+        assert_eq!(*trace.borrow(), expected);
+    }
+
+    #[test]
+    fn test_run_with_limit() {
+        let mut vm = AoVM::default();
+        let program = ao_program![jmp 0];
+        assert_eq!(
+            vm.run_with_limit(&program, 100),
+            AoStatus::StepLimitExceeded
+        );
+        assert_eq!(vm.pc, 0);
+
+        vm.reset();
+        let program = ao_program![
+            inc
+            jmp (-1)
+        ];
+        assert_eq!(vm.run_with_limit(&program, 10), AoStatus::StepLimitExceeded);
+        assert_eq!(vm.ca, AoType::AoInt(5));
+        assert_eq!(vm.pc, 0);
+
+        assert_eq!(vm.run_with_limit(&program, 3), AoStatus::StepLimitExceeded);
+        assert_eq!(vm.ca, AoType::AoInt(7));
+        assert_eq!(vm.pc, 1);
+    }
+
+    #[test]
+    fn test_step_n() {
+        let mut vm = AoVM::default();
+        // sum of 1..=10
+        let program = ao_program![
+            push 1
+            push 0
+            arg 0
+            mov ca,ds
+            le 10
+            jfa 15
+            mov ca,ds
+            arg 1
+            add ds
+            mov ds,ca
+            arg 0
+            mov ca,ds
+            inc
+            mov ds,ca
+            jmpa 2
+        ];
+        assert_eq!(vm.step_n(&program, 5), (AoStatus::Ok, 5));
+        assert_eq!(vm.pc, 5);
+        assert_eq!(vm.ds, vec![AoType::AoInt(1), AoType::AoInt(0)]);
+
+        assert_eq!(vm.step_n(&program, 1000), (AoStatus::Exit, 131));
+        assert_eq!(vm.ds[1], AoType::AoInt(55));
+        assert_eq!(vm.step_n(&program, 0), (AoStatus::Ok, 0));
+    }
+
+    #[test]
+    fn test_mem_load_slice() {
+        let mut vm = AoVM::default();
+        let values: Vec<AoType> = (1..=5).map(AoType::AoInt).collect();
+        vm.mem_load_slice(100, &values);
+        vm.mem_set(105, AoType::AoInt(10));
+
+        // sum of mem[100..106] into cb
+        let program = ao_program![
+            mov cb,0
+            mov mp,100
+            mov ca,cb
+            add mem
+            mov cb,ca
+            mov ca,mp
+            add 1
+            mov mp,ca
+            csi
+            lt 106
+            jt (-8)
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.cb, AoType::AoInt(25));
+        assert_eq!(vm.mem_get(102), AoType::AoInt(3));
+        assert_eq!(vm.mem_get(106), AoType::default());
+    }
+
+    #[test]
+    fn test_try_run() {
+        let program = ao_program![
+            /* 0 */ mov ca,"done"
+            /* 1 */ push ca
+            /* 2 */ mov cb,1
+            /* 3 */ cnf 2
+            /* 4 */ mov cb,2
+        ];
+        let mut vm = AoVM::default();
+        assert_eq!(
+            vm.try_run(&program),
+            Err(AoFault {
+                pc: 3,
+                status: AoStatus::BadDataStack,
+            })
+        );
+        assert_eq!(vm.cb, AoType::AoInt(1));
+
+        vm.reset();
+        assert_eq!(vm.try_run(&program[..3]), Ok(()));
+    }
+
+    #[test]
+    fn test_frame_arg() {
+        let mut vm = AoVM::default();
+        let program = ao_program![
+            push dsb
+            push "a"
+            push "b"
+            cnf 2
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.frame_arg(0), Some(&AoType::from("a")));
+        assert_eq!(vm.frame_arg(1), Some(&AoType::from("b")));
+        assert_eq!(vm.frame_arg(2), None);
+        assert_eq!(vm.frame_arg(u32::MAX), None);
+        assert_eq!(vm.frame_args(), &[AoType::from("a"), AoType::from("b")]);
+
+        vm.dsb = 10;
+        assert_eq!(vm.frame_arg(0), None);
+        assert!(vm.frame_args().is_empty());
+    }
+
+    #[test]
+    fn test_run_from() {
+        let program = ao_program![
+            // double
+            /* 0 */ mov ca,ds
+            /* 1 */ mul 2
+            /* 2 */ halt
+            // square
+            /* 3 */ mov ca,ds
+            /* 4 */ mul ds
+            /* 5 */ halt
+        ];
+        let mut vm = AoVM::default();
+        vm.push(AoType::AoInt(7));
+        assert_eq!(vm.run_from(&program, 3), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoInt(49));
+
+        vm.reset();
+        vm.push(AoType::AoInt(7));
+        assert_eq!(vm.run_from(&program, 0), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoInt(14));
+    }
+
+    #[test]
+    fn test_push_all() {
+        let expected: Vec<AoType> = [10, 20, 30].into_iter().map(AoType::AoInt).collect();
+
+        let mut vm = AoVM::default();
+        let program = ao_program![push [10, 20, 30] push []];
+        assert_eq!(program.len(), 3);
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ds, expected);
+
+        let mut vm = AoVM::default();
+        assert!(vm.push_all(&expected));
+        assert_eq!(vm.ds, expected);
+
+        let too_many = vec![AoType::default(); super::MAX_DATA_STACK + 2];
+        assert!(!vm.push_all(&too_many));
+    }
+
+    #[test]
+    fn test_stack() {
+        let mut vm = AoVM::default();
+        assert!(vm.stack().is_empty());
+
+        let program = ao_program![push 1 push "two" mov ca,3 push ca];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        let expected = [AoType::AoInt(1), AoType::from("two"), AoType::AoInt(3)];
+        assert_eq!(vm.stack(), expected);
+
+        let snapshot = vm.stack_snapshot();
+        vm.pop();
+        assert_eq!(snapshot, expected);
+        assert_eq!(vm.stack(), &expected[..2]);
+    }
+
+    #[test]
+    fn test_profile() {
+        // sum of 1..=100
+        let program = ao_program![
+            push 1
+            push 0
+            arg 0
+            mov ca,ds
+            le 100
+            jfa 15
+            mov ca,ds
+            arg 1
+            add ds
+            mov ds,ca
+            arg 0
+            mov ca,ds
+            inc
+            mov ds,ca
+            jmpa 2
+        ];
+        let mut vm = AoVM::default();
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert!(vm.profile_report().is_none());
+
+        vm.reset();
+        vm.enable_profiling();
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ds[1], AoType::AoInt(5050));
+        let report = vm.profile_report().unwrap();
+        // inc
+        assert_eq!(report[&0x35], 100);
+        // le
+        assert_eq!(report[&0x55], 101);
+        assert_eq!(report.values().sum::<u64>(), 1306);
+    }
+
+    #[test]
+    fn test_source_spans() {
+        let program = ao_program![
+            mov ca,1
+            add 2
+            sub "one"
+            halt
+        ];
+        let spans: Vec<SourceSpan> = (1..=4).map(|line| SourceSpan::new(line, 3)).collect();
+
+        let mut vm = AoVM::default();
+        vm.set_source_spans(spans);
+        let status = vm.run(&program);
+        assert_eq!(
+            status,
+            AoStatus::AtSource {
+                status: Box::new(AoStatus::InvalidOperation("3 - \"one\"".to_string())),
+                span: SourceSpan::new(3, 3),
+            }
+        );
+        assert_eq!(
+            status.to_string(),
+            "Invalid Operation(3 - \"one\") at line 3, column 3"
+        );
+
+        // the spans are kept on reset, and a program exiting normally is
+        // not affected
+        vm.reset();
+        assert_eq!(vm.run(&ao_program![mov ca,1 halt]), AoStatus::Exit);
+
+        // instructions past the spans report the bare status
+        vm.reset();
+        vm.set_source_spans(vec![SourceSpan::new(1, 1)]);
+        assert_eq!(
+            vm.run(&program),
+            AoStatus::InvalidOperation("3 - \"one\"".to_string())
+        );
+    }
+
+    #[test]
+    fn test_step_count() {
+        // sum of 1..=100
+        let program = ao_program![
+            push 1
+            push 0
+            arg 0
+            mov ca,ds
+            le 100
+            jfa 15
+            mov ca,ds
+            arg 1
+            add ds
+            mov ds,ca
+            arg 0
+            mov ca,ds
+            inc
+            mov ds,ca
+            jmpa 2
+        ];
+        let mut vm = AoVM::default();
+        assert_eq!(vm.step_n(&program, 10000), (AoStatus::Exit, 1306));
+        assert_eq!(vm.step_count(), 1306);
+
+        vm.reset();
+        assert_eq!(vm.step_count(), 0);
+
+        // `steps` counts itself
+        assert_eq!(vm.run(&ao_program![nop nop steps]), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoInt(3));
+        vm.steps = u64::MAX - 1;
+        vm.pc = 2;
+        assert_eq!(vm.run(&ao_program![nop nop steps]), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoInt(i32::MAX));
+    }
+
+    #[test]
+    fn test_rng() {
+        let program = ao_program![
+            rand
+            push ca
+            rand
+            push ca
+            randf
+            push ca
+        ];
+        let mut vm = AoVM::default();
+        vm.seed_rng(42);
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        let first = vm.ds.clone();
+        assert_ne!(first[0], first[1]);
+        for value in &first[..2] {
+            assert!(matches!(value, AoType::AoInt(v) if *v >= 0));
+        }
+        assert!(matches!(first[2], AoType::AoFloat(v) if (0.0..1.0).contains(&v)));
+
+        vm.reset();
+        vm.seed_rng(42);
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ds, first);
+
+        vm.reset();
+        vm.seed_rng(7);
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_ne!(vm.ds, first);
+
+        vm.seed_rng(0);
+        assert_eq!(vm.rng, super::DEFAULT_RNG_SEED);
+    }
+
+    #[test]
+    fn test_reserve_stack() {
+        let mut vm = AoVM::default();
+        vm.push(AoType::AoInt(1));
+        vm.reserve_stack(1000);
+        assert!(vm.ds.capacity() >= 1001);
+        assert_eq!(vm.ds, vec![AoType::AoInt(1)]);
+    }
+
+    #[test]
+    fn test_max_call_depth() {
+        // recurse until cb reaches ca
+        let program = |depth: i32| {
+            let mut program = ao_program![
+                /*  0 */ mov cb,0
+                /*  1 */ push dsb
+                /*  2 */ cnf 0
+                /*  3 */ call 5
+                /*  4 */ int 0
+                /*  5 */ mov ca,cb
+                /*  6 */ inc
+                /*  7 */ mov cb,ca
+                /*  8 */ lt 0
+                /*  9 */ jf 4
+                /* 10 */ push dsb
+                /* 11 */ cnf 0
+                /* 12 */ call 5
+                /* 13 */ ret
+            ];
+            program[8] = Box::new(opcodes::Lt {
+                src: AoArg::from(depth),
+            });
+            program
+        };
+
+        let mut vm = AoVM::default();
+        vm.set_max_call_depth(4);
+        assert_eq!(vm.config().max_call_stack, 4);
+        assert_eq!(vm.run(&program(4)), AoStatus::Exit);
+        assert_eq!(vm.cb, AoType::AoInt(4));
+
+        vm.reset();
+        assert_eq!(vm.run(&program(5)), AoStatus::CallStackOverflow(4));
+        assert_eq!(vm.cs.len(), 4);
+        assert_eq!(
+            AoStatus::CallStackOverflow(4).to_string(),
+            "Call Stack Overflow(4)"
+        );
+    }
+
+    #[test]
+    fn test_max_stack() {
+        let mut vm = AoVM::default();
+        vm.set_max_stack(3);
+        let program = ao_program![push [1, 2, 3, 4]];
+        assert_eq!(vm.run(&program), AoStatus::DataStackOverflow(3));
+        assert_eq!(vm.ds.len(), 3);
+        assert_eq!(vm.config().max_data_stack, 3);
+        assert_eq!(
+            AoStatus::DataStackOverflow(3).to_string(),
+            "Data Stack Overflow(3)"
+        );
+
+        vm.reset();
+        assert!(!vm.push_all(&vec![AoType::default(); 4]));
+        assert_eq!(vm.ds.len(), 3);
+    }
+
+    #[test]
+    fn test_gvs() {
+        let mut vm = AoVM::new(|_, _| Ok(None), 2);
+        let program = ao_program![
+            mov ca,42
+            mov mp,1
+            mov gvs,ca
+            mov ca,0
+            mov cb,gvs
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.gvs, vec![AoType::default(), AoType::AoInt(42)]);
+        assert_eq!(vm.cb, AoType::AoInt(42));
+
+        let bin = AoAsmSerializer::serialize(&program);
+        let parsed = AoAsmSerializer::deserialize(&bin).unwrap();
+        assert_eq!(parsed[2].to_string(), "mov gvs,ca");
+
+        vm.reset();
+        assert_eq!(vm.gvs, vec![AoType::default(); 2]);
+        assert_eq!(
+            vm.run(&ao_program![mov mp,5 mov gvs,ca]),
+            AoStatus::BadDataAccess
+        );
+        vm.reset();
+        assert_eq!(
+            vm.run(&ao_program![mov mp,5 push gvs]),
+            AoStatus::BadDataAccess
+        );
+        assert!(vm.ds.is_empty());
+    }
+
+    #[test]
+    fn test_reset_registers() {
+        let mut vm = AoVM::default();
+        vm.mem_set(7, AoType::AoInt(5));
+
+        let program = ao_program![
+            mov mp,7
+            mov ca,mem
+            mul 2
+            mov mem,ca
+            push ca
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.mem_get(7), AoType::AoInt(10));
+
+        vm.reset_registers();
+        assert_eq!(vm.pc, 0);
+        assert_eq!(vm.mp, 0);
+        assert_eq!(vm.ca, AoType::default());
+        assert!(vm.ds.is_empty());
+        assert_eq!(vm.mem_get(7), AoType::AoInt(10));
+
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.mem_get(7), AoType::AoInt(20));
+
+        vm.reset();
+        assert_eq!(vm.mem_get(7), AoType::default());
+    }
+
+    #[test]
+    fn test_memory_stats() {
+        let mut vm = AoVM::default();
+        assert_eq!(vm.memory_stats(), 0);
+
+        vm.mem_set(0x0300_0002, AoType::AoInt(3));
+        vm.mem_set(0x10, AoType::AoInt(1));
+        vm.mem_set(0x0001_0000, AoType::from("2"));
+        assert_eq!(vm.memory_stats(), 3 + 17 + 1);
+        assert_eq!(
+            vm.mem.iter().collect::<Vec<_>>(),
+            vec![
+                (0x10, AoType::AoInt(1)),
+                (0x0001_0000, AoType::from("2")),
+                (0x0300_0002, AoType::AoInt(3)),
+            ]
+        );
+        assert_eq!(vm.mem_get(0x0200_0000), AoType::default());
+        assert_eq!(vm.mem_get(0x0300_0001), AoType::default());
+    }
+
+    #[test]
+    fn test_max_memory_cells() {
+        let mut vm = AoVM::default();
+        vm.set_max_memory_cells(Some(300));
+        assert_eq!(vm.config().max_memory_cells, Some(300));
+
+        let program = ao_program![
+            mov mp,ca
+            mov mem,1
+        ];
+        vm.ca = AoType::AoPtr(255);
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.memory_stats(), 256);
+
+        vm.pc = 0;
+        vm.ca = AoType::AoPtr(0xFFFF_FFFF);
+        assert_eq!(vm.run(&program), AoStatus::OutOfMemory);
+        assert_eq!(vm.memory_stats(), 256);
+        assert_eq!(vm.mem_get(0xFFFF_FFFF), AoType::default());
+
+        assert!(vm.mem_set(0x0100_0000, AoType::AoInt(1)));
+        assert!(!vm.mem_load_slice(0x0200_0000, &vec![AoType::AoInt(1); 50]));
+        assert_eq!(vm.memory_stats(), 300);
+
+        vm.reset();
+        assert_eq!(vm.memory_stats(), 0);
+        assert_eq!(vm.config().max_memory_cells, Some(300));
+    }
+
+    #[test]
+    fn test_clone() {
+        let program = ao_program![
+            inc
+            mov mp,0
+            mov mem,ca
+            jmp (-3)
+        ];
+
+        let mut vm = AoVM::default();
+        vm.push(AoType::AoInt(0));
+        vm.cb = AoType::from("cb");
+        assert_eq!(vm.run_with_limit(&program, 4), AoStatus::StepLimitExceeded);
+
+        let mut copy = vm.clone();
+        assert_eq!(copy.pc, vm.pc);
+        assert_eq!(copy.cb, vm.cb);
+        assert_eq!(copy.ds, vm.ds);
+        assert_eq!(copy.mem.get(0), AoType::AoInt(1));
+
+        assert_eq!(
+            copy.run_with_limit(&program, 8),
+            AoStatus::StepLimitExceeded
+        );
+        copy.push(AoType::AoInt(1));
+        assert_eq!(copy.ca, AoType::AoInt(3));
+        assert_eq!(copy.mem.get(0), AoType::AoInt(3));
+        assert_eq!(vm.ca, AoType::AoInt(1));
+        assert_eq!(vm.mem.get(0), AoType::AoInt(1));
+        assert_eq!(vm.ds.len(), 1);
+
+        assert_eq!(vm.run_with_limit(&program, 4), AoStatus::StepLimitExceeded);
+        assert_eq!(vm.ca, AoType::AoInt(2));
+        assert_eq!(vm.mem.get(0), AoType::AoInt(2));
+        assert_eq!(copy.mem.get(0), AoType::AoInt(3));
+    }
+
+    #[test]
+    fn test_gas() {
+        let mut vm = AoVM::default();
+        vm.set_gas_limit(Some(10));
+        let program = ao_program![
+            mov ca,1
+            add 2
+            mul 3
+        ];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.gas, 7);
+        assert_eq!(vm.config().gas_limit, Some(10));
+
+        vm.reset();
+        vm.set_gas_limit(Some(2));
+        assert_eq!(vm.run(&program), AoStatus::OutOfGas);
+        assert_eq!(vm.pc, 2);
+        assert_eq!(vm.gas, 0);
+        assert_eq!(vm.ca, AoType::AoInt(3));
+    }
+
+    #[test]
+    fn test_debug() {
+        let mut vm = AoVM::default();
+        vm.push(AoType::AoInt(1));
+        let debug = format!("{:?}", vm);
+        assert!(debug.starts_with("AoVM { pc: 0, dp: 0,"));
+        assert!(debug.contains("ca: AoInt(0)"));
+        assert!(debug.contains("ds_depth: 1"));
+        assert!(!debug.contains("interrupt"));
+    }
+
+    #[test]
+    fn test_config() {
+        let vm = AoVM::default();
+        assert_eq!(
+            vm.config(),
+            vm::config::VmConfig {
+                max_data_stack: vm::MAX_DATA_STACK,
+                max_call_stack: vm::MAX_CALL_STACK,
+                max_array_len: vm::MAX_ARRAY_LEN,
+                gas_limit: None,
+                memory_backend: vm::config::MemoryBackend::Paged,
+                max_memory_cells: None,
+                safe_mode: false,
+                strict_jumps: false,
+            }
+        );
+
+        let mut vm = AoVM::default();
+        vm.set_max_stack(16);
+        vm.set_max_call_depth(8);
+        vm.set_max_array_len(32);
+        vm.set_gas_limit(Some(1000));
+        vm.set_max_memory_cells(Some(64));
+        vm.safe_mode = true;
+        vm.strict_jumps = true;
+        assert_eq!(
+            vm.config(),
+            vm::config::VmConfig {
+                max_data_stack: 16,
+                max_call_stack: 8,
+                max_array_len: 32,
+                gas_limit: Some(1000),
+                memory_backend: vm::config::MemoryBackend::Paged,
+                max_memory_cells: Some(64),
+                safe_mode: true,
+                strict_jumps: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_stack_as() {
+        let mut vm = AoVM::default();
+        vm.push(AoType::AoFloat(1.5));
+        vm.push(AoType::AoFloat(2.5));
+        assert_eq!(vm.stack_as_floats(), Ok(vec![1.5, 2.5]));
+        assert_eq!(vm.stack_as_ints(), Err(AoStatus::BadDataStack));
+
+        vm.reset();
+        vm.push(AoType::from("a"));
+        vm.push(AoType::from("b"));
+        assert_eq!(
+            vm.stack_as_strings(),
+            Ok(vec!["a".to_string(), "b".to_string()])
+        );
+
+        vm.push(AoType::AoPtr(3));
+        assert_eq!(vm.stack_as_strings(), Err(AoStatus::BadDataStack));
     }
 }
@@ -0,0 +1,311 @@
+use super::AoVM;
+use super::MAX_SAFE_MEMORY_CELLS;
+use crate::opcodes::*;
+use crate::AoArg;
+use crate::AoStatus;
+use crate::AoType;
+
+impl AoVM {
+    fn check_arg(&self, arg: &AoArg) -> AoStatus {
+        match arg {
+            AoArg::DS if self.dp as usize >= self.ds.len() => {
+                AoStatus::InvalidOperation(format!("ds[{}] out of range", self.dp))
+            }
+            _ => AoStatus::Ok,
+        }
+    }
+
+    fn check_frame(&self) -> AoStatus {
+        let dsb = self.dsb as usize;
+        if dsb == 0 || dsb > self.ds.len() {
+            AoStatus::BadDataStack
+        } else {
+            AoStatus::Ok
+        }
+    }
+
+    // Refuse a block copy or fill whose length, on top of the data stack, is
+    // more than the cells left, even if some of them are already allocated.
+    fn check_block(&self) -> AoStatus {
+        let len = match self.ds.last() {
+            Some(AoType::AoInt(len)) if *len >= 0 => *len as usize,
+            Some(AoType::AoPtr(len)) => *len as usize,
+            _ => return AoStatus::Ok,
+        };
+        let max_cells = self.mem.max_cells().unwrap_or(MAX_SAFE_MEMORY_CELLS);
+        if len > max_cells.saturating_sub(self.mem.allocated_cells()) {
+            AoStatus::OutOfMemory
+        } else {
+            AoStatus::Ok
+        }
+    }
+
+    /// Check that an absolute jump, jump table or call targets an instruction of a
+    /// program with `len` instructions.
+    pub(super) fn check_jump(opcode: &dyn AoOpcode, len: usize) -> AoStatus {
+        match (opcode.get_id(), opcode.get_args()) {
+            // call, jmpa, jta, jfa
+            (0x10 | 0x13 | 0x15 | 0x17, OpcodeArgType::u32(addr)) if addr as usize >= len => {
+                AoStatus::InvalidJumpTarget(addr)
+            }
+            // jmptable
+            (0x1D, OpcodeArgType::u32s(targets)) => {
+                match targets.iter().find(|addr| **addr as usize >= len) {
+                    Some(addr) => AoStatus::InvalidJumpTarget(*addr),
+                    None => AoStatus::Ok,
+                }
+            }
+            _ => AoStatus::Ok,
+        }
+    }
+
+    /// Check that executing the opcode cannot index out of bounds or divide
+    /// by zero, which would otherwise panic, nor allocate memory without
+    /// bound.
+    pub(super) fn check_safe(&self, opcode: &dyn AoOpcode) -> AoStatus {
+        let status = match opcode.get_args() {
+            OpcodeArgType::AoArg(arg) => self.check_arg(&arg),
+            OpcodeArgType::AoArg2(dst, src) => match self.check_arg(&dst) {
+                AoStatus::Ok => self.check_arg(&src),
+                status => status,
+            },
+            _ => AoStatus::Ok,
+        };
+        if status != AoStatus::Ok {
+            return status;
+        }
+
+        match (opcode.get_id(), opcode.get_args()) {
+            // ret, retv
+            (0x11 | 0x1E, _) if !self.cs.is_empty() => self.check_frame(),
+            // int
+            (0x21, OpcodeArgType::u8(id)) if id != 0 => self.check_frame(),
+            // memcpy, memset
+            (0x26 | 0x27, _) => self.check_block(),
+            // div, rem
+            (id @ (0x33 | 0x34), OpcodeArgType::AoArg(src)) => {
+                match (&self.ca, src.get_value(self)) {
+                    (
+                        AoType::AoInt(_) | AoType::AoPtr(_),
+                        Ok(AoType::AoInt(0) | AoType::AoPtr(0)),
+                    ) => AoStatus::InvalidOperation(format!(
+                        "{} {} 0",
+                        self.ca,
+                        if id == 0x33 { "/" } else { "%" }
+                    )),
+                    _ => AoStatus::Ok,
+                }
+            }
+            _ => AoStatus::Ok,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+    use std::rc::Rc;
+
+    use crate::opcodes::*;
+    use crate::*;
+
+    struct Rng(u64);
+
+    impl Rng {
+        fn bits(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn next(&mut self, n: u32) -> u32 {
+            (self.bits() % n as u64) as u32
+        }
+
+        // Any u32, or half of the time a small one so that nearby stack
+        // slots, addresses and instructions are hit too.
+        fn u32(&mut self) -> u32 {
+            if self.next(2) == 0 {
+                self.bits() as u32
+            } else {
+                self.next(20)
+            }
+        }
+
+        fn i32(&mut self) -> i32 {
+            if self.next(2) == 0 {
+                self.bits() as i32
+            } else {
+                self.next(11) as i32 - 5
+            }
+        }
+
+        fn value(&mut self) -> AoType {
+            match self.next(7) {
+                0 => AoType::AoBool(self.next(2) == 0),
+                1 => AoType::AoInt(self.i32()),
+                2 => AoType::AoFloat(f32::from_bits(self.bits() as u32)),
+                3 => AoType::AoPtr(self.u32()),
+                4 => AoType::AoArray(Rc::new(RefCell::new(vec![self.value()]))),
+                5 => AoType::AoMap(Rc::new(RefCell::new(BTreeMap::new()))),
+                _ => {
+                    let len = self.next(4);
+                    let text: String = (0..len)
+                        .map(|_| char::from_u32(self.next(0x11_0000)).unwrap_or('"'))
+                        .collect();
+                    AoType::from(text)
+                }
+            }
+        }
+
+        fn arg(&mut self) -> AoArg {
+            match self.next(11) {
+                0 => AoArg::PC,
+                1 => AoArg::DP,
+                2 => AoArg::MP,
+                3 => AoArg::DSB,
+                4 => AoArg::DST,
+                5 => AoArg::CA,
+                6 => AoArg::CB,
+                7 => AoArg::DS,
+                8 => AoArg::MEM,
+                9 => AoArg::GVS,
+                _ => AoArg::Imm(self.value()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_safe_mode_fuzz() {
+        let ids: Vec<u8> = (0..=u8::MAX)
+            .filter(|id| create_opcode_by_id(*id).is_some())
+            .collect();
+        let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+
+        for _ in 0..20000 {
+            // start with a few values on the stack, so that more opcodes get past
+            // their operands
+            let mut program: AoProgram = Vec::new();
+            for _ in 0..rng.next(8) {
+                program.push(Box::new(Push {
+                    src: AoArg::Imm(rng.value()),
+                }));
+            }
+            for _ in 0..rng.next(30) + 1 {
+                let id = ids[rng.next(ids.len() as u32) as usize];
+                let mut opcode = create_opcode_by_id(id).unwrap();
+                let args = match opcode.get_args() {
+                    OpcodeArgType::NoArg => OpcodeArgType::NoArg,
+                    OpcodeArgType::u8(_) => OpcodeArgType::u8(rng.bits() as u8),
+                    OpcodeArgType::i32(_) => OpcodeArgType::i32(rng.i32()),
+                    OpcodeArgType::u32(_) => OpcodeArgType::u32(rng.u32()),
+                    OpcodeArgType::bool(_) => OpcodeArgType::bool(rng.next(2) == 0),
+                    OpcodeArgType::AoArg(_) => OpcodeArgType::AoArg(rng.arg()),
+                    OpcodeArgType::AoArg2(_, _) => OpcodeArgType::AoArg2(rng.arg(), rng.arg()),
+                    OpcodeArgType::u32_u32(_, _) => OpcodeArgType::u32_u32(rng.u32(), rng.u32()),
+                    OpcodeArgType::u32_i32(_, _) => OpcodeArgType::u32_i32(rng.u32(), rng.i32()),
+                    OpcodeArgType::AoTypes(_) => {
+                        OpcodeArgType::AoTypes(vec![rng.value(), rng.value()])
+                    }
+                    OpcodeArgType::u32s(_) => OpcodeArgType::u32s(vec![rng.u32(), rng.u32()]),
+                };
+                opcode.set_args(args);
+                program.push(opcode);
+            }
+
+            let bin = AoAsmSerializer::serialize(&program);
+            let program = AoAsmSerializer::deserialize(&bin).unwrap();
+
+            let mut vm = AoVM::new(|_, _| Ok(None), 0);
+            vm.set_gas_limit(Some(500));
+            if let Err(err) = validate_and_run(&mut vm, &program) {
+                assert!(matches!(err, ValidationError::ImmediateDestination { .. }));
+            }
+        }
+    }
+
+    #[test]
+    fn test_safe_mode() {
+        let mut vm = AoVM::new(|_, _| Ok(None), 0);
+        vm.safe_mode = true;
+
+        let cases: Vec<(AoProgram, AoStatus)> = vec![
+            (
+                ao_program![mov ca,ds],
+                AoStatus::InvalidOperation("ds[0] out of range".to_string()),
+            ),
+            (ao_program![ret], AoStatus::CallStackUnderflow),
+            (ao_program![call 1 ret], AoStatus::BadDataStack),
+            (ao_program![push 1 int 1], AoStatus::BadDataStack),
+            (ao_program![cnf 1], AoStatus::BadDataStack),
+            (
+                ao_program![mov ca,1 div 0],
+                AoStatus::InvalidOperation("1 / 0".to_string()),
+            ),
+            (
+                ao_program![mov ca,1 rem 0],
+                AoStatus::InvalidOperation("1 % 0".to_string()),
+            ),
+            (
+                ao_program![push 0 push 0 push 2000000000 memset],
+                AoStatus::OutOfMemory,
+            ),
+            (
+                ao_program![push 0 push 1000 push 1048577 memcpy],
+                AoStatus::OutOfMemory,
+            ),
+        ];
+        for (program, status) in cases {
+            vm.reset();
+            assert_eq!(vm.run(&program), status);
+        }
+
+        vm.reset();
+        let program = ao_program![push 1 push 0 push 1048576 memset];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.memory_stats(), 1048576);
+        let program = ao_program![push 1 push 0 push 1 memset];
+        assert_eq!(vm.run_from(&program, 0), AoStatus::OutOfMemory);
+
+        vm.reset();
+        let program = ao_program![mov ca,1.5 div 0];
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoFloat(f32::INFINITY));
+    }
+
+    #[test]
+    fn test_strict_jumps() {
+        let program = ao_program![
+            mov ca,1
+            jmpa 10
+            mov ca,2
+        ];
+
+        let mut vm = AoVM::default();
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.pc, 10);
+        assert_eq!(vm.ca, AoType::AoInt(1));
+
+        let mut vm = AoVM {
+            strict_jumps: true,
+            ..AoVM::default()
+        };
+        assert_eq!(vm.run(&program), AoStatus::InvalidJumpTarget(10));
+        assert_eq!(vm.pc, 1);
+
+        let cases = [
+            (ao_program![jmpa 2 nop], AoStatus::InvalidJumpTarget(2)),
+            (ao_program![jta 5], AoStatus::InvalidJumpTarget(5)),
+            (ao_program![jfa 5], AoStatus::InvalidJumpTarget(5)),
+            (ao_program![call 7], AoStatus::InvalidJumpTarget(7)),
+            (ao_program![jmptable [0, 4]], AoStatus::InvalidJumpTarget(4)),
+            (ao_program![jmpa 1 halt], AoStatus::Exit),
+        ];
+        for (program, status) in cases {
+            vm.reset();
+            assert_eq!(vm.run(&program), status);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+use super::AoVM;
+use crate::AoType;
+
+/// Registers, stacks, memory and globals of a VM at one point of execution.
+///
+/// Unlike a clone of the VM, a snapshot holds no interrupt handlers, so it
+/// can be stored or, with the `serde` feature, serialized.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VmSnapshot {
+    pub pc: u32,
+    pub dp: u32,
+    pub mp: u32,
+    pub dsb: u32,
+    pub ca: AoType,
+    pub cb: AoType,
+    pub cs: Vec<u32>,
+    pub ds: Vec<AoType>,
+    /// Written memory cells in address order.
+    pub mem: Vec<(u32, AoType)>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub gvs: Vec<AoType>,
+}
+
+impl AoVM {
+    /// Capture the registers, stacks, memory and globals.
+    ///
+    /// Arrays and maps are copied, so later stores into them do not change
+    /// the snapshot.
+    pub fn snapshot(&self) -> VmSnapshot {
+        let mut copies = HashMap::new();
+        let mut copy = |value: &AoType| value.deep_clone_shared(&mut copies);
+        VmSnapshot {
+            pc: self.pc,
+            dp: self.dp,
+            mp: self.mp,
+            dsb: self.dsb,
+            ca: copy(&self.ca),
+            cb: copy(&self.cb),
+            cs: self.cs.clone(),
+            ds: self.ds.iter().map(&mut copy).collect(),
+            mem: self
+                .mem
+                .iter()
+                .map(|(addr, value)| (addr, copy(&value)))
+                .collect(),
+            gvs: self.gvs.iter().map(&mut copy).collect(),
+        }
+    }
+
+    /// Roll the registers, stacks, memory and globals back to a snapshot.
+    ///
+    /// Gas, limits and interrupt handlers are kept as they are. Memory is
+    /// restored even if it exceeds the current memory limit. Arrays and maps
+    /// are copied, so the snapshot can be restored again later.
+    ///
+    /// # Examples
+    /// ```
+    /// use aoi::*;
+    ///
+    /// let program = ao_program![inc inc inc];
+    /// let mut vm = AoVM::default();
+    /// vm.step(&program);
+    /// let snapshot = vm.snapshot();
+    ///
+    /// vm.run(&program);
+    /// assert_eq!(vm.ca, AoType::AoInt(3));
+    ///
+    /// vm.restore(&snapshot);
+    /// assert_eq!(vm.pc, 1);
+    /// assert_eq!(vm.ca, AoType::AoInt(1));
+    /// ```
+    pub fn restore(&mut self, snapshot: &VmSnapshot) {
+        self.pc = snapshot.pc;
+        self.dp = snapshot.dp;
+        self.mp = snapshot.mp;
+        self.dsb = snapshot.dsb;
+        let mut copies = HashMap::new();
+        let mut copy = |value: &AoType| value.deep_clone_shared(&mut copies);
+        self.ca = copy(&snapshot.ca);
+        self.cb = copy(&snapshot.cb);
+        self.cs = snapshot.cs.clone();
+        self.ds = snapshot.ds.iter().map(&mut copy).collect();
+        self.gvs = snapshot.gvs.iter().map(&mut copy).collect();
+
+        let max_cells = self.mem.max_cells();
+        self.mem.clear();
+        self.mem.set_max_cells(None);
+        for (addr, value) in &snapshot.mem {
+            self.mem.set(*addr, copy(value));
+        }
+        self.mem.set_max_cells(max_cells);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_snapshot() {
+        // mem[i] = i * i for i in 0..5
+        let program = ao_program![
+            push dsb
+            push 0
+            cnf 1
+            arg 0
+            mov ca,ds
+            ge 5
+            jta 18
+            mov ca,ds
+            mul ds
+            mov cb,ca
+            mov ca,ds
+            csp
+            mov mp,ca
+            mov mem,cb
+            mov ca,ds
+            inc
+            mov ds,ca
+            jmpa 3
+        ];
+
+        let mut vm = AoVM::default();
+        assert_eq!(vm.run_with_limit(&program, 40), AoStatus::StepLimitExceeded);
+        let snapshot = vm.snapshot();
+        assert_eq!(snapshot.mem.len(), 2);
+
+        assert_eq!(vm.run_with_limit(&program, 25), AoStatus::StepLimitExceeded);
+        assert_ne!(vm.snapshot(), snapshot);
+
+        vm.restore(&snapshot);
+        assert_eq!(vm.snapshot(), snapshot);
+        assert_eq!(vm.mem_get(3), AoType::default());
+
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        for i in 0..5 {
+            assert_eq!(vm.mem_get(i), AoType::AoInt((i * i) as i32));
+        }
+    }
+
+    #[test]
+    fn test_snapshot_arrays() {
+        let mut vm = AoVM::default();
+        assert_eq!(
+            vm.run(&ao_program![mov ca,1 newarr push ca push ca]),
+            AoStatus::Exit
+        );
+        let snapshot = vm.snapshot();
+        let program = ao_program![push 0 push 7 aset];
+
+        assert_eq!(vm.run_from(&program, 0), AoStatus::Exit);
+        assert_eq!(vm.ca.to_string(), "[7]");
+        assert_eq!(vm.ds[0].to_string(), "[7]");
+        assert_eq!(snapshot.ca.to_string(), "[0]");
+        assert_eq!(snapshot.ds[0].to_string(), "[0]");
+
+        vm.restore(&snapshot);
+        assert_eq!(vm.run_from(&program, 0), AoStatus::Exit);
+        assert_eq!(vm.ds[1].to_string(), "[7]");
+        assert_eq!(snapshot.ca.to_string(), "[0]");
+
+        // the array is still shared between CA and the stack after restoring
+        vm.restore(&snapshot);
+        assert_eq!(vm.run_from(&program, 0), AoStatus::Exit);
+        assert_eq!(vm.ds[1].to_string(), "[7]");
+
+        let copy = vm.clone();
+        assert_eq!(
+            vm.run_from(&ao_program![push 0 push 5 aset], 0),
+            AoStatus::Exit
+        );
+        assert_eq!(vm.ds[0].to_string(), "[5]");
+        assert_eq!(copy.ds[0].to_string(), "[7]");
+        assert_eq!(copy.ds[1].to_string(), "[7]");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_snapshot_serde() {
+        let mut vm = AoVM::default();
+        vm.push(AoType::from("Aoi"));
+        vm.mem_set(7, AoType::AoFloat(1.5));
+        let snapshot = vm.snapshot();
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let parsed: vm::snapshot::VmSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, snapshot);
+    }
+}
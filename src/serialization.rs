@@ -1,38 +1,147 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
 use crate::opcodes::*;
 use crate::AoArg;
+use crate::AoMapKey;
 use crate::AoProgram;
 use crate::AoType;
+use crate::OpcodeRegistry;
+use crate::SourceSpan;
+use crate::MAX_NESTING_DEPTH;
+
+const MAGIC: &[u8; 4] = b"AOI\0";
+const FORMAT_VERSION: u16 = 1;
+/// Tag of the optional debug section right after the header. It is not an
+/// opcode id, so it cannot be mistaken for the first opcode.
+const DEBUG_SECTION: u8 = 0xFE;
+
+/// Error returned when deserializing an invalid Aoi assembly blob.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AoDeserializeError {
+    /// The blob is shorter than the header.
+    MissingHeader,
+    /// The blob does not start with the Aoi magic.
+    BadMagic,
+    /// The blob was written by an unsupported format version.
+    UnsupportedVersion(u16),
+    /// The blob ends in the middle of an opcode.
+    UnexpectedEof,
+    /// A string immediate is not valid UTF-8.
+    InvalidUtf8,
+    /// No opcode has the given id.
+    UnknownOpcode(u8),
+    /// No register or immediate has the given tag.
+    UnknownArgTag(u8),
+    /// No immediate type has the given tag.
+    UnknownTypeTag(u8),
+    /// A map key has a type that cannot be a key, given by its tag.
+    InvalidMapKey(u8),
+    /// Arrays and maps nest deeper than [`MAX_NESTING_DEPTH`].
+    NestingTooDeep,
+    /// The reader failed with the given error kind.
+    Io(io::ErrorKind),
+}
+
+impl std::fmt::Display for AoDeserializeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AoDeserializeError::MissingHeader => write!(f, "Missing Header"),
+            AoDeserializeError::BadMagic => write!(f, "Bad Magic"),
+            AoDeserializeError::UnsupportedVersion(v) => write!(f, "Unsupported Version({})", v),
+            AoDeserializeError::UnexpectedEof => write!(f, "Unexpected EOF"),
+            AoDeserializeError::InvalidUtf8 => write!(f, "Invalid UTF-8"),
+            AoDeserializeError::UnknownOpcode(id) => write!(f, "Unknown Opcode({:#04X})", id),
+            AoDeserializeError::UnknownArgTag(tag) => write!(f, "Unknown Arg Tag({:#04X})", tag),
+            AoDeserializeError::UnknownTypeTag(tag) => write!(f, "Unknown Type Tag({:#04X})", tag),
+            AoDeserializeError::InvalidMapKey(tag) => write!(f, "Invalid Map Key({:#04X})", tag),
+            AoDeserializeError::NestingTooDeep => write!(f, "Nesting Too Deep"),
+            AoDeserializeError::Io(kind) => write!(f, "IO Error({})", kind),
+        }
+    }
+}
+
+impl std::error::Error for AoDeserializeError {}
+
+/// Load a program serialized by [`AoAsmSerializer::serialize`].
+///
+/// # Examples
+/// ```
+/// use aoi::*;
+///
+/// let bin = AoAsmSerializer::serialize(&ao_program![push dsb push "Hello Aoi!" cnf 1 int 1]);
+/// let program = load_program(&bin).unwrap();
+/// assert_eq!(program.len(), 4);
+/// ```
+pub fn load_program(bin: &[u8]) -> Result<AoProgram, AoDeserializeError> {
+    AoAsmSerializer::deserialize(bin)
+}
 
 /// Serializer for serializing and deserializing the Aoi assembly.
+///
+/// Serialized programs start with the magic `AOI\0` followed by the format
+/// version as a little-endian `u16`.
+///
+/// The header may be followed by a debug section holding the source span of
+/// every instruction: the tag `0xFE`, the number of spans as a `u32`, then
+/// the line and column of each span as `u32`s. Deserializing without spans
+/// skips the section.
+///
+/// Float NaNs are canonicalized to the quiet NaN `0x7FC00000`, so the output
+/// does not depend on how a NaN was produced.
 pub enum AoAsmSerializer {}
 
 impl AoAsmSerializer {
-    fn serialize_type(value: &AoType) -> Vec<u8> {
+    pub(crate) fn serialize_type(value: &AoType) -> Vec<u8> {
         let mut result = Vec::new();
+        AoAsmSerializer::write_type(value, 1, &mut result);
+        result
+    }
+
+    // Arrays and maps nested deeper than `MAX_NESTING_DEPTH` are written
+    // empty, since they could not be read back.
+    fn write_type(value: &AoType, depth: usize, result: &mut Vec<u8>) {
+        result.push(value.type_tag());
         match value {
             AoType::AoBool(value) => {
-                result.push(0x01);
                 result.push(if *value { 0x01 } else { 0x00 });
             }
             AoType::AoInt(value) => {
-                result.push(0x02);
                 result.extend_from_slice(&value.to_le_bytes());
             }
             AoType::AoFloat(value) => {
-                result.push(0x03);
+                // every NaN is written as the quiet NaN `0x7FC00000`
+                let value = if value.is_nan() { f32::NAN } else { *value };
                 result.extend_from_slice(&value.to_le_bytes());
             }
             AoType::AoPtr(value) => {
-                result.push(0x04);
                 result.extend_from_slice(&value.to_le_bytes());
             }
             AoType::AoString(value) => {
-                result.push(0x05);
                 result.extend_from_slice(&(value.len() as u32).to_le_bytes());
                 result.extend_from_slice(value.as_bytes());
             }
+            AoType::AoArray(_) | AoType::AoMap(_) if depth > MAX_NESTING_DEPTH => {
+                result.extend_from_slice(&0u32.to_le_bytes());
+            }
+            AoType::AoArray(value) => {
+                let value = value.borrow();
+                result.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                for item in value.iter() {
+                    AoAsmSerializer::write_type(item, depth + 1, result);
+                }
+            }
+            AoType::AoMap(value) => {
+                let value = value.borrow();
+                result.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                for (key, item) in value.iter() {
+                    AoAsmSerializer::write_type(&AoType::from(key.clone()), depth + 1, result);
+                    AoAsmSerializer::write_type(item, depth + 1, result);
+                }
+            }
         }
-        result
     }
 
     fn serialize_arg(value: &AoArg) -> Vec<u8> {
@@ -65,6 +174,9 @@ impl AoAsmSerializer {
             AoArg::MEM => {
                 result.push(0xE2);
             }
+            AoArg::GVS => {
+                result.push(0xE3);
+            }
             AoArg::Imm(value) => {
                 result.push(0xFF);
                 result.extend_from_slice(&AoAsmSerializer::serialize_type(value));
@@ -96,126 +208,860 @@ impl AoAsmSerializer {
                 result.extend_from_slice(&AoAsmSerializer::serialize_arg(&value1));
                 result.extend_from_slice(&AoAsmSerializer::serialize_arg(&value2));
             }
+            OpcodeArgType::u32_u32(value1, value2) => {
+                result.extend_from_slice(value1.to_le_bytes().as_ref());
+                result.extend_from_slice(value2.to_le_bytes().as_ref());
+            }
+            OpcodeArgType::u32_i32(value1, value2) => {
+                result.extend_from_slice(value1.to_le_bytes().as_ref());
+                result.extend_from_slice(value2.to_le_bytes().as_ref());
+            }
+            OpcodeArgType::AoTypes(values) => {
+                result.extend_from_slice(&(values.len() as u32).to_le_bytes());
+                for value in values.iter() {
+                    result.extend_from_slice(&AoAsmSerializer::serialize_type(value));
+                }
+            }
+            OpcodeArgType::u32s(values) => {
+                result.extend_from_slice(&(values.len() as u32).to_le_bytes());
+                for value in values.iter() {
+                    result.extend_from_slice(value.to_le_bytes().as_ref());
+                }
+            }
         }
         result
     }
 
-    pub fn serialize(asm: &[Box<dyn AoOpcode>]) -> Vec<u8> {
+    pub fn serialize<O: AoOpcode>(asm: &[O]) -> Vec<u8> {
+        let mut result = Vec::new();
+        AoAsmSerializer::serialize_to(asm, &mut result).unwrap();
+        result
+    }
+
+    /// Serialize the program with a debug section holding the source span of
+    /// every instruction, by index.
+    ///
+    /// # Examples
+    /// ```
+    /// use aoi::*;
+    ///
+    /// let program = ao_program![mov ca,1 inc];
+    /// let spans = vec![SourceSpan::new(1, 1), SourceSpan::new(2, 1)];
+    /// let bin = AoAsmSerializer::serialize_with_spans(&program, &spans);
+    ///
+    /// let (parsed, parsed_spans) = AoAsmSerializer::deserialize_with_spans(&bin).unwrap();
+    /// assert_eq!(parsed.len(), 2);
+    /// assert_eq!(parsed_spans, spans);
+    /// assert_eq!(AoAsmSerializer::deserialize(&bin).unwrap().len(), 2);
+    /// ```
+    pub fn serialize_with_spans<O: AoOpcode>(asm: &[O], spans: &[SourceSpan]) -> Vec<u8> {
         let mut result = Vec::new();
+        AoAsmSerializer::write_program(asm, Some(spans), &mut result).unwrap();
+        result
+    }
+
+    /// Serialize the program into a writer, one opcode at a time.
+    pub fn serialize_to<O: AoOpcode, W: Write>(asm: &[O], writer: &mut W) -> io::Result<()> {
+        AoAsmSerializer::write_program(asm, None, writer)
+    }
+
+    fn write_program<O: AoOpcode, W: Write>(
+        asm: &[O],
+        spans: Option<&[SourceSpan]>,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        if let Some(spans) = spans {
+            writer.write_all(&[DEBUG_SECTION])?;
+            writer.write_all(&(spans.len() as u32).to_le_bytes())?;
+            for span in spans {
+                writer.write_all(&span.line.to_le_bytes())?;
+                writer.write_all(&span.column.to_le_bytes())?;
+            }
+        }
         for opcode in asm {
-            result.extend_from_slice(&AoAsmSerializer::serialize_opcode(opcode.as_ref()));
+            writer.write_all(&AoAsmSerializer::serialize_opcode(opcode))?;
+        }
+        Ok(())
+    }
+
+    /// Render the program as text, one opcode per line prefixed with its index.
+    ///
+    /// The index is written as a comment, the same way it is annotated in
+    /// hand-written programs.
+    ///
+    /// # Examples
+    /// ```
+    /// use aoi::*;
+    ///
+    /// let program = ao_program![push dsb push "Hi" cnf 1 int 1];
+    /// assert_eq!(
+    ///     AoAsmSerializer::disassemble(&program),
+    ///     "/*   0 */ push dsb\n/*   1 */ push \"Hi\"\n/*   2 */ cnf 1\n/*   3 */ int 1\n"
+    /// );
+    /// ```
+    pub fn disassemble<O: AoOpcode>(asm: &[O]) -> String {
+        let width = asm.len().saturating_sub(1).to_string().len().max(3);
+        let mut result = String::new();
+        for (index, opcode) in asm.iter().enumerate() {
+            result.push_str(&format!("/* {:>width$} */ {}\n", index, opcode));
         }
         result
     }
 
-    fn deserialize_type(bin: &[u8], offset: &mut usize) -> Option<AoType> {
-        match bin[*offset] {
-            0x01 => {
-                *offset += 2;
-                Some(AoType::AoBool(bin[*offset - 1] != 0x00))
-            }
-            0x02 => {
-                *offset += 5;
-                Some(AoType::AoInt(i32::from_le_bytes(
-                    bin[*offset - 4..*offset].try_into().unwrap(),
-                )))
+    fn read_error(e: io::Error) -> AoDeserializeError {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => AoDeserializeError::UnexpectedEof,
+            kind => AoDeserializeError::Io(kind),
+        }
+    }
+
+    fn take<R: Read>(reader: &mut R, len: usize) -> Result<Vec<u8>, AoDeserializeError> {
+        let mut result = Vec::new();
+        reader
+            .take(len as u64)
+            .read_to_end(&mut result)
+            .map_err(AoAsmSerializer::read_error)?;
+        if result.len() < len {
+            return Err(AoDeserializeError::UnexpectedEof);
+        }
+        Ok(result)
+    }
+
+    fn take_u8<R: Read>(reader: &mut R) -> Result<u8, AoDeserializeError> {
+        let mut buf = [0; 1];
+        reader
+            .read_exact(&mut buf)
+            .map_err(AoAsmSerializer::read_error)?;
+        Ok(buf[0])
+    }
+
+    fn take_4<R: Read>(reader: &mut R) -> Result<[u8; 4], AoDeserializeError> {
+        let mut buf = [0; 4];
+        reader
+            .read_exact(&mut buf)
+            .map_err(AoAsmSerializer::read_error)?;
+        Ok(buf)
+    }
+
+    fn deserialize_type<R: Read>(reader: &mut R) -> Result<AoType, AoDeserializeError> {
+        AoAsmSerializer::read_type(reader, 1)
+    }
+
+    fn read_type<R: Read>(reader: &mut R, depth: usize) -> Result<AoType, AoDeserializeError> {
+        match AoAsmSerializer::take_u8(reader)? {
+            0x01 => Ok(AoType::AoBool(AoAsmSerializer::take_u8(reader)? != 0x00)),
+            0x02 => Ok(AoType::AoInt(i32::from_le_bytes(AoAsmSerializer::take_4(
+                reader,
+            )?))),
+            0x03 => Ok(AoType::AoFloat(f32::from_le_bytes(
+                AoAsmSerializer::take_4(reader)?,
+            ))),
+            0x04 => Ok(AoType::AoPtr(u32::from_le_bytes(AoAsmSerializer::take_4(
+                reader,
+            )?))),
+            0x05 => {
+                let str_len = u32::from_le_bytes(AoAsmSerializer::take_4(reader)?) as usize;
+                let bytes = AoAsmSerializer::take(reader, str_len)?;
+                String::from_utf8(bytes)
+                    .map(AoType::from)
+                    .map_err(|_| AoDeserializeError::InvalidUtf8)
             }
-            0x03 => {
-                *offset += 5;
-                Some(AoType::AoFloat(f32::from_le_bytes(
-                    bin[*offset - 4..*offset].try_into().unwrap(),
-                )))
+            0x06 | 0x07 if depth > MAX_NESTING_DEPTH => Err(AoDeserializeError::NestingTooDeep),
+            0x06 => {
+                let len = u32::from_le_bytes(AoAsmSerializer::take_4(reader)?);
+                let mut items = Vec::new();
+                for _ in 0..len {
+                    items.push(AoAsmSerializer::read_type(reader, depth + 1)?);
+                }
+                Ok(AoType::AoArray(Rc::new(RefCell::new(items))))
             }
-            0x04 => {
-                *offset += 5;
-                Some(AoType::AoPtr(u32::from_le_bytes(
-                    bin[*offset - 4..*offset].try_into().unwrap(),
-                )))
+            0x07 => {
+                let len = u32::from_le_bytes(AoAsmSerializer::take_4(reader)?);
+                let mut entries = BTreeMap::new();
+                for _ in 0..len {
+                    let key = AoAsmSerializer::read_type(reader, depth + 1)?;
+                    let key = AoMapKey::new(&key)
+                        .ok_or(AoDeserializeError::InvalidMapKey(key.type_tag()))?;
+                    entries.insert(key, AoAsmSerializer::read_type(reader, depth + 1)?);
+                }
+                Ok(AoType::AoMap(Rc::new(RefCell::new(entries))))
             }
-            0x05 => {
-                let str_len =
-                    u32::from_le_bytes(bin[*offset + 1..*offset + 5].try_into().unwrap()) as usize;
-                *offset += 5 + str_len;
-                Some(AoType::AoString(
-                    String::from_utf8(bin[*offset - str_len..*offset].to_vec()).unwrap(),
-                ))
-            }
-            _ => None,
-        }
-    }
-
-    fn deserialize_arg(bin: &[u8], offset: &mut usize) -> Option<AoArg> {
-        *offset += 1;
-        match bin[*offset - 1] {
-            0x01 => Some(AoArg::PC),
-            0x02 => Some(AoArg::DP),
-            0x03 => Some(AoArg::MP),
-            0x11 => Some(AoArg::DSB),
-            0x12 => Some(AoArg::DST),
-            0x21 => Some(AoArg::CA),
-            0x22 => Some(AoArg::CB),
-            0xE1 => Some(AoArg::DS),
-            0xE2 => Some(AoArg::MEM),
-            0xFF => Some(AoArg::Imm(
-                AoAsmSerializer::deserialize_type(bin, offset).unwrap(),
-            )),
-            _ => None,
-        }
-    }
-
-    fn deserialize_opcode(bin: &[u8], offset: &mut usize) -> Option<Box<dyn AoOpcode>> {
-        *offset += 1;
-        let opcode = create_opcode_by_id(bin[*offset - 1]);
-        opcode.as_ref()?;
-        let mut opcode = opcode.unwrap();
+            tag => Err(AoDeserializeError::UnknownTypeTag(tag)),
+        }
+    }
 
-        match opcode.get_args() {
-            OpcodeArgType::NoArg => (),
-            OpcodeArgType::u8(_) => {
-                *offset += 1;
-                opcode.set_args(OpcodeArgType::u8(bin[*offset - 1]));
-            }
+    fn deserialize_arg<R: Read>(reader: &mut R) -> Result<AoArg, AoDeserializeError> {
+        match AoAsmSerializer::take_u8(reader)? {
+            0x01 => Ok(AoArg::PC),
+            0x02 => Ok(AoArg::DP),
+            0x03 => Ok(AoArg::MP),
+            0x11 => Ok(AoArg::DSB),
+            0x12 => Ok(AoArg::DST),
+            0x21 => Ok(AoArg::CA),
+            0x22 => Ok(AoArg::CB),
+            0xE1 => Ok(AoArg::DS),
+            0xE2 => Ok(AoArg::MEM),
+            0xE3 => Ok(AoArg::GVS),
+            0xFF => Ok(AoArg::Imm(AoAsmSerializer::deserialize_type(reader)?)),
+            tag => Err(AoDeserializeError::UnknownArgTag(tag)),
+        }
+    }
+
+    fn deserialize_opcode<R: Read, C: Fn(u8) -> Option<Box<dyn AoOpcode>>>(
+        create: &C,
+        id: u8,
+        reader: &mut R,
+    ) -> Result<Box<dyn AoOpcode>, AoDeserializeError> {
+        let mut opcode = create(id).ok_or(AoDeserializeError::UnknownOpcode(id))?;
+
+        let args = match opcode.get_args() {
+            OpcodeArgType::NoArg => OpcodeArgType::NoArg,
+            OpcodeArgType::u8(_) => OpcodeArgType::u8(AoAsmSerializer::take_u8(reader)?),
             OpcodeArgType::i32(_) => {
-                *offset += 4;
-                opcode.set_args(OpcodeArgType::i32(i32::from_le_bytes(
-                    bin[*offset - 4..*offset].try_into().unwrap(),
-                )));
+                OpcodeArgType::i32(i32::from_le_bytes(AoAsmSerializer::take_4(reader)?))
             }
             OpcodeArgType::u32(_) => {
-                *offset += 4;
-                opcode.set_args(OpcodeArgType::u32(u32::from_le_bytes(
-                    bin[*offset - 4..*offset].try_into().unwrap(),
-                )));
+                OpcodeArgType::u32(u32::from_le_bytes(AoAsmSerializer::take_4(reader)?))
             }
             OpcodeArgType::bool(_) => {
-                *offset += 1;
-                opcode.set_args(OpcodeArgType::bool(bin[*offset - 1] != 0x00));
+                OpcodeArgType::bool(AoAsmSerializer::take_u8(reader)? != 0x00)
             }
             OpcodeArgType::AoArg(_) => {
-                let value = AoAsmSerializer::deserialize_arg(bin, offset).unwrap();
-                opcode.set_args(OpcodeArgType::AoArg(value));
+                OpcodeArgType::AoArg(AoAsmSerializer::deserialize_arg(reader)?)
             }
             OpcodeArgType::AoArg2(_, _) => {
-                let value1 = AoAsmSerializer::deserialize_arg(bin, offset).unwrap();
-                let value2 = AoAsmSerializer::deserialize_arg(bin, offset).unwrap();
-                opcode.set_args(OpcodeArgType::AoArg2(value1, value2));
+                let value1 = AoAsmSerializer::deserialize_arg(reader)?;
+                let value2 = AoAsmSerializer::deserialize_arg(reader)?;
+                OpcodeArgType::AoArg2(value1, value2)
+            }
+            OpcodeArgType::u32_u32(_, _) => OpcodeArgType::u32_u32(
+                u32::from_le_bytes(AoAsmSerializer::take_4(reader)?),
+                u32::from_le_bytes(AoAsmSerializer::take_4(reader)?),
+            ),
+            OpcodeArgType::u32_i32(_, _) => OpcodeArgType::u32_i32(
+                u32::from_le_bytes(AoAsmSerializer::take_4(reader)?),
+                i32::from_le_bytes(AoAsmSerializer::take_4(reader)?),
+            ),
+            OpcodeArgType::AoTypes(_) => {
+                let len = u32::from_le_bytes(AoAsmSerializer::take_4(reader)?);
+                let mut values = Vec::new();
+                for _ in 0..len {
+                    values.push(AoAsmSerializer::deserialize_type(reader)?);
+                }
+                OpcodeArgType::AoTypes(values)
             }
+            OpcodeArgType::u32s(_) => {
+                let len = u32::from_le_bytes(AoAsmSerializer::take_4(reader)?);
+                let mut values = Vec::new();
+                for _ in 0..len {
+                    values.push(u32::from_le_bytes(AoAsmSerializer::take_4(reader)?));
+                }
+                OpcodeArgType::u32s(values)
+            }
+        };
+        opcode.set_args(args);
+
+        Ok(opcode)
+    }
+
+    pub fn deserialize(value: &[u8]) -> Result<AoProgram, AoDeserializeError> {
+        AoAsmSerializer::deserialize_from(&mut &value[..])
+    }
+
+    /// Deserialize a program along with the source spans of its debug
+    /// section, which are empty if the section is missing.
+    pub fn deserialize_with_spans(
+        value: &[u8],
+    ) -> Result<(AoProgram, Vec<SourceSpan>), AoDeserializeError> {
+        AoAsmSerializer::read_program(&create_opcode_by_id, &mut &value[..])
+    }
+
+    /// Deserialize a program using the opcodes in `registry`.
+    pub fn deserialize_with(
+        registry: &OpcodeRegistry,
+        value: &[u8],
+    ) -> Result<AoProgram, AoDeserializeError> {
+        AoAsmSerializer::deserialize_from_with(registry, &mut &value[..])
+    }
+
+    /// Deserialize a program from a reader, one opcode at a time.
+    pub fn deserialize_from<R: Read>(reader: &mut R) -> Result<AoProgram, AoDeserializeError> {
+        AoAsmSerializer::read_program(&create_opcode_by_id, reader).map(|(program, _)| program)
+    }
+
+    /// Deserialize a program from a reader using the opcodes in `registry`.
+    pub fn deserialize_from_with<R: Read>(
+        registry: &OpcodeRegistry,
+        reader: &mut R,
+    ) -> Result<AoProgram, AoDeserializeError> {
+        AoAsmSerializer::read_program(&|id| registry.create(id), reader).map(|(program, _)| program)
+    }
+
+    fn read_header<R: Read>(reader: &mut R) -> Result<(), AoDeserializeError> {
+        let mut header = [0; 6];
+        reader.read_exact(&mut header).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => AoDeserializeError::MissingHeader,
+            kind => AoDeserializeError::Io(kind),
+        })?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(AoDeserializeError::BadMagic);
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != FORMAT_VERSION {
+            return Err(AoDeserializeError::UnsupportedVersion(version));
         }
+        Ok(())
+    }
 
-        Some(opcode)
+    /// Read the debug section after its tag.
+    fn read_spans<R: Read>(reader: &mut R) -> Result<Vec<SourceSpan>, AoDeserializeError> {
+        let len = u32::from_le_bytes(AoAsmSerializer::take_4(reader)?);
+        let mut spans = Vec::new();
+        for _ in 0..len {
+            let line = u32::from_le_bytes(AoAsmSerializer::take_4(reader)?);
+            let column = u32::from_le_bytes(AoAsmSerializer::take_4(reader)?);
+            spans.push(SourceSpan { line, column });
+        }
+        Ok(spans)
     }
 
-    pub fn deserialize(value: &[u8]) -> Option<AoProgram> {
+    /// Decode a serialized program one instruction at a time, yielding the
+    /// byte offset of each instruction in `bin` with the decoded opcode.
+    ///
+    /// The length of an instruction is the distance to the next offset, or
+    /// to the end of `bin` for the last one. Iteration stops after the first
+    /// error.
+    ///
+    /// # Examples
+    /// ```
+    /// use aoi::*;
+    ///
+    /// let bin = AoAsmSerializer::serialize(&ao_program![nop ret]);
+    /// let offsets: Vec<usize> = AoAsmSerializer::iter_instructions(&bin)
+    ///     .map(|item| item.unwrap().0)
+    ///     .collect();
+    /// assert_eq!(offsets, vec![6, 7]);
+    /// ```
+    pub fn iter_instructions(
+        bin: &[u8],
+    ) -> impl Iterator<Item = Result<(usize, Box<dyn AoOpcode>), AoDeserializeError>> + '_ {
+        let mut rest = bin;
+        let mut failed = AoAsmSerializer::read_header(&mut rest).err();
+        if failed.is_none() {
+            if let Some((&DEBUG_SECTION, tail)) = rest.split_first() {
+                rest = tail;
+                failed = AoAsmSerializer::read_spans(&mut rest).err();
+            }
+        }
+        std::iter::from_fn(move || {
+            if let Some(error) = failed.take() {
+                rest = &[];
+                return Some(Err(error));
+            }
+            let (&id, tail) = rest.split_first()?;
+            let offset = bin.len() - rest.len();
+            rest = tail;
+            match AoAsmSerializer::deserialize_opcode(&create_opcode_by_id, id, &mut rest) {
+                Ok(opcode) => Some(Ok((offset, opcode))),
+                Err(error) => {
+                    rest = &[];
+                    Some(Err(error))
+                }
+            }
+        })
+    }
+
+    fn read_program<R: Read, C: Fn(u8) -> Option<Box<dyn AoOpcode>>>(
+        create: &C,
+        reader: &mut R,
+    ) -> Result<(AoProgram, Vec<SourceSpan>), AoDeserializeError> {
+        AoAsmSerializer::read_header(reader)?;
+
         let mut result = Vec::new();
-        let mut offset = 0;
-        while offset < value.len() {
-            let opcode = AoAsmSerializer::deserialize_opcode(value, &mut offset);
-            if let Some(opcode) = opcode {
-                result.push(opcode);
-            } else {
-                return None;
+        let mut spans = Vec::new();
+        let mut first = true;
+        let mut id = [0; 1];
+        loop {
+            match reader.read(&mut id) {
+                Ok(0) => break,
+                Ok(_) if first && id[0] == DEBUG_SECTION => {
+                    spans = AoAsmSerializer::read_spans(reader)?;
+                }
+                Ok(_) => result.push(AoAsmSerializer::deserialize_opcode(create, id[0], reader)?),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(AoDeserializeError::Io(e.kind())),
+            }
+            first = false;
+        }
+        Ok((result, spans))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+    use std::rc::Rc;
+
+    use crate::opcodes::*;
+    use crate::*;
+
+    #[test]
+    fn test_disassemble() {
+        let program = ao_program![
+            mov ca,1
+            add 2
+            jt (-2)
+            push "a b"
+            pop
+        ];
+        assert_eq!(
+            AoAsmSerializer::disassemble(&program),
+            concat!(
+                "/*   0 */ mov ca,1\n",
+                "/*   1 */ add 2\n",
+                "/*   2 */ jt -2\n",
+                "/*   3 */ push \"a b\"\n",
+                "/*   4 */ pop\n",
+            )
+        );
+
+        let program: AoProgram = (0..1001)
+            .map(|_| Box::new(Nop) as Box<dyn AoOpcode>)
+            .collect();
+        let text = AoAsmSerializer::disassemble(&program);
+        assert!(text.starts_with("/*    0 */ nop\n"));
+        assert!(text.ends_with("/* 1000 */ nop\n"));
+        assert_eq!(AoAsmSerializer::disassemble(&AoProgram::new()), "");
+    }
+
+    #[test]
+    fn test_nan() {
+        let quiet = f32::NAN;
+        let other = f32::from_bits(0xFFA0_0001);
+        assert!(other.is_nan());
+        assert_ne!(quiet.to_bits(), other.to_bits());
+
+        let program = |value: f32| -> AoProgram {
+            vec![Box::new(Mov {
+                dst: AoArg::CA,
+                src: AoArg::Imm(AoType::AoFloat(value)),
+            })]
+        };
+        let a = AoAsmSerializer::serialize(&program(quiet));
+        let b = AoAsmSerializer::serialize(&program(other));
+        assert_eq!(a, b);
+
+        let program = AoAsmSerializer::deserialize(&b).unwrap();
+        match program[0].get_args() {
+            OpcodeArgType::AoArg2(_, AoArg::Imm(AoType::AoFloat(v))) => {
+                assert_eq!(v.to_bits(), 0x7FC0_0000)
             }
+            _ => panic!("expected a float immediate"),
         }
-        Some(result)
+    }
+
+    #[test]
+    fn test_nopn() {
+        let mut program = ao_program![
+            mov ca,1
+            nopn 0
+            inc
+        ];
+        let bin = AoAsmSerializer::serialize(&program[1..]);
+        assert_eq!(bin.len(), 6 + 5 + 1);
+        assert_eq!(&bin[6..11], &[0x01, 0, 0, 0, 0]);
+
+        let patched = ao_program![jmpa 2];
+        assert_eq!(
+            AoAsmSerializer::serialize(&patched).len() - 6,
+            AoAsmSerializer::serialize(&program[1..2]).len() - 6
+        );
+
+        let mut vm = AoVM::default();
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoInt(2));
+
+        program[1] = ao_asm!(jmpa 3);
+        vm.reset();
+        assert_eq!(vm.run(&program), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoInt(1));
+    }
+
+    #[test]
+    fn test_header() {
+        let program = ao_program![push dsb push "Hello" cnf 1 int 1];
+        let bin = AoAsmSerializer::serialize(&program);
+        assert_eq!(&bin[..6], b"AOI\0\x01\x00");
+
+        let parsed = AoAsmSerializer::deserialize(&bin).unwrap();
+        assert_eq!(AoAsmSerializer::serialize(&parsed), bin);
+        assert_eq!(
+            AoAsmSerializer::deserialize(&bin[..6]).map(|p| p.len()),
+            Ok(0)
+        );
+
+        let mut bad = bin.clone();
+        bad[0] = b'X';
+        assert_eq!(
+            AoAsmSerializer::deserialize(&bad).err(),
+            Some(AoDeserializeError::BadMagic)
+        );
+        let mut bad = bin.clone();
+        bad[4] = 2;
+        assert_eq!(
+            AoAsmSerializer::deserialize(&bad).err(),
+            Some(AoDeserializeError::UnsupportedVersion(2))
+        );
+        assert_eq!(
+            AoAsmSerializer::deserialize(b"AOI").err(),
+            Some(AoDeserializeError::MissingHeader)
+        );
+        assert_eq!(
+            AoAsmSerializer::deserialize(&[0x8B, 0x21, 0xF5, 0x3C, 0x00, 0x9A, 0x10]).err(),
+            Some(AoDeserializeError::BadMagic)
+        );
+        assert_eq!(
+            AoAsmSerializer::deserialize(b"AOI\0\x01\x00\xFF").err(),
+            Some(AoDeserializeError::UnknownOpcode(0xFF))
+        );
+    }
+
+    #[test]
+    fn test_deserialize_error() {
+        let bin = AoAsmSerializer::serialize(&ao_program![push "Hello"]);
+
+        // string length running past the buffer
+        let mut bad = bin.clone();
+        bad[9..13].copy_from_slice(&100u32.to_le_bytes());
+        assert_eq!(
+            AoAsmSerializer::deserialize(&bad).err(),
+            Some(AoDeserializeError::UnexpectedEof)
+        );
+        assert_eq!(
+            AoAsmSerializer::deserialize(&bin[..bin.len() - 1]).err(),
+            Some(AoDeserializeError::UnexpectedEof)
+        );
+
+        let mut bad = bin.clone();
+        bad[13] = 0xFF;
+        assert_eq!(
+            AoAsmSerializer::deserialize(&bad).err(),
+            Some(AoDeserializeError::InvalidUtf8)
+        );
+
+        let mut bad = bin.clone();
+        bad[7] = 0x7F;
+        assert_eq!(
+            AoAsmSerializer::deserialize(&bad).err(),
+            Some(AoDeserializeError::UnknownArgTag(0x7F))
+        );
+
+        let mut bad = bin.clone();
+        bad[6] = 0xEE;
+        assert_eq!(
+            AoAsmSerializer::deserialize(&bad).err(),
+            Some(AoDeserializeError::UnknownOpcode(0xEE))
+        );
+    }
+
+    #[test]
+    fn test_stream() {
+        let program = ao_program![
+            push dsb
+            push "Hello"
+            cnf 1
+            int 1
+            cswap 0,1
+            mov ca,2.5
+        ];
+        let mut bin = Vec::new();
+        AoAsmSerializer::serialize_to(&program, &mut bin).unwrap();
+        assert_eq!(bin, AoAsmSerializer::serialize(&program));
+
+        let mut reader = std::io::Cursor::new(bin);
+        let parsed = AoAsmSerializer::deserialize_from(&mut reader).unwrap();
+        assert_eq!(
+            AoAsmSerializer::disassemble(&parsed),
+            AoAsmSerializer::disassemble(&program)
+        );
+    }
+
+    #[test]
+    fn test_load_program() {
+        let program = ao_program![
+            push dsb
+            push "Hello Aoi!"
+            cnf 1
+            int 1
+        ];
+        let bin = AoAsmSerializer::serialize(&program);
+        let loaded = load_program(&bin).unwrap();
+        assert_eq!(
+            AoAsmSerializer::disassemble(&loaded),
+            AoAsmSerializer::disassemble(&program)
+        );
+
+        let mut vm = AoVM::default();
+        assert_eq!(vm.run(&loaded), AoStatus::Exit);
+        assert_eq!(
+            load_program(&bin[..3]).err(),
+            Some(AoDeserializeError::MissingHeader)
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let program = ao_program![
+            mov ca,1
+            sub "one"
+            halt
+        ];
+        let spans = vec![
+            SourceSpan::new(1, 1),
+            SourceSpan::new(2, 7),
+            SourceSpan::new(3, 1),
+        ];
+        let bin = AoAsmSerializer::serialize_with_spans(&program, &spans);
+        let plain = AoAsmSerializer::serialize(&program);
+        assert_eq!(bin[6], 0xFE);
+        assert_eq!(bin.len(), plain.len() + 1 + 4 + 3 * 8);
+        assert_eq!(&bin[..6], &plain[..6]);
+        assert_eq!(&bin[6 + 1 + 4 + 3 * 8..], &plain[6..]);
+
+        let (parsed, parsed_spans) = AoAsmSerializer::deserialize_with_spans(&bin).unwrap();
+        assert_eq!(parsed_spans, spans);
+        assert_eq!(AoAsmSerializer::serialize(&parsed), plain);
+        assert_eq!(AoAsmSerializer::deserialize(&bin).unwrap().len(), 3);
+        assert_eq!(
+            AoAsmSerializer::deserialize_with_spans(&plain).unwrap().1,
+            vec![]
+        );
+
+        let offsets: Vec<usize> = AoAsmSerializer::iter_instructions(&bin)
+            .map(|item| item.unwrap().0)
+            .collect();
+        assert_eq!(offsets[0], 6 + 1 + 4 + 3 * 8);
+        assert_eq!(offsets.len(), 3);
+
+        assert_eq!(
+            AoAsmSerializer::deserialize(&bin[..20]).err(),
+            Some(AoDeserializeError::UnexpectedEof)
+        );
+
+        let mut vm = AoVM::default();
+        vm.set_source_spans(parsed_spans);
+        match vm.run(&parsed) {
+            AoStatus::AtSource { status, span } => {
+                assert!(matches!(*status, AoStatus::InvalidOperation(_)));
+                assert_eq!(span.line, 2);
+            }
+            status => panic!("unexpected status {}", status),
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        struct Twice;
+
+        impl std::fmt::Display for Twice {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "twice")
+            }
+        }
+
+        impl Serializable for Twice {
+            fn get_id(&self) -> u8 {
+                0x90
+            }
+            fn get_args(&self) -> OpcodeArgType {
+                OpcodeArgType::NoArg
+            }
+            fn set_args(&mut self, _args: OpcodeArgType) {}
+        }
+
+        impl AoOpcode for Twice {
+            fn execute(&self, vm: &mut AoVM) -> AoStatus {
+                match vm.ca {
+                    AoType::AoInt(v) => vm.ca = AoType::AoInt(v * 2),
+                    _ => return AoStatus::InvalidOperation("twice on non-int".to_string()),
+                }
+                AoStatus::Ok
+            }
+        }
+
+        let mut program = ao_program![mov ca,21];
+        program.push(Box::new(Twice));
+        let bin = AoAsmSerializer::serialize(&program);
+        assert_eq!(
+            AoAsmSerializer::deserialize(&bin).err(),
+            Some(AoDeserializeError::UnknownOpcode(0x90))
+        );
+
+        let mut registry = OpcodeRegistry::default();
+        assert!(registry.register(0x90, || Box::new(Twice)));
+        let parsed = AoAsmSerializer::deserialize_with(&registry, &bin).unwrap();
+        assert_eq!(
+            AoAsmSerializer::disassemble(&parsed),
+            "/*   0 */ mov ca,21\n/*   1 */ twice\n"
+        );
+
+        let mut vm = AoVM::default();
+        assert_eq!(vm.run(&parsed), AoStatus::Exit);
+        assert_eq!(vm.ca, AoType::AoInt(42));
+    }
+
+    #[test]
+    fn test_iter_instructions() {
+        let program = ao_program![
+            push dsb
+            push "Hello Aoi!"
+            cnf 1
+            int 1
+        ];
+        let bin = AoAsmSerializer::serialize(&program);
+        let items: Vec<(usize, String)> = AoAsmSerializer::iter_instructions(&bin)
+            .map(|item| item.map(|(offset, opcode)| (offset, opcode.to_string())))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            items,
+            vec![
+                (6, "push dsb".to_string()),
+                (8, "push \"Hello Aoi!\"".to_string()),
+                (25, "cnf 1".to_string()),
+                (30, "int 1".to_string()),
+            ]
+        );
+        assert_eq!(bin.len(), 32);
+
+        let errors: Vec<_> = AoAsmSerializer::iter_instructions(&bin[..20]).collect();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[1].as_ref().err(),
+            Some(&AoDeserializeError::UnexpectedEof)
+        );
+
+        let mut iter = AoAsmSerializer::iter_instructions(b"AOI");
+        assert_eq!(
+            iter.next().unwrap().err(),
+            Some(AoDeserializeError::MissingHeader)
+        );
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_array() {
+        let array = |items: Vec<AoType>| AoType::AoArray(Rc::new(RefCell::new(items)));
+        let value = array(vec![
+            AoType::AoInt(1),
+            AoType::from("two"),
+            array(vec![AoType::AoBool(true)]),
+        ]);
+        assert_eq!(
+            AoAsmSerializer::serialize_type(&value),
+            vec![
+                0x06, 3, 0, 0, 0, // array of 3
+                0x02, 1, 0, 0, 0, // 1
+                0x05, 3, 0, 0, 0, b't', b'w', b'o', // "two"
+                0x06, 1, 0, 0, 0, 0x01, 0x01, // [true]
+            ]
+        );
+
+        let program: AoProgram = vec![Box::new(Mov {
+            dst: AoArg::CA,
+            src: AoArg::Imm(value.clone()),
+        })];
+        let bin = AoAsmSerializer::serialize(&program);
+        let program = AoAsmSerializer::deserialize(&bin).unwrap();
+        match program[0].get_args() {
+            OpcodeArgType::AoArg2(AoArg::CA, AoArg::Imm(v)) => assert_eq!(v, value),
+            _ => panic!("expected an array immediate"),
+        }
+        assert_eq!(program[0].to_string(), "mov ca,[1, \"two\", [true]]");
+
+        let mut bin = AoAsmSerializer::serialize(&program);
+        bin.truncate(bin.len() - 1);
+        assert_eq!(
+            AoAsmSerializer::deserialize(&bin).err(),
+            Some(AoDeserializeError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        // `push` followed by the tag of an immediate
+        let mut head = AoAsmSerializer::serialize(&ao_program![push 1]);
+        head.truncate(8);
+        let nested = |depth: usize| {
+            let mut bin = head.clone();
+            for _ in 0..depth {
+                bin.extend_from_slice(&[0x06, 1, 0, 0, 0]);
+            }
+            bin.extend_from_slice(&[0x02, 7, 0, 0, 0]);
+            bin
+        };
+
+        let program = AoAsmSerializer::deserialize(&nested(MAX_NESTING_DEPTH)).unwrap();
+        assert_eq!(
+            program[0].to_string(),
+            format!(
+                "push {}7{}",
+                "[".repeat(MAX_NESTING_DEPTH),
+                "]".repeat(MAX_NESTING_DEPTH)
+            )
+        );
+        assert_eq!(
+            AoAsmSerializer::deserialize(&nested(MAX_NESTING_DEPTH + 1)).err(),
+            Some(AoDeserializeError::NestingTooDeep)
+        );
+        assert_eq!(
+            AoAsmSerializer::deserialize(&nested(1_000_000)).err(),
+            Some(AoDeserializeError::NestingTooDeep)
+        );
+        assert_eq!(
+            AoDeserializeError::NestingTooDeep.to_string(),
+            "Nesting Too Deep"
+        );
+    }
+
+    #[test]
+    fn test_map() {
+        let map = AoType::AoMap(Rc::new(RefCell::new(BTreeMap::from([
+            (AoMapKey::Int(1), AoType::from("one")),
+            (AoMapKey::String("two".into()), AoType::AoFloat(2.0)),
+        ]))));
+        assert_eq!(
+            AoAsmSerializer::serialize_type(&map),
+            vec![
+                0x07, 2, 0, 0, 0, // map of 2
+                0x02, 1, 0, 0, 0, // 1
+                0x05, 3, 0, 0, 0, b'o', b'n', b'e', // "one"
+                0x05, 3, 0, 0, 0, b't', b'w', b'o', // "two"
+                0x03, 0, 0, 0, 0x40, // 2f
+            ]
+        );
+
+        let program: AoProgram = vec![Box::new(Push {
+            src: AoArg::Imm(map.clone()),
+        })];
+        let bin = AoAsmSerializer::serialize(&program);
+        let program = AoAsmSerializer::deserialize(&bin).unwrap();
+        match program[0].get_args() {
+            OpcodeArgType::AoArg(AoArg::Imm(v)) => assert_eq!(v, map),
+            _ => panic!("expected a map immediate"),
+        }
+
+        // a float key
+        let mut bin = AoAsmSerializer::serialize(&ao_program![nop]);
+        bin.extend_from_slice(&[
+            0x22, 0xFF, 0x07, 1, 0, 0, 0, 0x03, 0, 0, 0, 0, 0x02, 0, 0, 0, 0,
+        ]);
+        assert_eq!(
+            AoAsmSerializer::deserialize(&bin).err(),
+            Some(AoDeserializeError::InvalidMapKey(0x03))
+        );
     }
 }
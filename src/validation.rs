@@ -0,0 +1,189 @@
+use std::fmt::Display;
+
+use crate::opcodes::*;
+use crate::AoArg;
+use crate::AoStatus;
+use crate::AoVM;
+
+/// Problem found by [`validate`], with the index of the offending opcode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The opcode id is not a built-in opcode.
+    UnknownOpcode { index: usize, id: u8 },
+    /// A `mov` writes to an immediate.
+    ImmediateDestination { index: usize },
+}
+
+impl ValidationError {
+    /// Index of the opcode in the program.
+    pub fn index(&self) -> usize {
+        match self {
+            ValidationError::UnknownOpcode { index, .. } => *index,
+            ValidationError::ImmediateDestination { index } => *index,
+        }
+    }
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::UnknownOpcode { index, id } => {
+                write!(f, "Unknown Opcode({:#04X}) at {}", id, index)
+            }
+            ValidationError::ImmediateDestination { index } => {
+                write!(f, "Immediate Destination at {}", index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Check a program for opcodes that cannot be serialized or executed as
+/// built.
+///
+/// Every opcode must be a built-in one, and `mov` must not write to an
+/// immediate. The first problem is returned.
+///
+/// # Examples
+/// ```
+/// use aoi::*;
+/// use aoi::opcodes::Mov;
+///
+/// assert_eq!(validate(&ao_program![mov ca,1 push ca]), Ok(()));
+///
+/// let program: AoProgram = vec![Box::new(Mov {
+///     dst: AoArg::from(1),
+///     src: AoArg::CA,
+/// })];
+/// assert_eq!(
+///     validate(&program),
+///     Err(ValidationError::ImmediateDestination { index: 0 })
+/// );
+/// ```
+pub fn validate(program: &[Box<dyn AoOpcode>]) -> Result<(), ValidationError> {
+    for (index, opcode) in program.iter().enumerate() {
+        let id = opcode.get_id();
+        if create_opcode_by_id(id).is_none() {
+            return Err(ValidationError::UnknownOpcode { index, id });
+        }
+        // mov
+        if let (0x20, OpcodeArgType::AoArg2(AoArg::Imm(_), _)) = (id, opcode.get_args()) {
+            return Err(ValidationError::ImmediateDestination { index });
+        }
+    }
+    Ok(())
+}
+
+/// Validate a program, then run it in safe mode.
+///
+/// Safe mode is turned on in the VM before running, so a program that
+/// passes [`validate`] stops with a status instead of panicking. Limit the
+/// steps with [`AoVM::set_gas_limit`] when the program is untrusted.
+///
+/// # Examples
+/// ```
+/// use aoi::*;
+///
+/// let mut vm = AoVM::default();
+/// let program = ao_program![mov ca,1 div 0];
+/// assert_eq!(
+///     validate_and_run(&mut vm, &program),
+///     Ok(AoStatus::InvalidOperation("1 / 0".to_string()))
+/// );
+/// assert!(vm.safe_mode);
+/// ```
+pub fn validate_and_run(
+    vm: &mut AoVM,
+    program: &[Box<dyn AoOpcode>],
+) -> Result<AoStatus, ValidationError> {
+    validate(program)?;
+    vm.safe_mode = true;
+    Ok(vm.run(program))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[derive(Default)]
+    struct Custom;
+
+    impl Display for Custom {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "custom")
+        }
+    }
+
+    impl Serializable for Custom {
+        fn get_id(&self) -> u8 {
+            0x90
+        }
+        fn get_args(&self) -> OpcodeArgType {
+            OpcodeArgType::NoArg
+        }
+        fn set_args(&mut self, _: OpcodeArgType) {}
+    }
+
+    impl AoOpcode for Custom {
+        fn execute(&self, _: &mut AoVM) -> AoStatus {
+            AoStatus::Ok
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let program = ao_program![
+            mov ca,"Hello"
+            mov mp,1
+            mov mem,ca
+            push 1
+            pop
+            int 0
+        ];
+        assert_eq!(validate(&program), Ok(()));
+        assert_eq!(validate(&[]), Ok(()));
+
+        let mut program = ao_program![nop nop];
+        program.push(Box::new(Mov {
+            dst: AoArg::from(1),
+            src: AoArg::from(2),
+        }));
+        let err = validate(&program).unwrap_err();
+        assert_eq!(err, ValidationError::ImmediateDestination { index: 2 });
+        assert_eq!(err.index(), 2);
+        assert_eq!(err.to_string(), "Immediate Destination at 2");
+
+        let mut program = ao_program![nop];
+        program.push(Box::new(Custom));
+        assert_eq!(
+            validate(&program),
+            Err(ValidationError::UnknownOpcode { index: 1, id: 0x90 })
+        );
+    }
+
+    #[test]
+    fn test_validate_and_run() {
+        let mut vm = AoVM::default();
+        let program = ao_program![mov ca,1 add 2];
+        assert_eq!(validate_and_run(&mut vm, &program), Ok(AoStatus::Exit));
+        assert_eq!(vm.ca, AoType::AoInt(3));
+        assert!(vm.safe_mode);
+
+        vm.reset();
+        assert_eq!(
+            validate_and_run(&mut vm, &ao_program![mov ca,ds]),
+            Ok(AoStatus::InvalidOperation("ds[0] out of range".to_string()))
+        );
+
+        let mut program = ao_program![mov ca,1];
+        program.push(Box::new(Custom));
+        vm.reset();
+        assert_eq!(
+            validate_and_run(&mut vm, &program),
+            Err(ValidationError::UnknownOpcode { index: 1, id: 0x90 })
+        );
+        assert_eq!(vm.ca, AoType::AoInt(0));
+    }
+}